@@ -67,8 +67,11 @@ fn linker_be_nice() {
         std::process::exit(0);
     }
 
+    // Xtensa links through gcc, RISC-V chips (ESP32-C3) through rust-lld directly
+    let prefix = if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("riscv32") { "" } else { "-Wl," };
     println!(
-        "cargo:rustc-link-arg=-Wl,--error-handling-script={}",
+        "cargo:rustc-link-arg={}--error-handling-script={}",
+        prefix,
         std::env::current_exe().unwrap().display()
     );
 }
@@ -0,0 +1,193 @@
+use alloc::collections::VecDeque;
+
+use crate::command::ServoCommand;
+
+/// Anything that can be commanded to an angle
+///
+/// Lets code that only moves a servo (such as [`sweep`]) work with any
+/// actuator, including a fake one, rather than the concrete LEDC type.
+/// The HTTP and serial layers don't need it: they never touch a servo, they
+/// queue a `ServoCommand` for the main loop, which owns the servos.
+pub trait AngleActuator {
+    /// Move to `angle` degrees
+    fn command_angle(&mut self, angle: u8);
+}
+
+/// Angles a sweep from `from` to `to` passes through in `steps` evenly spaced
+/// steps (at least one), always ending on `to`
+///
+/// `from` itself is not included: the servo is already there.
+pub fn sweep_angles(from: u8, to: u8, steps: u32) -> impl Iterator<Item = u8> {
+    let steps = steps.max(1) as i32;
+    let span = to as i32 - from as i32;
+    (1..=steps).map(move |step| (from as i32 + span * step / steps) as u8)
+}
+
+/// Move `actuator` from `from` to `to` in `steps` steps (see [`sweep_angles`])
+///
+/// `moved` is told every angle commanded, and `wait_step` waits out the pause
+/// between two steps; if it returns a command instead, the sweep stops where
+/// it is and hands that command back with the angle reached, so the command
+/// can take over. A finished sweep returns `to` and `None`.
+pub async fn sweep<C>(
+    actuator: &mut impl AngleActuator,
+    from: u8,
+    to: u8,
+    steps: u32,
+    mut moved: impl FnMut(u8),
+    mut wait_step: impl AsyncFnMut() -> Option<C>,
+) -> (u8, Option<C>) {
+    let mut angles = sweep_angles(from, to, steps).peekable();
+    while let Some(angle) = angles.next() {
+        actuator.command_angle(angle);
+        moved(angle);
+        if angles.peek().is_none() {
+            break;
+        }
+        if let Some(next) = wait_step().await {
+            return (angle, Some(next));
+        }
+    }
+    (to, None)
+}
+
+/// Wait for a command that should cut a pulse hold or sweep of servo `index`
+/// short: one for the same servo that `admit` accepts
+///
+/// Commands come from `receive`. Those for other servos, and ones `admit`
+/// refuses, go to the back of `pending` to be handled once the hold or sweep
+/// is over; past `depth` of them the oldest is discarded and passed to
+/// `dropped`, as when the queue itself is full.
+pub async fn interrupting_command(
+    index: usize,
+    mut receive: impl AsyncFnMut() -> ServoCommand,
+    admit: impl Fn(&ServoCommand) -> bool,
+    pending: &mut VecDeque<ServoCommand>,
+    depth: usize,
+    mut dropped: impl FnMut(ServoCommand),
+) -> ServoCommand {
+    loop {
+        let next = receive().await;
+        if next.servo as usize == index && admit(&next) {
+            return next;
+        }
+        if pending.len() >= depth
+            && let Some(oldest) = pending.pop_front()
+        {
+            dropped(oldest);
+        }
+        pending.push_back(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::arbiter::PriorityArbiter;
+    use crate::command::CommandSource;
+
+    /// Records every angle it is commanded to
+    #[derive(Default)]
+    struct FakeActuator(Vec<u8>);
+
+    impl AngleActuator for FakeActuator {
+        fn command_angle(&mut self, angle: u8) {
+            self.0.push(angle);
+        }
+    }
+
+    fn swept(from: u8, to: u8, steps: u32) -> Vec<u8> {
+        let mut actuator = FakeActuator::default();
+        for angle in sweep_angles(from, to, steps) {
+            actuator.command_angle(angle);
+        }
+        actuator.0
+    }
+
+    #[test]
+    fn sweeps_in_even_steps() {
+        assert_eq!(swept(0, 90, 3), [30, 60, 90]);
+        assert_eq!(swept(180, 0, 4), [135, 90, 45, 0]);
+    }
+
+    #[test]
+    fn always_ends_on_the_target() {
+        assert_eq!(swept(0, 10, 3), [3, 6, 10]);
+        assert_eq!(swept(0, 2, 5), [0, 0, 1, 1, 2]);
+        assert_eq!(swept(45, 90, 0), [90]);
+        assert_eq!(swept(90, 90, 2), [90, 90]);
+    }
+
+    /// Poll `future` to completion; everything in these tests is ready at once
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn finished_sweeps_end_on_the_target() {
+        let mut actuator = FakeActuator::default();
+        let mut reported = Vec::new();
+        let (reached, interrupt) =
+            block_on(sweep(&mut actuator, 0, 90, 3, |angle| reported.push(angle), async || None::<ServoCommand>));
+        assert_eq!((reached, interrupt), (90, None));
+        assert_eq!(actuator.0, [30, 60, 90]);
+        assert_eq!(reported, actuator.0);
+    }
+
+    #[test]
+    fn serial_commands_stop_an_http_sweep() {
+        // The HTTP sweep holds servo 0 against lower-priority sources
+        let mut arbiter = PriorityArbiter::<2>::new(500);
+        assert!(arbiter.admit(0, CommandSource::Http.priority(), false, 0));
+        let admit = |command: &ServoCommand| arbiter.would_admit(command.servo as usize, command.source.priority(), false, 100);
+
+        let other_servo = ServoCommand::set(1, 45, CommandSource::Http);
+        let held_off = ServoCommand::set(0, 0, CommandSource::Auto);
+        let serial = ServoCommand::set(0, 120, CommandSource::Serial);
+        let mut arrivals = VecDeque::from([other_servo, held_off, serial]);
+        let mut pending = VecDeque::new();
+        let mut dropped = Vec::new();
+
+        // Nothing arrives during the first two pauses
+        let mut pauses = 0;
+        let mut actuator = FakeActuator::default();
+        let (reached, interrupt) = block_on(sweep(&mut actuator, 0, 90, 9, |_| {}, async || {
+            pauses += 1;
+            if pauses < 3 {
+                return None;
+            }
+            let receive = async || arrivals.pop_front().unwrap();
+            Some(interrupting_command(0, receive, admit, &mut pending, 4, |command| dropped.push(command)).await)
+        }));
+
+        assert_eq!(actuator.0, [10, 20, 30]);
+        assert_eq!((reached, interrupt), (30, Some(serial)));
+        assert_eq!(pending, [other_servo, held_off]);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn deferred_commands_drop_the_oldest_past_the_depth() {
+        let first = ServoCommand::set(1, 10, CommandSource::Http);
+        let second = ServoCommand::set(1, 20, CommandSource::Http);
+        let third = ServoCommand::set(0, 30, CommandSource::Serial);
+        let mut arrivals = VecDeque::from([first, second, third]);
+        let mut pending = VecDeque::new();
+        let mut dropped = Vec::new();
+        let receive = async || arrivals.pop_front().unwrap();
+        let next = block_on(interrupting_command(0, receive, |_| true, &mut pending, 1, |command| dropped.push(command)));
+        assert_eq!(next, third);
+        assert_eq!(pending, [second]);
+        assert_eq!(dropped, [first]);
+    }
+}
@@ -0,0 +1,153 @@
+/// Drops commands that would override a recent higher-priority command
+///
+/// Each applied command "holds" its servo for `hold_ms`; during that window
+/// only commands of equal or higher priority (or ones that always pass, such
+/// as detaches) are admitted. `N` is the number of servos; times are
+/// milliseconds of uptime.
+pub struct PriorityArbiter<const N: usize> {
+    /// Per servo: priority of the last applied command and when its hold ends
+    holds: [(u8, u64); N],
+    hold_ms: u64,
+}
+
+impl<const N: usize> PriorityArbiter<N> {
+    pub const fn new(hold_ms: u64) -> Self {
+        Self { holds: [(0, 0); N], hold_ms }
+    }
+
+    /// Whether a command for `servo` at `priority` would be admitted at
+    /// `now_ms`, without recording anything
+    ///
+    /// `always` commands make the servo safer and are never held off.
+    pub fn would_admit(&self, servo: usize, priority: u8, always: bool, now_ms: u64) -> bool {
+        match self.holds.get(servo) {
+            Some(&(held, until)) => always || now_ms >= until || priority >= held,
+            None => false,
+        }
+    }
+
+    /// Decide whether a command may be applied now, recording the hold if so
+    pub fn admit(&mut self, servo: usize, priority: u8, always: bool, now_ms: u64) -> bool {
+        if !self.would_admit(servo, priority, always, now_ms) {
+            return false;
+        }
+        self.holds[servo] = (priority, now_ms + self.hold_ms);
+        true
+    }
+}
+
+/// Rejects moves that would reverse a servo within `dwell_ms` of its last
+/// move (0 = never)
+///
+/// `N` is the number of servos; times are milliseconds of uptime.
+pub struct ReverseDwell<const N: usize> {
+    /// Per servo: direction of the last move (-1, 0, 1) and when it was applied
+    last: [(i8, u64); N],
+    dwell_ms: u64,
+}
+
+impl<const N: usize> ReverseDwell<N> {
+    pub const fn new(dwell_ms: u64) -> Self {
+        Self { last: [(0, 0); N], dwell_ms }
+    }
+
+    /// Decide whether `servo` may move from `from` to `to` now, recording the
+    /// move if so
+    pub fn admit(&mut self, servo: usize, from: u8, to: u8, now_ms: u64) -> bool {
+        let Some(last) = self.last.get_mut(servo) else {
+            return false;
+        };
+        let direction = (to as i16 - from as i16).signum() as i8;
+        if direction == 0 {
+            return true;
+        }
+        if direction == -last.0 && now_ms < last.1 + self.dwell_ms {
+            return false;
+        }
+        *last = (direction, now_ms);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HIGH: u8 = 3;
+    const LOW: u8 = 1;
+
+    #[test]
+    fn lower_priority_waits_out_the_hold() {
+        let mut arbiter = PriorityArbiter::<2>::new(500);
+        assert!(arbiter.admit(0, HIGH, false, 1_000));
+        assert!(!arbiter.admit(0, LOW, false, 1_499));
+        assert!(arbiter.admit(0, LOW, false, 1_500));
+    }
+
+    #[test]
+    fn equal_or_higher_priority_always_passes() {
+        let mut arbiter = PriorityArbiter::<1>::new(500);
+        assert!(arbiter.admit(0, LOW, false, 1_000));
+        assert!(arbiter.admit(0, LOW, false, 1_001));
+        assert!(arbiter.admit(0, HIGH, false, 1_002));
+        assert!(arbiter.admit(0, HIGH, false, 1_003));
+    }
+
+    #[test]
+    fn always_commands_pass_and_take_the_hold() {
+        let mut arbiter = PriorityArbiter::<1>::new(500);
+        assert!(arbiter.admit(0, HIGH, false, 1_000));
+        assert!(arbiter.admit(0, LOW, true, 1_001));
+        // The detach replaced the high-priority hold with its own
+        assert!(arbiter.admit(0, LOW, false, 1_002));
+    }
+
+    #[test]
+    fn servos_are_held_separately() {
+        let mut arbiter = PriorityArbiter::<2>::new(500);
+        assert!(arbiter.admit(0, HIGH, false, 1_000));
+        assert!(arbiter.admit(1, LOW, false, 1_001));
+    }
+
+    #[test]
+    fn checking_records_nothing() {
+        let mut arbiter = PriorityArbiter::<1>::new(500);
+        assert!(arbiter.admit(0, LOW, false, 1_000));
+        assert!(arbiter.would_admit(0, HIGH, false, 1_001));
+        assert!(arbiter.admit(0, LOW, false, 1_002));
+        assert!(!arbiter.would_admit(1, HIGH, true, 1_003));
+    }
+
+    #[test]
+    fn unknown_servos_are_refused() {
+        let mut arbiter = PriorityArbiter::<1>::new(500);
+        assert!(!arbiter.admit(1, HIGH, true, 0));
+        let mut dwell = ReverseDwell::<1>::new(100);
+        assert!(!dwell.admit(1, 0, 90, 0));
+    }
+
+    #[test]
+    fn reversal_waits_for_the_dwell() {
+        let mut dwell = ReverseDwell::<1>::new(100);
+        assert!(dwell.admit(0, 0, 90, 1_000));
+        assert!(!dwell.admit(0, 90, 45, 1_099));
+        assert!(dwell.admit(0, 90, 45, 1_100));
+    }
+
+    #[test]
+    fn same_direction_and_no_op_moves_pass() {
+        let mut dwell = ReverseDwell::<1>::new(100);
+        assert!(dwell.admit(0, 0, 90, 1_000));
+        assert!(dwell.admit(0, 90, 120, 1_001));
+        assert!(dwell.admit(0, 120, 120, 1_002));
+        // The no-op didn't reset the direction, so this is still a reversal
+        assert!(!dwell.admit(0, 120, 60, 1_003));
+    }
+
+    #[test]
+    fn zero_dwell_never_refuses() {
+        let mut dwell = ReverseDwell::<1>::new(0);
+        assert!(dwell.admit(0, 0, 90, 1_000));
+        assert!(dwell.admit(0, 90, 0, 1_000));
+    }
+}
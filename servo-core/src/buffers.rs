@@ -0,0 +1,85 @@
+/// Bytes of one request the HTTP server reads before answering; longer
+/// requests are refused by [`oversize_response`](crate::router::oversize_response)
+pub const RX_BUFFER_SIZE: usize = 1024;
+
+/// Socket transmit buffer size of each HTTP connection handler
+pub const TX_BUFFER_SIZE: usize = 1024;
+
+/// Buffers owned by exactly one connection handler
+pub struct ConnectionBuffers {
+    /// Socket receive buffer
+    pub rx: [u8; RX_BUFFER_SIZE],
+    /// Socket transmit buffer
+    pub tx: [u8; TX_BUFFER_SIZE],
+    /// The request being assembled from what the socket delivers
+    pub request: [u8; RX_BUFFER_SIZE],
+}
+
+impl ConnectionBuffers {
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; RX_BUFFER_SIZE],
+            tx: [0; TX_BUFFER_SIZE],
+            request: [0; RX_BUFFER_SIZE],
+        }
+    }
+}
+
+impl Default for ConnectionBuffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One set of buffers per connection handler, sized at compile time so the
+/// pool can live in a static instead of the heap
+pub struct BufferPool<const N: usize> {
+    sets: [ConnectionBuffers; N],
+}
+
+impl<const N: usize> BufferPool<N> {
+    pub const fn new() -> Self {
+        Self { sets: [const { ConnectionBuffers::new() }; N] }
+    }
+
+    /// Hand out every set, one per handler (`0..N`)
+    ///
+    /// The sets are disjoint borrows of the pool, so no two handlers can
+    /// ever alias the same memory.
+    pub fn take_all(&mut self) -> [&mut ConnectionBuffers; N] {
+        self.sets.each_mut()
+    }
+}
+
+impl<const N: usize> Default for BufferPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use core::mem::size_of;
+    use core::ops::Range;
+
+    use super::*;
+
+    fn span(buffers: &ConnectionBuffers) -> Range<usize> {
+        let start = buffers as *const ConnectionBuffers as usize;
+        start..start + size_of::<ConnectionBuffers>()
+    }
+
+    #[test]
+    fn workers_get_disjoint_buffers() {
+        let mut pool = Box::new(BufferPool::<2>::new());
+        let [worker0, worker1] = pool.take_all();
+        let (first, second) = (span(worker0), span(worker1));
+        assert!(first.end <= second.start || second.end <= first.start);
+        // Each handler's own buffers don't overlap either
+        let rx = worker0.rx.as_ptr_range();
+        let tx = worker0.tx.as_ptr_range();
+        let request = worker0.request.as_ptr_range();
+        assert!(rx.end <= tx.start && tx.end <= request.start);
+    }
+}
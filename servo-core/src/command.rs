@@ -0,0 +1,193 @@
+/// Where a servo command came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandSource {
+    /// HTTP API request
+    Http,
+    /// Serial console command
+    Serial,
+    /// Physical button
+    Button,
+    /// Issued by the firmware itself (startup position, failsafes, ...)
+    Auto,
+    /// Potentiometer knob
+    Pot,
+}
+
+impl CommandSource {
+    /// Lowercase name used in logs and JSON responses
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CommandSource::Http => "http",
+            CommandSource::Serial => "serial",
+            CommandSource::Button => "button",
+            CommandSource::Pot => "pot",
+            CommandSource::Auto => "auto",
+        }
+    }
+
+    /// Arbitration priority; higher wins
+    ///
+    /// Default order: button, pot > serial > HTTP > auto. A person at the device
+    /// beats a tethered host, which beats the network, which beats anything the
+    /// firmware schedules on its own.
+    pub fn priority(self) -> u8 {
+        match self {
+            CommandSource::Button | CommandSource::Pot => 3,
+            CommandSource::Serial => 2,
+            CommandSource::Http => 1,
+            CommandSource::Auto => 0,
+        }
+    }
+
+    /// Compact form for storing in an atomic
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Inverse of [`to_u8`](Self::to_u8)
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CommandSource::Http),
+            1 => Some(CommandSource::Serial),
+            2 => Some(CommandSource::Button),
+            3 => Some(CommandSource::Auto),
+            4 => Some(CommandSource::Pot),
+            _ => None,
+        }
+    }
+}
+
+/// What a command asks a servo to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServoAction {
+    /// Move to an absolute angle
+    Set(u8),
+    /// Move to an absolute angle, then wait `dwell_ms` before the next command
+    ///
+    /// Never coalesced with other moves, so a queued sequence like 0, 180, 90
+    /// is made move by move.
+    Step { angle: u8, dwell_ms: u32 },
+    /// Move to an angle, hold it for `hold_ms`, then return to where the servo was
+    Pulse { angle: u8, hold_ms: u32 },
+    /// Move to `from`, then on to `to`, spending `step_ms` per degree
+    Sweep { from: u8, to: u8, step_ms: u16 },
+    /// Stop sending pulses so the servo relaxes
+    Detach,
+    /// Set the trim (degrees added to every angle) and re-apply the current angle
+    Trim(i8),
+    /// Set the pulse widths for 0° and 180° and re-apply the current angle
+    PulseRange { min_us: u16, max_us: u16 },
+}
+
+/// Longest hold accepted for a [`ServoAction::Pulse`]
+pub const MAX_PULSE_HOLD_MS: u32 = 10_000;
+
+/// Slowest per-degree pace accepted for a [`ServoAction::Sweep`] (a full
+/// 180° sweep then takes three minutes)
+pub const MAX_SWEEP_STEP_MS: u16 = 1_000;
+
+/// A request for one servo, tagged with its source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServoCommand {
+    /// Index of the target servo (`0..MAX_SERVOS`)
+    pub servo: u8,
+    pub action: ServoAction,
+    pub source: CommandSource,
+}
+
+impl ServoCommand {
+    /// Move `servo` to `angle`
+    pub fn set(servo: u8, angle: u8, source: CommandSource) -> Self {
+        Self { servo, action: ServoAction::Set(angle), source }
+    }
+
+    /// Move `servo` to `angle` and dwell there for `dwell_ms`
+    pub fn step(servo: u8, angle: u8, dwell_ms: u32, source: CommandSource) -> Self {
+        Self { servo, action: ServoAction::Step { angle, dwell_ms }, source }
+    }
+
+    /// Pulse `servo` to `angle` for `hold_ms`, then return
+    pub fn pulse(servo: u8, angle: u8, hold_ms: u32, source: CommandSource) -> Self {
+        Self { servo, action: ServoAction::Pulse { angle, hold_ms }, source }
+    }
+
+    /// Sweep `servo` from `from` to `to` at `step_ms` per degree
+    pub fn sweep(servo: u8, from: u8, to: u8, step_ms: u16, source: CommandSource) -> Self {
+        Self { servo, action: ServoAction::Sweep { from, to, step_ms }, source }
+    }
+
+    /// Detach `servo`
+    pub fn detach(servo: u8, source: CommandSource) -> Self {
+        Self { servo, action: ServoAction::Detach, source }
+    }
+}
+
+/// Why the servos can't be moved right now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotReady {
+    /// Startup homing hasn't finished yet
+    Starting,
+    /// An overload fault is latched
+    Fault,
+    /// The emergency stop is engaged
+    Estop,
+    /// The device is idle and going into deep sleep
+    LowPower,
+}
+
+impl NotReady {
+    /// Name used in `/status` and log lines
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NotReady::Starting => "starting",
+            NotReady::Fault => "fault",
+            NotReady::Estop => "estop",
+            NotReady::LowPower => "low_power",
+        }
+    }
+}
+
+/// Apply an allowed `(min, max)` range to a requested angle
+///
+/// Returns the angle to apply, clamped into range, or when `strict` the range
+/// itself if the angle falls outside it.
+pub fn limit_angle(angle: u8, (min, max): (u8, u8), strict: bool) -> Result<u8, (u8, u8)> {
+    if (min..=max).contains(&angle) {
+        Ok(angle)
+    } else if strict {
+        Err((min, max))
+    } else {
+        Ok(angle.clamp(min, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_round_trip_through_u8() {
+        for source in [CommandSource::Http, CommandSource::Serial, CommandSource::Button, CommandSource::Auto, CommandSource::Pot] {
+            assert_eq!(CommandSource::from_u8(source.to_u8()), Some(source));
+        }
+        assert_eq!(CommandSource::from_u8(5), None);
+    }
+
+    #[test]
+    fn angles_in_range_pass_unchanged() {
+        assert_eq!(limit_angle(10, (10, 170), false), Ok(10));
+        assert_eq!(limit_angle(170, (10, 170), true), Ok(170));
+    }
+
+    #[test]
+    fn lenient_limits_clamp() {
+        assert_eq!(limit_angle(5, (10, 170), false), Ok(10));
+        assert_eq!(limit_angle(180, (10, 170), false), Ok(170));
+    }
+
+    #[test]
+    fn strict_limits_refuse_with_the_range() {
+        assert_eq!(limit_angle(5, (10, 170), true), Err((10, 170)));
+        assert_eq!(limit_angle(180, (10, 170), true), Err((10, 170)));
+    }
+}
@@ -0,0 +1,358 @@
+use alloc::string::String;
+
+use crate::url::{form_param, percent_decode};
+
+/// Find the value of header `name` (case-insensitive) in a raw request
+pub fn find_header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+}
+
+/// Parse a single `bytes=` range against a body of `len` bytes
+///
+/// Returns the inclusive `(start, end)` byte positions, or `None` if the
+/// range is malformed, unsatisfiable or uses multiple ranges.
+pub fn parse_range(header: &str, len: usize) -> Option<(usize, usize)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || len == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => {
+            let end: usize = end.parse().ok()?;
+            (start.parse().ok()?, end.min(len - 1))
+        }
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+/// Split a request target into its percent-decoded path and its raw query
+///
+/// The split happens before anything is decoded, so an escaped `?` (`%3F`)
+/// stays part of the path; read query values with [`form_param`], which
+/// decodes them pair by pair.
+pub fn split_target(target: &str) -> (String, Option<&str>) {
+    match target.split_once('?') {
+        Some((path, query)) => (percent_decode(path), Some(query)),
+        None => (percent_decode(target), None),
+    }
+}
+
+/// Parse the HTTP request and extract the method and path
+pub fn parse_request(request: &str) -> Option<(&str, &str)> {
+    let first_line = request.lines().next()?;
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next()?;
+    let path = parts.next()?;
+    Some((method, path))
+}
+
+/// Why the angle of a move couldn't be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AngleError {
+    /// No angle, or a path that isn't a move at all
+    Missing,
+    /// Not a number, e.g. `/servo/abc`
+    NotANumber,
+    /// A fraction, e.g. `/servo/90.5`; angles are whole degrees
+    Fractional,
+    /// A whole number too large to be an angle, e.g. `/servo/300`
+    OutOfRange,
+}
+
+impl AngleError {
+    /// JSON error body naming the problem
+    pub fn body(self) -> &'static str {
+        match self {
+            AngleError::Missing => r#"{"error": "Missing or invalid angle parameter. Use /servo/90 or /servo?angle=90"}"#,
+            AngleError::NotANumber => r#"{"error": "Angle is not a number. Use a whole number of degrees, e.g. /servo/90"}"#,
+            AngleError::Fractional => r#"{"error": "Angle must be a whole number of degrees"}"#,
+            AngleError::OutOfRange => r#"{"error": "Angle must be between 0 and 180"}"#,
+        }
+    }
+}
+
+/// Parse one angle value, telling apart the ways it can be wrong
+pub fn parse_angle(text: &str) -> Result<u8, AngleError> {
+    if text.is_empty() {
+        return Err(AngleError::Missing);
+    }
+    if let Ok(angle) = text.parse() {
+        return Ok(angle);
+    }
+    if text.bytes().all(|b| b.is_ascii_digit()) {
+        Err(AngleError::OutOfRange)
+    } else if text.parse::<f32>().is_ok_and(f32::is_finite) {
+        Err(AngleError::Fractional)
+    } else {
+        Err(AngleError::NotANumber)
+    }
+}
+
+/// Parse the target servo and angle from paths like /servo/90, /servo/1/90,
+/// /servo?angle=90 or /servo?servo=1&angle=90
+///
+/// `path` is decoded and `query` raw, as from [`split_target`]. Servo 0 is
+/// assumed when no index is given. A bad servo index is
+/// [`AngleError::Missing`], so it gets the generic usage message.
+pub fn parse_servo_target(path: &str, query: Option<&str>) -> Result<(u8, u8), AngleError> {
+    // Try path format: /servo/90 or /servo/<index>/90 (a trailing slash is ignored)
+    if let Some(rest) = path.strip_prefix("/servo/") {
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        return match rest.split_once('/') {
+            Some((index, angle)) => Ok((index.parse().map_err(|_| AngleError::Missing)?, parse_angle(angle)?)),
+            None => Ok((0, parse_angle(rest)?)),
+        };
+    }
+
+    // Try query format: /servo?angle=90 or /servo?servo=1&angle=90, keys in any order
+    let query = query.filter(|_| path == "/servo").ok_or(AngleError::Missing)?;
+    let servo = match form_param(query, "servo") {
+        Some(index) => index.parse().map_err(|_| AngleError::Missing)?,
+        None => 0,
+    };
+    Ok((servo, parse_angle(&form_param(query, "angle").ok_or(AngleError::Missing)?)?))
+}
+
+/// Whether a path-format move has more segments than `/servo/<index>/<angle>`
+///
+/// Lets `/servo/90/extra` get an error naming the problem rather than the
+/// generic invalid-angle one.
+pub fn has_extra_segments(path: &str) -> bool {
+    let Some(rest) = path.strip_prefix("/servo/") else {
+        return false;
+    };
+    rest.strip_suffix('/').unwrap_or(rest).split('/').count() > 2
+}
+
+/// Servo index of a query-format request whose `angle` value is empty
+///
+/// `/servo?angle=` and `/servo?servo=1&angle=` match; `angle=0` and a query
+/// without `angle` do not.
+pub fn parse_empty_angle(path: &str, query: Option<&str>) -> Option<u8> {
+    let query = query.filter(|_| path == "/servo")?;
+    if !form_param(query, "angle")?.is_empty() {
+        return None;
+    }
+    match form_param(query, "servo") {
+        Some(index) => index.parse().ok(),
+        None => Some(0),
+    }
+}
+
+/// Escape `"`, `\` and control characters for use inside a JSON string
+pub fn json_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&alloc::format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parse the servo index from /servo/detach or /servo/<index>/detach
+pub fn parse_detach_target(path: &str) -> Option<u8> {
+    let rest = path.strip_prefix("/servo")?.strip_suffix("/detach")?;
+    match rest.strip_prefix('/') {
+        Some(index) => index.parse().ok(),
+        None if rest.is_empty() => Some(0),
+        None => None,
+    }
+}
+
+/// Parse `/servo/by/<delta>` (servo 0), e.g. `/servo/by/-5` or `/servo/by/+10`
+pub fn parse_relative_target(path: &str) -> Option<i16> {
+    path.strip_prefix("/servo/by/")?.parse().ok()
+}
+
+/// Parse a path-format move whose angle is negative (`/servo/-5` or
+/// `/servo/<index>/-5`) into the servo and the (negative) angle
+pub fn parse_bare_negative(path: &str) -> Option<(u8, i16)> {
+    let rest = path.strip_prefix("/servo/")?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (servo, angle) = match rest.split_once('/') {
+        Some((index, angle)) => (index.parse().ok()?, angle),
+        None => (0, rest),
+    };
+    if !angle.starts_with('-') {
+        return None;
+    }
+    Some((servo, angle.parse().ok()?))
+}
+
+/// Parse `/servo/pulse/<angle>/<hold_ms>` (servo 0)
+pub fn parse_pulse_target(path: &str) -> Option<(u8, u32)> {
+    let (angle, hold_ms) = path.strip_prefix("/servo/pulse/")?.split_once('/')?;
+    Some((angle.parse().ok()?, hold_ms.parse().ok()?))
+}
+
+/// Where the headers of a raw request end: the offsets of the blank line
+/// ending them and of the body after it
+///
+/// Bare `\n` line endings are accepted as well as `\r\n`, for `nc` and
+/// other minimal clients.
+pub fn headers_end(request: &[u8]) -> Option<(usize, usize)> {
+    request.windows(2).enumerate().find_map(|(i, pair)| match pair {
+        [b'\n', b'\n'] => Some((i + 1, i + 2)),
+        [b'\n', b'\r'] if request.get(i + 2) == Some(&b'\n') => Some((i + 1, i + 3)),
+        _ => None,
+    })
+}
+
+/// Body of a request: everything after the blank line ending the headers
+pub fn request_body(request: &str) -> &str {
+    headers_end(request.as_bytes()).map(|(_, body)| &request[body..]).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: &str = "GET /servo/90 HTTP/1.1\r\nHost: esp32-servo.local\r\ncontent-length: 4\r\n\r\nbody";
+
+    #[test]
+    fn parses_the_request_line() {
+        assert_eq!(parse_request(REQUEST), Some(("GET", "/servo/90")));
+        assert_eq!(parse_request("GET\r\n\r\n"), None);
+        assert_eq!(parse_request(""), None);
+    }
+
+    #[test]
+    fn finds_headers_case_insensitively() {
+        assert_eq!(find_header(REQUEST, "Content-Length"), Some("4"));
+        assert_eq!(find_header(REQUEST, "host"), Some("esp32-servo.local"));
+        assert_eq!(find_header(REQUEST, "Range"), None);
+        // Header lookups stop at the body
+        assert_eq!(find_header("GET / HTTP/1.1\r\n\r\nRange: bytes=0-1", "Range"), None);
+    }
+
+    #[test]
+    fn splits_off_the_body() {
+        assert_eq!(request_body(REQUEST), "body");
+        assert_eq!(request_body("POST /wifi HTTP/1.0\n\nssid=x"), "ssid=x");
+        assert_eq!(request_body("GET / HTTP/1.1\r\nHost: x\r\n"), "");
+        assert_eq!(headers_end(b"GET / HTTP/1.1\r\n\r\n"), Some((16, 18)));
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+    }
+
+    #[test]
+    fn rejects_unusable_ranges() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("bytes=-0", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+        assert_eq!(parse_range("bytes=0-1", 0), None);
+    }
+
+    /// [`parse_servo_target`] on a raw request target
+    fn target(raw: &str) -> Result<(u8, u8), AngleError> {
+        let (path, query) = split_target(raw);
+        parse_servo_target(&path, query)
+    }
+
+    fn empty_angle(raw: &str) -> Option<u8> {
+        let (path, query) = split_target(raw);
+        parse_empty_angle(&path, query)
+    }
+
+    #[test]
+    fn splits_targets_before_decoding() {
+        assert_eq!(split_target("/servo%2F90"), (String::from("/servo/90"), None));
+        assert_eq!(split_target("/servo?angle=%39%30"), (String::from("/servo"), Some("angle=%39%30")));
+        assert_eq!(split_target("/a%3Fb?c=d"), (String::from("/a?b"), Some("c=d")));
+        assert_eq!(split_target("/servo?"), (String::from("/servo"), Some("")));
+    }
+
+    #[test]
+    fn parses_move_targets() {
+        assert_eq!(target("/servo/90"), Ok((0, 90)));
+        assert_eq!(target("/servo/90/"), Ok((0, 90)));
+        assert_eq!(target("/servo/1/45"), Ok((1, 45)));
+        assert_eq!(target("/servo?angle=180"), Ok((0, 180)));
+        assert_eq!(target("/servo?angle=10&servo=1"), Ok((1, 10)));
+        assert_eq!(target("/servo%2F90"), Ok((0, 90)));
+        assert_eq!(target("/servo?angle=%39%30"), Ok((0, 90)));
+    }
+
+    #[test]
+    fn tells_apart_bad_angles() {
+        assert_eq!(target("/servo/abc"), Err(AngleError::NotANumber));
+        assert_eq!(target("/servo/90.5"), Err(AngleError::Fractional));
+        assert_eq!(target("/servo/300"), Err(AngleError::OutOfRange));
+        assert_eq!(target("/servo?angle="), Err(AngleError::Missing));
+        assert_eq!(target("/servo?servo=x&angle=90"), Err(AngleError::Missing));
+        assert_eq!(target("/servo/x/90"), Err(AngleError::Missing));
+        assert_eq!(target("/status"), Err(AngleError::Missing));
+        assert_eq!(target("/status?angle=90"), Err(AngleError::Missing));
+        assert_eq!(parse_angle("inf"), Err(AngleError::NotANumber));
+    }
+
+    #[test]
+    fn escaped_separators_stay_in_the_value() {
+        // `%26` is not a pair separator, so the angle is "90&servo=1"
+        assert_eq!(target("/servo?angle=90%26servo=1"), Err(AngleError::NotANumber));
+        // `%3F` is not the query separator, so this is not a query-format move
+        assert_eq!(target("/servo%3Fangle=90"), Err(AngleError::Missing));
+    }
+
+    #[test]
+    fn spots_malformed_move_paths() {
+        assert!(has_extra_segments("/servo/1/90/extra"));
+        assert!(!has_extra_segments("/servo/1/90/"));
+        assert_eq!(empty_angle("/servo?angle="), Some(0));
+        assert_eq!(empty_angle("/servo?servo=1&angle="), Some(1));
+        assert_eq!(empty_angle("/servo?angle=0"), None);
+        assert_eq!(empty_angle("/servo?servo=1"), None);
+        assert_eq!(empty_angle("/servo/?angle="), None);
+    }
+
+    #[test]
+    fn parses_other_servo_routes() {
+        assert_eq!(parse_detach_target("/servo/detach"), Some(0));
+        assert_eq!(parse_detach_target("/servo/1/detach"), Some(1));
+        assert_eq!(parse_detach_target("/servoX/detach"), None);
+        assert_eq!(parse_relative_target("/servo/by/-5"), Some(-5));
+        assert_eq!(parse_relative_target("/servo/by/+10"), Some(10));
+        assert_eq!(parse_relative_target("/servo/by/x"), None);
+        assert_eq!(parse_bare_negative("/servo/-5"), Some((0, -5)));
+        assert_eq!(parse_bare_negative("/servo/1/-90/"), Some((1, -90)));
+        assert_eq!(parse_bare_negative("/servo/5"), None);
+        assert_eq!(parse_pulse_target("/servo/pulse/90/500"), Some((90, 500)));
+        assert_eq!(parse_pulse_target("/servo/pulse/90"), None);
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_escape(r#"say "hi"\"#), r#"say \"hi\"\\"#);
+        assert_eq!(json_escape("a\nb"), "a\\u000ab");
+        assert_eq!(json_escape("café"), "café");
+    }
+}
@@ -0,0 +1,251 @@
+/// First byte of every ESP32 app image
+const IMAGE_MAGIC: u8 = 0xE9;
+
+/// Common plus extended image header
+const IMAGE_HEADER_LEN: u32 = 24;
+
+/// Seed of the image checksum (XOR of all segment data)
+const CHECKSUM_SEED: u8 = 0xEF;
+
+/// Length of the SHA-256 digest some images carry after the checksum
+const HASH_LEN: u32 = 32;
+
+/// Part of the app image [`ImageCheck`] is reading
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Part {
+    ImageHeader,
+    SegmentHeader,
+    SegmentData,
+    /// Padding up to the checksum byte
+    Padding,
+    Hash,
+    Done,
+}
+
+/// Checks an app image's layout and checksum as it streams in, so a
+/// truncated or corrupted upload is never activated
+///
+/// The bootloader still verifies the SHA-256 digest, when present, at boot.
+pub struct ImageCheck {
+    /// Bytes seen so far
+    pos: u32,
+    part: Part,
+    /// Bytes left in the current segment data or hash
+    remaining: u32,
+    segments_left: u8,
+    segment_header: [u8; 8],
+    hash_appended: bool,
+    checksum: u8,
+    error: Option<&'static str>,
+}
+
+impl ImageCheck {
+    pub const fn new() -> Self {
+        Self {
+            pos: 0,
+            part: Part::ImageHeader,
+            remaining: 0,
+            segments_left: 0,
+            segment_header: [0; 8],
+            hash_appended: false,
+            checksum: CHECKSUM_SEED,
+            error: None,
+        }
+    }
+
+    /// Check the next part of the image
+    pub fn feed(&mut self, data: &[u8]) {
+        for &byte in data {
+            if self.error.is_some() {
+                return;
+            }
+            self.byte(byte);
+            self.pos += 1;
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        match self.part {
+            Part::ImageHeader => match self.pos {
+                0 if byte != IMAGE_MAGIC => self.error = Some("not an ESP32 app image"),
+                1 if byte == 0 => self.error = Some("image has no segments"),
+                1 => self.segments_left = byte,
+                23 => {
+                    self.hash_appended = byte == 1;
+                    self.part = Part::SegmentHeader;
+                    self.remaining = 8;
+                }
+                _ => {}
+            },
+            Part::SegmentHeader => {
+                self.segment_header[8 - self.remaining as usize] = byte;
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    let len = &self.segment_header[4..8];
+                    self.remaining = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+                    if self.remaining == 0 {
+                        self.end_segment();
+                    } else {
+                        self.part = Part::SegmentData;
+                    }
+                }
+            }
+            Part::SegmentData => {
+                self.checksum ^= byte;
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    self.end_segment();
+                }
+            }
+            // The checksum is the last byte of the 16-byte block the data ends in
+            Part::Padding if self.pos % 16 == 15 => {
+                if byte != self.checksum {
+                    self.error = Some("checksum mismatch");
+                } else if self.hash_appended {
+                    self.part = Part::Hash;
+                    self.remaining = HASH_LEN;
+                } else {
+                    self.part = Part::Done;
+                }
+            }
+            Part::Padding => {}
+            Part::Hash => {
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    self.part = Part::Done;
+                }
+            }
+            // Images may be padded out after the digest
+            Part::Done => {}
+        }
+    }
+
+    /// First problem found so far, if any
+    pub fn error(&self) -> Option<&'static str> {
+        self.error
+    }
+
+    fn end_segment(&mut self) {
+        self.segments_left -= 1;
+        if self.segments_left == 0 {
+            self.part = Part::Padding;
+        } else {
+            self.part = Part::SegmentHeader;
+            self.remaining = 8;
+        }
+    }
+
+    /// Whether the whole image, checksum included, has been seen
+    pub fn finish(&self) -> Result<(), &'static str> {
+        match (self.error, self.part) {
+            (Some(error), _) => Err(error),
+            (None, Part::Done) => Ok(()),
+            (None, _) if self.pos < IMAGE_HEADER_LEN => Err("image header truncated"),
+            (None, _) => Err("image truncated"),
+        }
+    }
+}
+
+impl Default for ImageCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image with one segment per entry of `segments`, a correct checksum
+    /// and, if `hash`, a digest after it
+    fn image(segments: &[&[u8]], hash: bool) -> Vec<u8> {
+        let mut image = vec![0u8; IMAGE_HEADER_LEN as usize];
+        image[0] = IMAGE_MAGIC;
+        image[1] = segments.len() as u8;
+        image[23] = hash as u8;
+        let mut checksum = CHECKSUM_SEED;
+        for data in segments {
+            image.extend_from_slice(&0x4008_0000u32.to_le_bytes());
+            image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            image.extend_from_slice(data);
+            checksum = data.iter().fold(checksum, |sum, b| sum ^ b);
+        }
+        while image.len() % 16 != 15 {
+            image.push(0);
+        }
+        image.push(checksum);
+        if hash {
+            image.extend_from_slice(&[0xAA; HASH_LEN as usize]);
+        }
+        image
+    }
+
+    fn check(image: &[u8]) -> Result<(), &'static str> {
+        let mut check = ImageCheck::new();
+        check.feed(image);
+        check.finish()
+    }
+
+    #[test]
+    fn accepts_valid_images() {
+        assert_eq!(check(&image(&[b"abc"], false)), Ok(()));
+        assert_eq!(check(&image(&[b"abc", b"", b"defgh"], true)), Ok(()));
+    }
+
+    #[test]
+    fn accepts_padding_after_the_image() {
+        let mut image = image(&[b"abc"], true);
+        image.extend_from_slice(&[0xFF; 64]);
+        assert_eq!(check(&image), Ok(()));
+    }
+
+    #[test]
+    fn accepts_the_image_in_any_chunks() {
+        let image = image(&[b"0123456789abcdef0123", b"xyz"], true);
+        for chunk in [1, 7, 16, 100] {
+            let mut check = ImageCheck::new();
+            for part in image.chunks(chunk) {
+                check.feed(part);
+            }
+            assert_eq!(check.finish(), Ok(()), "chunk size {chunk}");
+        }
+    }
+
+    #[test]
+    fn rejects_a_bad_magic_byte() {
+        let mut image = image(&[b"abc"], false);
+        image[0] = 0;
+        assert_eq!(check(&image), Err("not an ESP32 app image"));
+    }
+
+    #[test]
+    fn rejects_an_image_without_segments() {
+        let mut image = image(&[b"abc"], false);
+        image[1] = 0;
+        assert_eq!(check(&image), Err("image has no segments"));
+    }
+
+    #[test]
+    fn rejects_corrupted_data() {
+        let mut image = image(&[b"abc"], false);
+        image[IMAGE_HEADER_LEN as usize + 8] ^= 1;
+        assert_eq!(check(&image), Err("checksum mismatch"));
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let image = image(&[b"abc"], true);
+        assert_eq!(check(&image[..10]), Err("image header truncated"));
+        assert_eq!(check(&image[..40]), Err("image truncated"));
+        assert_eq!(check(&image[..image.len() - 1]), Err("image truncated"));
+    }
+
+    #[test]
+    fn keeps_the_first_error() {
+        let mut check = ImageCheck::new();
+        check.feed(&[0]);
+        assert_eq!(check.error(), Some("not an ESP32 app image"));
+        check.feed(&image(&[b"abc"], false));
+        assert_eq!(check.finish(), Err("not an ESP32 app image"));
+    }
+}
@@ -0,0 +1,313 @@
+/// The body is not a well-formed flat JSON object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Malformed;
+
+/// A field value, borrowed from the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// The number as written, e.g. `90`, `-5` or `1.5e3`
+    Number(&'a str),
+    /// The string between the quotes, escapes not decoded
+    String(&'a str),
+    Bool(bool),
+    Null,
+}
+
+impl Value<'_> {
+    /// The value as a non-negative integer, if it is one
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Number(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Cursor over the body being parsed
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `byte` after any whitespace
+    fn expect(&mut self, byte: u8) -> Result<(), Malformed> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(Malformed);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// A string, returned without its quotes and with escapes in place
+    fn string(&mut self) -> Result<&'a str, Malformed> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        loop {
+            match self.peek().ok_or(Malformed)? {
+                b'"' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    match self.peek().ok_or(Malformed)? {
+                        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => self.pos += 1,
+                        b'u' => {
+                            let hex = self.text.get(self.pos + 1..self.pos + 5).ok_or(Malformed)?;
+                            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                                return Err(Malformed);
+                            }
+                            self.pos += 5;
+                        }
+                        _ => return Err(Malformed),
+                    }
+                }
+                byte if byte < 0x20 => return Err(Malformed),
+                _ => self.pos += 1,
+            }
+        }
+        let raw = &self.text[start..self.pos];
+        self.pos += 1;
+        Ok(raw)
+    }
+
+    /// A number, checked against the JSON grammar
+    fn number(&mut self) -> Result<&'a str, Malformed> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(Malformed),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits()?;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+        Ok(&self.text[start..self.pos])
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), Malformed> {
+        let start = self.pos;
+        self.digits();
+        if self.pos == start {
+            return Err(Malformed);
+        }
+        Ok(())
+    }
+
+    /// `true`, `false` or `null`
+    fn literal(&mut self, word: &str) -> Result<(), Malformed> {
+        if !self.text[self.pos..].starts_with(word) {
+            return Err(Malformed);
+        }
+        self.pos += word.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, Malformed> {
+        self.skip_whitespace();
+        match self.peek().ok_or(Malformed)? {
+            b'"' => self.string().map(Value::String),
+            b't' => self.literal("true").map(|()| Value::Bool(true)),
+            b'f' => self.literal("false").map(|()| Value::Bool(false)),
+            b'n' => self.literal("null").map(|()| Value::Null),
+            // Nested objects and arrays are not supported
+            _ => self.number().map(Value::Number),
+        }
+    }
+}
+
+/// Whether the still-escaped string `raw` reads as `key`
+fn key_matches(raw: &str, key: &str) -> bool {
+    let mut expected = key.chars();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('u') => {
+                    let hex: [Option<char>; 4] = [chars.next(), chars.next(), chars.next(), chars.next()];
+                    let code = hex.iter().try_fold(0u32, |code, digit| Some(code * 16 + digit.and_then(|d| d.to_digit(16))?));
+                    match code.and_then(char::from_u32) {
+                        Some(c) => c,
+                        None => return false,
+                    }
+                }
+                Some(c) => c,
+                None => return false,
+            }
+        } else {
+            c
+        };
+        if expected.next() != Some(c) {
+            return false;
+        }
+    }
+    expected.next().is_none()
+}
+
+/// The value of field `key` in the flat JSON object `body`
+///
+/// Enough JSON for request bodies like `{"servo": 1, "angle": 90}` without
+/// `serde_json`: values are numbers, strings, `true`, `false` or `null`, and
+/// nested objects or arrays are [`Malformed`]. Nothing is allocated.
+///
+/// The whole body is checked, so trailing commas, missing braces and
+/// trailing garbage are [`Malformed`] even when `key` came before them.
+/// `Ok(None)` means the object is fine but has no such field; if the key is
+/// repeated the last value wins.
+pub fn field<'a>(body: &'a str, key: &str) -> Result<Option<Value<'a>>, Malformed> {
+    let mut scanner = Scanner { text: body, pos: 0 };
+    let mut found = None;
+    scanner.expect(b'{')?;
+    scanner.skip_whitespace();
+    if scanner.peek() == Some(b'}') {
+        scanner.pos += 1;
+    } else {
+        loop {
+            scanner.skip_whitespace();
+            let name = scanner.string()?;
+            scanner.expect(b':')?;
+            let value = scanner.value()?;
+            if key_matches(name, key) {
+                found = Some(value);
+            }
+            scanner.skip_whitespace();
+            match scanner.peek() {
+                Some(b',') => scanner.pos += 1,
+                Some(b'}') => {
+                    scanner.pos += 1;
+                    break;
+                }
+                _ => return Err(Malformed),
+            }
+        }
+    }
+    scanner.skip_whitespace();
+    if scanner.pos != body.len() {
+        return Err(Malformed);
+    }
+    Ok(found)
+}
+
+/// Integer field `key` of `body`, if the body is well-formed and the field
+/// holds a non-negative integer
+pub fn get_u32_field(body: &str, key: &str) -> Option<u32> {
+    field(body, key).ok()??.as_u32()
+}
+
+/// Integer field `key` of `body`, if it holds a number between 0 and 255
+pub fn get_u8_field(body: &str, key: &str) -> Option<u8> {
+    get_u32_field(body, key)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_each_value_kind() {
+        let body = r#"{"angle": 90, "name": "door", "on": true, "off": false, "none": null, "trim": -1.5e2}"#;
+        assert_eq!(field(body, "angle"), Ok(Some(Value::Number("90"))));
+        assert_eq!(field(body, "name"), Ok(Some(Value::String("door"))));
+        assert_eq!(field(body, "on"), Ok(Some(Value::Bool(true))));
+        assert_eq!(field(body, "off"), Ok(Some(Value::Bool(false))));
+        assert_eq!(field(body, "none"), Ok(Some(Value::Null)));
+        assert_eq!(field(body, "trim"), Ok(Some(Value::Number("-1.5e2"))));
+    }
+
+    #[test]
+    fn missing_field_is_not_an_error() {
+        assert_eq!(field(r#"{"angle": 90}"#, "servo"), Ok(None));
+        assert_eq!(field("{}", "servo"), Ok(None));
+        assert_eq!(field(" \r\n{ } \n", "servo"), Ok(None));
+    }
+
+    #[test]
+    fn last_repeated_key_wins() {
+        assert_eq!(field(r#"{"angle": 1, "angle": 2}"#, "angle"), Ok(Some(Value::Number("2"))));
+    }
+
+    #[test]
+    fn escaped_keys_match() {
+        assert_eq!(field(r#"{"\u0061ngle": 45}"#, "angle"), Ok(Some(Value::Number("45"))));
+        assert_eq!(field(r#"{"a\"b": 1}"#, "a\"b"), Ok(Some(Value::Number("1"))));
+        assert_eq!(field(r#"{"angle\n": 1}"#, "angle"), Ok(None));
+    }
+
+    #[test]
+    fn string_values_keep_their_escapes() {
+        assert_eq!(field(r#"{"name": "a\"b"}"#, "name"), Ok(Some(Value::String(r#"a\"b"#))));
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        for body in [
+            "",
+            "90",
+            r#"{"angle": 90"#,
+            r#"{"angle": 90,}"#,
+            r#"{"angle" 90}"#,
+            r#"{"angle": 90} x"#,
+            r#"{angle: 90}"#,
+            r#"{"angle": 090}"#,
+            r#"{"angle": 9.}"#,
+            r#"{"angle": +9}"#,
+            r#"{"angle": [90]}"#,
+            r#"{"angle": {"deg": 90}}"#,
+            r#"{"angle": tru}"#,
+            r#"{"name": "bad \x"}"#,
+            r#"{"name": "\u12"}"#,
+            "{\"name\": \"tab\there\"}",
+        ] {
+            assert_eq!(field(body, "angle"), Err(Malformed), "{body}");
+        }
+    }
+
+    #[test]
+    fn malformed_after_the_key_still_fails() {
+        assert_eq!(field(r#"{"angle": 90, "servo": }"#, "angle"), Err(Malformed));
+    }
+
+    #[test]
+    fn integer_helpers() {
+        let body = r#"{"angle": 90, "big": 300, "neg": -1, "frac": 1.5, "name": "90"}"#;
+        assert_eq!(get_u32_field(body, "angle"), Some(90));
+        assert_eq!(get_u8_field(body, "angle"), Some(90));
+        assert_eq!(get_u32_field(body, "big"), Some(300));
+        assert_eq!(get_u8_field(body, "big"), None);
+        assert_eq!(get_u32_field(body, "neg"), None);
+        assert_eq!(get_u32_field(body, "frac"), None);
+        assert_eq!(get_u32_field(body, "name"), None);
+        assert_eq!(get_u32_field(r#"{"angle": 90,}"#, "angle"), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! The firmware's hardware-free logic: request parsing and routing, connection
+//! buffers, command parsing, the config record format, image checks, pulse
+//! math and move arbitration
+//!
+//! Nothing here touches a peripheral, so it builds for the host and its tests
+//! run there; see Host Tests in the README.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod actuator;
+pub mod arbiter;
+pub mod buffers;
+pub mod command;
+pub mod http;
+pub mod image;
+pub mod json;
+pub mod mdns;
+pub mod pulse;
+pub mod record;
+pub mod router;
+pub mod serial;
+pub mod time;
+pub mod url;
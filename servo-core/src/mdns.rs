@@ -0,0 +1,109 @@
+/// DNS record type of an IPv4 address
+pub const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+
+/// Read the name starting at `pos` in `packet`: whether it is
+/// `<hostname>.local` (case-insensitively), and the offset after it
+fn is_our_name(packet: &[u8], mut pos: usize, hostname: &str) -> Option<(bool, usize)> {
+    let mut expected = hostname.split('.').chain(core::iter::once("local"));
+    let mut matches = true;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            return Some((matches && expected.next().is_none(), pos));
+        }
+        // Compression pointers only appear in questions after the first; a
+        // query for us is always the plain form
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        let label = packet.get(pos..pos + len)?;
+        matches &= expected.next().is_some_and(|want| want.as_bytes().eq_ignore_ascii_case(label));
+        pos += len;
+    }
+}
+
+/// Whether a DNS query asks for the A record of `<hostname>.local`
+pub fn asks_for_us(packet: &[u8], hostname: &str) -> bool {
+    let Some(header) = packet.get(..12) else {
+        return false;
+    };
+    // Responses from other hosts are not questions for us
+    if header[2] & 0x80 != 0 {
+        return false;
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        let Some((ours, end)) = is_our_name(packet, pos, hostname) else {
+            return false;
+        };
+        let Some(fields) = packet.get(end..end + 4) else {
+            return false;
+        };
+        let qtype = u16::from_be_bytes([fields[0], fields[1]]);
+        if ours && (qtype == TYPE_A || qtype == TYPE_ANY) {
+            return true;
+        }
+        pos = end + 4;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query with one question per `(name, qtype)`
+    fn query(flags: u8, questions: &[(&str, u16)]) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, flags, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0];
+        for (name, qtype) in questions {
+            for label in name.split('.') {
+                packet.push(label.len() as u8);
+                packet.extend_from_slice(label.as_bytes());
+            }
+            packet.push(0);
+            packet.extend_from_slice(&qtype.to_be_bytes());
+            packet.extend_from_slice(&1u16.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn answers_a_and_any_queries_for_our_name() {
+        assert!(asks_for_us(&query(0, &[("esp-servo.local", TYPE_A)]), "esp-servo"));
+        assert!(asks_for_us(&query(0, &[("esp-servo.local", TYPE_ANY)]), "esp-servo"));
+        assert!(asks_for_us(&query(0, &[("ESP-Servo.LOCAL", TYPE_A)]), "esp-servo"));
+        assert!(asks_for_us(&query(0, &[("lab.servo.local", TYPE_A)]), "lab.servo"));
+    }
+
+    #[test]
+    fn finds_our_question_after_others() {
+        let packet = query(0, &[("printer.local", TYPE_A), ("esp-servo.local", TYPE_A)]);
+        assert!(asks_for_us(&packet, "esp-servo"));
+    }
+
+    #[test]
+    fn ignores_other_names_and_types() {
+        assert!(!asks_for_us(&query(0, &[("other.local", TYPE_A)]), "esp-servo"));
+        assert!(!asks_for_us(&query(0, &[("esp-servo", TYPE_A)]), "esp-servo"));
+        assert!(!asks_for_us(&query(0, &[("esp-servo.local.local", TYPE_A)]), "esp-servo"));
+        assert!(!asks_for_us(&query(0, &[("esp-servo.local", 28)]), "esp-servo"));
+    }
+
+    #[test]
+    fn ignores_responses() {
+        assert!(!asks_for_us(&query(0x84, &[("esp-servo.local", TYPE_A)]), "esp-servo"));
+    }
+
+    #[test]
+    fn ignores_truncated_and_compressed_packets() {
+        let packet = query(0, &[("esp-servo.local", TYPE_A)]);
+        assert!(!asks_for_us(&packet[..6], "esp-servo"));
+        assert!(!asks_for_us(&packet[..packet.len() - 3], "esp-servo"));
+        let mut pointer = packet[..12].to_vec();
+        pointer.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(!asks_for_us(&pointer, "esp-servo"));
+    }
+}
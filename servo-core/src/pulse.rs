@@ -0,0 +1,134 @@
+/// SG90 servo configuration
+/// - PWM frequency: 50Hz (20ms period)
+/// - Pulse width: 0.5ms (0°) to 2.5ms (180°)
+pub const SERVO_FREQ_HZ: u32 = 50;
+
+/// Minimum pulse width in microseconds (0 degrees)
+const MIN_PULSE_US: u32 = 500;
+
+/// Maximum pulse width in microseconds (180 degrees)
+const MAX_PULSE_US: u32 = 2500;
+
+/// Pulse-width calibration of one servo
+///
+/// The SG90 defaults map 0-180° onto 500-2500us at 50Hz. Servos that hit their
+/// mechanical stops early with those values (e.g. the MG996R) need a narrower
+/// range, found by stepping the pulse until the horn just stops at each end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServoConfig {
+    /// Pulse width at 0 degrees
+    pub min_pulse_us: u32,
+    /// Pulse width at 180 degrees
+    pub max_pulse_us: u32,
+    /// PWM frequency the servo expects; must match its `ServoTimer`
+    pub freq_hz: u32,
+}
+
+impl ServoConfig {
+    /// Calibration for the SG90 (and most hobby servos)
+    pub const SG90: ServoConfig = ServoConfig {
+        min_pulse_us: MIN_PULSE_US,
+        max_pulse_us: MAX_PULSE_US,
+        freq_hz: SERVO_FREQ_HZ,
+    };
+}
+
+/// Pulse range `min_us..=max_us` made usable in a `period_us` period: a
+/// pulse can't outlast the period, and the range can't be inverted
+pub fn clamp_pulse_range(min_us: u32, max_us: u32, period_us: u32) -> (u32, u32) {
+    let max_us = max_us.min(period_us);
+    (min_us.min(max_us), max_us)
+}
+
+/// Pulse width for `angle` (0-180, larger values clamp to 180) under
+/// `config`, rounded to the nearest microsecond
+///
+/// Trim, inversion and limits are the controller's business and aren't
+/// applied here. With the SG90 calibration, 0°, 90° and 180° are 500, 1500
+/// and 2500us.
+pub fn angle_to_pulse_us(angle: u8, config: &ServoConfig) -> u32 {
+    (angle_f32_to_pulse_us(angle as f32, config.min_pulse_us, config.max_pulse_us) + 0.5) as u32
+}
+
+/// [`angle_to_pulse_us`] without rounding, for fractional angles (clamped to
+/// 0.0-180.0) on a `min_pulse_us..=max_pulse_us` range
+pub fn angle_f32_to_pulse_us(angle: f32, min_pulse_us: u32, max_pulse_us: u32) -> f32 {
+    let span = (max_pulse_us - min_pulse_us) as f32;
+    min_pulse_us as f32 + span * angle.clamp(0.0, 180.0) / 180.0
+}
+
+/// Raw duty for a `pulse_us` pulse in a `period_us` period at `resolution`
+/// steps, rounded to the nearest step
+///
+/// Truncating instead would make the 500us pulse at 50Hz 409 steps (499.5us)
+/// instead of 410 at 14 bits. A pulse longer than the period is full duty.
+pub fn pulse_us_to_duty(pulse_us: u32, period_us: u32, resolution: u32) -> u32 {
+    pulse_f32_to_duty(pulse_us as f32, period_us, resolution)
+}
+
+/// [`pulse_us_to_duty`] for a fractional pulse
+pub fn pulse_f32_to_duty(pulse_us: f32, period_us: u32, resolution: u32) -> u32 {
+    let pulse_us = pulse_us.clamp(0.0, period_us as f32);
+    // The scale factor is applied last so the product stays well within f32 precision
+    (pulse_us * (resolution as f32 / period_us as f32) + 0.5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 14-bit duty, as on the ESP32's LEDC
+    const RESOLUTION: u32 = 16384;
+    /// 50Hz
+    const PERIOD_US: u32 = 20_000;
+
+    #[test]
+    fn sg90_angles_map_onto_its_pulse_range() {
+        assert_eq!(angle_to_pulse_us(0, &ServoConfig::SG90), 500);
+        assert_eq!(angle_to_pulse_us(90, &ServoConfig::SG90), 1500);
+        assert_eq!(angle_to_pulse_us(180, &ServoConfig::SG90), 2500);
+    }
+
+    #[test]
+    fn angles_past_180_clamp() {
+        assert_eq!(angle_to_pulse_us(255, &ServoConfig::SG90), 2500);
+        assert_eq!(angle_f32_to_pulse_us(-5.0, 500, 2500), 500.0);
+    }
+
+    #[test]
+    fn calibrated_range_bounds_the_pulse() {
+        let mg996r = ServoConfig { min_pulse_us: 600, max_pulse_us: 2400, ..ServoConfig::SG90 };
+        assert_eq!(angle_to_pulse_us(0, &mg996r), 600);
+        assert_eq!(angle_to_pulse_us(90, &mg996r), 1500);
+        assert_eq!(angle_to_pulse_us(180, &mg996r), 2400);
+    }
+
+    #[test]
+    fn fractional_angles_stay_unrounded() {
+        assert_eq!(angle_f32_to_pulse_us(90.9, 500, 2500), 1510.0);
+        assert_eq!(angle_to_pulse_us(91, &ServoConfig::SG90), 1511);
+    }
+
+    #[test]
+    fn duty_rounds_to_the_nearest_step() {
+        assert_eq!(pulse_us_to_duty(500, PERIOD_US, RESOLUTION), 410);
+        assert_eq!(pulse_us_to_duty(1500, PERIOD_US, RESOLUTION), 1229);
+        assert_eq!(pulse_us_to_duty(2500, PERIOD_US, RESOLUTION), 2048);
+    }
+
+    #[test]
+    fn duty_is_bounded_by_the_period() {
+        assert_eq!(pulse_us_to_duty(0, PERIOD_US, RESOLUTION), 0);
+        assert_eq!(pulse_us_to_duty(PERIOD_US, PERIOD_US, RESOLUTION), RESOLUTION);
+        assert_eq!(pulse_us_to_duty(25_000, PERIOD_US, RESOLUTION), RESOLUTION);
+        assert_eq!(pulse_f32_to_duty(-1.0, PERIOD_US, RESOLUTION), 0);
+    }
+
+    #[test]
+    fn pulse_ranges_fit_the_period() {
+        assert_eq!(clamp_pulse_range(500, 2500, PERIOD_US), (500, 2500));
+        // 333Hz: a 3000us period
+        assert_eq!(clamp_pulse_range(500, 3500, 3000), (500, 3000));
+        assert_eq!(clamp_pulse_range(2600, 2500, PERIOD_US), (2500, 2500));
+    }
+}
@@ -0,0 +1,137 @@
+use alloc::vec::Vec;
+
+/// Marks a written config record ("SRVO")
+const MAGIC: u32 = 0x5352_564F;
+
+/// Layout version of the record header. Fields inside the payload are
+/// tagged, so adding a field does not need a new version; older firmware
+/// skips tags it doesn't know and newer firmware defaults missing ones.
+const FORMAT_VERSION: u8 = 1;
+
+/// Header: magic (4), version (1), sequence (4), payload length (2), CRC-32 (4)
+pub const HEADER_LEN: usize = 15;
+
+/// What a valid record header says about the payload after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Incremented on every save; the highest one is the newest record
+    pub sequence: u32,
+    pub len: usize,
+    crc: u32,
+}
+
+impl Header {
+    /// Whether `payload` is the one this header was written for
+    pub fn matches(&self, payload: &[u8]) -> bool {
+        payload.len() == self.len && crc32(payload) == self.crc
+    }
+}
+
+/// Read a record header, or `None` if `header` isn't one (erased flash,
+/// another format)
+pub fn parse_header(header: &[u8; HEADER_LEN]) -> Option<Header> {
+    let word = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+    if word(0) != MAGIC || header[4] != FORMAT_VERSION {
+        return None;
+    }
+    Some(Header { sequence: word(5), len: u16::from_le_bytes([header[9], header[10]]) as usize, crc: word(11) })
+}
+
+/// Header followed by `payload`, ready to write to a slot
+pub fn encode(sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&MAGIC.to_le_bytes());
+    record.push(FORMAT_VERSION);
+    record.extend_from_slice(&sequence.to_le_bytes());
+    record.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    record.extend_from_slice(&crc32(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// CRC-32 (IEEE) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The valid record with the highest sequence number, from
+/// `(sequence, record)` pairs
+pub fn newest<T>(records: impl IntoIterator<Item = (u32, T)>) -> Option<(u32, T)> {
+    records.into_iter().max_by_key(|(sequence, _)| *sequence)
+}
+
+/// Sequence number and slot (of two) for the next save, given the newest
+/// record's: the other slot, so a power cut mid-write leaves the newest intact
+pub fn next_slot(newest: Option<(u32, usize)>) -> (u32, usize) {
+    match newest {
+        Some((sequence, slot)) => (sequence.wrapping_add(1), 1 - slot),
+        None => (1, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(record: &[u8]) -> Option<Header> {
+        parse_header(record[..HEADER_LEN].try_into().unwrap())
+    }
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn encoded_records_read_back() {
+        let record = encode(7, b"payload");
+        let header = header(&record).unwrap();
+        assert_eq!(header.sequence, 7);
+        assert_eq!(header.len, 7);
+        assert!(header.matches(&record[HEADER_LEN..]));
+    }
+
+    #[test]
+    fn erased_flash_is_not_a_record() {
+        assert_eq!(parse_header(&[0xFF; HEADER_LEN]), None);
+    }
+
+    #[test]
+    fn other_versions_are_not_read() {
+        let mut record = encode(1, b"x");
+        record[4] = FORMAT_VERSION + 1;
+        assert_eq!(header(&record), None);
+    }
+
+    #[test]
+    fn corrupted_payloads_fail_the_crc() {
+        let mut record = encode(1, b"payload");
+        let header = header(&record).unwrap();
+        record[HEADER_LEN] ^= 1;
+        assert!(!header.matches(&record[HEADER_LEN..]));
+        assert!(!header.matches(b"payloa"));
+    }
+
+    #[test]
+    fn picks_the_newest_record() {
+        assert_eq!(newest([(3, 'a'), (4, 'b')]), Some((4, 'b')));
+        assert_eq!(newest([(9, 'a'), (4, 'b')]), Some((9, 'a')));
+        assert_eq!(newest([(2, 'a')]), Some((2, 'a')));
+        assert_eq!(newest::<char>([]), None);
+    }
+
+    #[test]
+    fn saves_alternate_slots() {
+        assert_eq!(next_slot(None), (1, 0));
+        assert_eq!(next_slot(Some((1, 0))), (2, 1));
+        assert_eq!(next_slot(Some((2, 1))), (3, 0));
+    }
+}
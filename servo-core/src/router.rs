@@ -0,0 +1,889 @@
+use alloc::format;
+use alloc::string::String;
+
+use crate::command::{CommandSource, NotReady, ServoCommand, MAX_PULSE_HOLD_MS, MAX_SWEEP_STEP_MS};
+use crate::http::{
+    find_header, has_extra_segments, headers_end, json_escape, parse_bare_negative, parse_detach_target, parse_empty_angle,
+    parse_pulse_target, parse_range, parse_relative_target, parse_request, parse_servo_target, request_body,
+    split_target,
+};
+use crate::json;
+use crate::url::form_param;
+
+/// Keep connections open between requests (`Connection: keep-alive`) so
+/// browsers don't reopen a TCP connection per call
+const HTTP_KEEP_ALIVE: bool = true;
+
+/// Requests served on one connection before it is closed anyway
+///
+/// With few connection handlers a persistent connection occupies one of them,
+/// so the cap and the short idle timeout below stop a single client from
+/// starving the others.
+pub const MAX_REQUESTS_PER_CONNECTION: u32 = 16;
+
+/// How long a kept-alive connection may wait for its next request
+pub const KEEP_ALIVE_IDLE_TIMEOUT_MS: u64 = 2_000;
+
+/// How `GET /servo/` (trailing slash, no angle) is answered: with the current
+/// angle when `true`, or with a `400` usage message when `false`
+const TRAILING_SLASH_RETURNS_ANGLE: bool = true;
+
+/// How an empty query value (`/servo?angle=`) is answered: with the current
+/// angle when `true`, or with a `400` like any other unparsable angle when
+/// `false`. `angle=0` is always a move to 0 and a missing `angle` always a `400`.
+const EMPTY_ANGLE_RETURNS_ANGLE: bool = false;
+
+/// How a bare negative angle (`/servo/-5`) is answered: as a relative move
+/// like `/servo/by/-5` when `true`, or with a `400` as an invalid absolute
+/// angle when `false`
+const BARE_NEGATIVE_IS_RELATIVE: bool = false;
+
+/// Include `target` and `moving` next to the angle in read responses
+const REPORT_MOTION: bool = true;
+
+/// JSON field name used for the servo angle in responses
+///
+/// Change this to match clients that expect a different name (e.g. `position`).
+pub const ANGLE_FIELD: &str = "angle";
+
+/// Path browsers fetch an icon from on every page view; answered with an
+/// empty `204` and left out of the request log
+pub const FAVICON_PATH: &str = "/favicon.ico";
+
+/// Origin allowed to call the API from a browser page served elsewhere, sent
+/// as `Access-Control-Allow-Origin` (`None` = no CORS headers)
+///
+/// `"*"` lets any page use the API; set a single origin such as
+/// `"http://dashboard.local"` to lock it down.
+const CORS_ALLOW_ORIGIN: Option<&str> = Some("*");
+
+/// Methods and request headers a cross-origin page may use, answered to
+/// `OPTIONS` preflight requests
+const CORS_ALLOW_METHODS: &str = "GET, HEAD, POST, DELETE, OPTIONS";
+const CORS_ALLOW_HEADERS: &str = "Content-Type, Range, Authorization";
+
+/// The device as the routes see it: the live servo state they read and the
+/// command queue they send to
+///
+/// The firmware implements it over its shared state; tests use a fake.
+pub trait Device {
+    /// Number of servo slots; indices at or above it are unknown
+    fn servo_count(&self) -> usize;
+    /// Angle most recently applied to `servo`
+    fn current_angle(&self, servo: usize) -> u8;
+    /// Angle `servo` is heading to
+    fn target_angle(&self, servo: usize) -> u8;
+    /// Whether `servo` is still on its way to the target
+    fn is_moving(&self, servo: usize) -> bool;
+    /// Whether a move may be accepted now, or why not
+    fn check_ready(&self) -> Result<(), NotReady>;
+    /// Range a client may command on `servo`
+    fn allowed_range(&self, servo: usize) -> (u8, u8);
+    /// The angle to apply for a requested one, or the allowed range if the
+    /// angle is refused (see [`limit_angle`](crate::command::limit_angle))
+    fn apply_limits(&self, servo: usize, angle: u8) -> Result<u8, (u8, u8)>;
+    /// Whether accepted moves are held until the emergency stop is cleared
+    fn moves_held(&self) -> bool;
+    /// Queue `command` for the servos
+    fn send(&self, command: ServoCommand);
+    /// Body of `GET /status`
+    fn status_json(&self) -> String;
+    /// Bearer token required on routes that move the servos or change
+    /// settings (`None` = no authentication)
+    fn api_token(&self) -> Option<&str>;
+}
+
+/// Simple HTTP response builder
+pub fn build_response(status: &str, content_type: &str, body: &str) -> String {
+    build_response_with_headers(status, content_type, "", body)
+}
+
+/// HTTP response builder with extra headers (each ending in `\r\n`)
+///
+/// Headers always come in the same order: `Content-Type`, `Content-Length`
+/// (the body's length in bytes, so UTF-8 pages are counted right), the CORS
+/// header, then `extra_headers`. The `Connection` header is added ahead of
+/// them by the server loop with [`with_connection_header`], once it knows
+/// whether the connection stays open. A `204` must not carry a
+/// `Content-Length`, so it gets none.
+pub fn build_response_with_headers(status: &str, content_type: &str, extra_headers: &str, body: &str) -> String {
+    debug_assert!(extra_headers.is_empty() || extra_headers.ends_with("\r\n"));
+    let content_length = if status.starts_with("204") {
+        String::new()
+    } else {
+        format!("Content-Length: {}\r\n", body.len())
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\n{}{}{}\r\n{}",
+        status,
+        content_type,
+        content_length,
+        cors_headers(),
+        extra_headers,
+        body
+    )
+}
+
+/// `Access-Control-Allow-Origin` header line for [`CORS_ALLOW_ORIGIN`]
+pub fn cors_headers() -> String {
+    match CORS_ALLOW_ORIGIN {
+        Some(origin) => format!("Access-Control-Allow-Origin: {}\r\n", origin),
+        None => String::new(),
+    }
+}
+
+/// Answer a CORS preflight (`OPTIONS`) with `204` and the allowed methods
+/// and headers
+fn handle_preflight() -> String {
+    let headers = format!(
+        "Access-Control-Allow-Methods: {}\r\nAccess-Control-Allow-Headers: {}\r\nAccess-Control-Max-Age: 600\r\n",
+        CORS_ALLOW_METHODS, CORS_ALLOW_HEADERS
+    );
+    build_response_with_headers("204 No Content", "text/plain", &headers, "")
+}
+
+/// Whether the client asked to keep the connection open: the HTTP/1.1
+/// default unless it sent `Connection: close`, and opt-in for HTTP/1.0
+pub fn wants_keep_alive(request: &str) -> bool {
+    if !HTTP_KEEP_ALIVE {
+        return false;
+    }
+    let connection = find_header(request, "Connection");
+    let http_11 = request.lines().next().is_some_and(|line| line.ends_with("HTTP/1.1"));
+    match connection {
+        Some(value) if value.eq_ignore_ascii_case("close") => false,
+        Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+        _ => http_11,
+    }
+}
+
+/// Insert the `Connection` header (and `Keep-Alive` hints) after the status line
+pub fn with_connection_header(response: String, keep_alive: bool) -> String {
+    let Some(status_end) = response.find("\r\n") else {
+        return response;
+    };
+    let header = if keep_alive {
+        format!(
+            "Connection: keep-alive\r\nKeep-Alive: timeout={}, max={}\r\n",
+            KEEP_ALIVE_IDLE_TIMEOUT_MS / 1000,
+            MAX_REQUESTS_PER_CONNECTION
+        )
+    } else {
+        String::from("Connection: close\r\n")
+    };
+    let mut out = String::with_capacity(response.len() + header.len());
+    out.push_str(&response[..status_end + 2]);
+    out.push_str(&header);
+    out.push_str(&response[status_end + 2..]);
+    out
+}
+
+/// Cut a built response down to its status line and headers, for `HEAD`;
+/// `Content-Length` still gives the length of the body a GET would get
+fn without_body(mut response: String) -> String {
+    if let Some(end) = response.find("\r\n\r\n") {
+        response.truncate(end + 4);
+    }
+    response
+}
+
+/// Numeric status of a built response (`0` if it has no status line)
+pub fn status_code(response: &str) -> u16 {
+    response.get(9..12).and_then(|code| code.parse().ok()).unwrap_or(0)
+}
+
+/// Whether `request` announces a longer body (`Content-Length`) than it holds,
+/// i.e. the request buffer filled before the body was read
+pub fn body_truncated(request: &str) -> bool {
+    find_header(request, "Content-Length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .is_some_and(|len| len > request_body(request).len())
+}
+
+/// Answer for a request that didn't fit the receive buffer, or `None` if it
+/// arrived whole
+///
+/// Headers that never ended get `431`, a body cut short of its
+/// `Content-Length` gets `413`. The rest of such a request is still unread, so
+/// the connection must not be reused after either.
+pub fn oversize_response(request: &str) -> Option<String> {
+    if headers_end(request.as_bytes()).is_none() {
+        Some(build_response("431 Request Header Fields Too Large", "text/plain", "Request Header Fields Too Large"))
+    } else if body_truncated(request) {
+        Some(build_response("413 Payload Too Large", "text/plain", "Payload Too Large"))
+    } else {
+        None
+    }
+}
+
+/// Whether the client accepts an HTML page: browsers list `text/html`, while
+/// scripts typically send no `Accept` header or ask for JSON
+pub fn accepts_html(request: &str) -> bool {
+    find_header(request, "Accept").is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Serve a static asset, honoring a single-range `Range` request with `206`
+///
+/// Absent or unsatisfiable ranges fall back to the full content.
+pub fn serve_static(request: &str, content_type: &str, body: &str) -> String {
+    let range = find_header(request, "Range").and_then(|range| parse_range(range, body.len()));
+    match range.and_then(|(start, end)| Some((start, end, body.get(start..=end)?))) {
+        Some((start, end, slice)) => {
+            let headers = format!("Content-Range: bytes {}-{}/{}\r\nAccept-Ranges: bytes\r\n", start, end, body.len());
+            build_response_with_headers("206 Partial Content", content_type, &headers, slice)
+        }
+        None => build_response_with_headers("200 OK", content_type, "Accept-Ranges: bytes\r\n", body),
+    }
+}
+
+/// `200 OK` with a JSON body
+pub fn json_ok(body: &str) -> String {
+    build_response("200 OK", "application/json", body)
+}
+
+/// `404` echoing the path that was asked for, to help debug clients
+pub fn not_found(path: &str) -> String {
+    let body = format!(r#"{{"error": "Not Found", "path": "{}"}}"#, json_escape(path));
+    build_response("404 Not Found", "application/json", &body)
+}
+
+pub fn method_not_allowed() -> String {
+    let body = r#"{"error": "Method Not Allowed"}"#;
+    build_response("405 Method Not Allowed", "application/json", body)
+}
+
+/// Whether `request` carries `token` (always true when there is none)
+pub fn authorized(request: &str, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    find_header(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| sent.trim() == token)
+}
+
+pub fn unauthorized() -> String {
+    let body = r#"{"error": "Unauthorized. Send Authorization: Bearer <token>"}"#;
+    build_response_with_headers("401 Unauthorized", "application/json", "WWW-Authenticate: Bearer\r\n", body)
+}
+
+/// Build the JSON body for reading one servo's angle (`GET /servo/`),
+/// with its motion state when [`REPORT_MOTION`] is set
+pub fn build_angle_json(device: &dyn Device, servo: usize) -> String {
+    if REPORT_MOTION {
+        format!(
+            r#"{{"servo": {}, "{}": {}, "target": {}, "moving": {}}}"#,
+            servo,
+            ANGLE_FIELD,
+            device.current_angle(servo),
+            device.target_angle(servo),
+            device.is_moving(servo)
+        )
+    } else {
+        format!(r#"{{"servo": {}, "{}": {}}}"#, servo, ANGLE_FIELD, device.current_angle(servo))
+    }
+}
+
+/// `404` for a servo index the device doesn't have, naming the valid range
+fn unknown_servo(device: &dyn Device, servo: u8) -> String {
+    let body = format!(
+        r#"{{"error": "Unknown servo {}. Valid indices are 0-{}", "servo_count": {}}}"#,
+        servo,
+        device.servo_count() - 1,
+        device.servo_count()
+    );
+    build_response("404 Not Found", "application/json", &body)
+}
+
+/// Queue a request to detach `servo`
+fn handle_servo_detach(device: &dyn Device, servo: u8) -> String {
+    if servo as usize >= device.servo_count() {
+        return unknown_servo(device, servo);
+    }
+    device.send(ServoCommand::detach(servo, CommandSource::Http));
+    let body = format!(r#"{{"servo": {}, "attached": false}}"#, servo);
+    build_response("200 OK", "application/json", &body)
+}
+
+/// Response for a move refused by [`Device::check_ready`]: `503` while
+/// starting up (it clears by itself) or going to sleep, `423` when an explicit
+/// clear is needed
+fn not_ready(reason: NotReady) -> String {
+    match reason {
+        NotReady::Starting => {
+            let body = r#"{"error": "Servos are still homing. Retry shortly", "reason": "starting"}"#;
+            build_response_with_headers("503 Service Unavailable", "application/json", "Retry-After: 1\r\n", body)
+        }
+        NotReady::Fault => {
+            let body = r#"{"error": "Overload fault latched. POST /fault/clear to resume", "reason": "fault"}"#;
+            build_response("423 Locked", "application/json", body)
+        }
+        NotReady::Estop => {
+            let body = r#"{"error": "Emergency stop engaged. POST /estop/clear to resume", "reason": "estop"}"#;
+            build_response("423 Locked", "application/json", body)
+        }
+        NotReady::LowPower => {
+            let body = r#"{"error": "Going into deep sleep after idling", "reason": "low_power"}"#;
+            build_response("503 Service Unavailable", "application/json", body)
+        }
+    }
+}
+
+/// Check that `servo` may be moved to `angle`, returning the angle to apply
+/// or the error response
+fn validate_move(device: &dyn Device, servo: u8, angle: u8) -> Result<u8, String> {
+    if servo as usize >= device.servo_count() {
+        return Err(unknown_servo(device, servo));
+    }
+    if angle > 180 {
+        let body = r#"{"error": "Angle must be between 0 and 180"}"#;
+        return Err(build_response("400 Bad Request", "application/json", body));
+    }
+    if let Err(reason) = device.check_ready() {
+        return Err(not_ready(reason));
+    }
+    device.apply_limits(servo as usize, angle).map_err(|(min, max)| {
+        let body = format!(
+            r#"{{"error": "Angle outside the allowed range {}-{}", "min": {}, "max": {}}}"#,
+            min, max, min, max
+        );
+        build_response("400 Bad Request", "application/json", &body)
+    })
+}
+
+/// Status line for an accepted move: `202` if it will be held until the
+/// emergency stop is cleared
+fn move_accepted_status(device: &dyn Device) -> &'static str {
+    if device.moves_held() { "202 Accepted" } else { "200 OK" }
+}
+
+/// Validate and send a request to move `servo` to `angle`
+pub fn handle_servo_set(device: &dyn Device, servo: u8, angle: u8) -> String {
+    match validate_move(device, servo, angle) {
+        Ok(angle) => {
+            device.send(ServoCommand::set(servo, angle, CommandSource::Http));
+            let body = format!(r#"{{"servo": {}, "{}": {}}}"#, servo, ANGLE_FIELD, angle);
+            build_response(move_accepted_status(device), "application/json", &body)
+        }
+        Err(response) => response,
+    }
+}
+
+/// Move `servo` by `delta` degrees from the angle it is heading to, saturating
+/// at its allowed range
+fn handle_servo_relative(device: &dyn Device, servo: u8, delta: i16) -> String {
+    if servo as usize >= device.servo_count() {
+        return unknown_servo(device, servo);
+    }
+    let (min, max) = device.allowed_range(servo as usize);
+    let from = device.target_angle(servo as usize) as i16;
+    let angle = (from + delta).clamp(min as i16, max as i16) as u8;
+    handle_servo_set(device, servo, angle)
+}
+
+/// Validate and send a request to pulse servo 0 to `angle` for `hold_ms`
+fn handle_servo_pulse(device: &dyn Device, angle: u8, hold_ms: u32) -> String {
+    if !(1..=MAX_PULSE_HOLD_MS).contains(&hold_ms) {
+        let body = format!(r#"{{"error": "Hold must be between 1 and {} ms"}}"#, MAX_PULSE_HOLD_MS);
+        return build_response("400 Bad Request", "application/json", &body);
+    }
+    match validate_move(device, 0, angle) {
+        Ok(angle) => {
+            device.send(ServoCommand::pulse(0, angle, hold_ms, CommandSource::Http));
+            let body = format!(r#"{{"servo": 0, "{}": {}, "hold_ms": {}}}"#, ANGLE_FIELD, angle, hold_ms);
+            build_response(move_accepted_status(device), "application/json", &body)
+        }
+        Err(response) => response,
+    }
+}
+
+/// Apply a `POST /servo` body like `{"angle": 90}` or `{"servo": 1, "angle": 90}`
+fn handle_servo_post(device: &dyn Device, body: &str) -> String {
+    let servo = match json::field(body, "servo") {
+        Err(json::Malformed) => {
+            let body = r#"{"error": "Malformed JSON body. Send {\"angle\": <0-180>}"}"#;
+            return build_response("400 Bad Request", "application/json", body);
+        }
+        Ok(None) => 0,
+        Ok(Some(value)) => match value.as_u32().and_then(|servo| u8::try_from(servo).ok()) {
+            Some(servo) => servo,
+            None => {
+                let body = r#"{"error": "Invalid servo index"}"#;
+                return build_response("400 Bad Request", "application/json", body);
+            }
+        },
+    };
+    match json::get_u32_field(body, ANGLE_FIELD) {
+        Some(angle) if angle <= 180 => handle_servo_set(device, servo, angle as u8),
+        Some(_) => {
+            let body = r#"{"error": "Angle must be between 0 and 180"}"#;
+            build_response("400 Bad Request", "application/json", body)
+        }
+        _ => {
+            let body = r#"{"error": "Missing or invalid angle. Send {\"angle\": <0-180>}"}"#;
+            build_response("400 Bad Request", "application/json", body)
+        }
+    }
+}
+
+/// A parsed request as seen by route handlers
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// Percent-decoded path, without the query string
+    pub path: &'a str,
+    /// Raw query string after the `?`, if any; read values with [`form_param`]
+    pub query: Option<&'a str>,
+    pub body: &'a str,
+    /// Raw request text, for handlers that read headers
+    pub raw: &'a str,
+    /// State and command queue the request acts on
+    pub device: &'a dyn Device,
+}
+
+/// How a [`Route`] matches the request path
+pub enum PathMatch {
+    Exact(&'static str),
+    Prefix(&'static str),
+}
+
+impl PathMatch {
+    /// Exact paths only match without a query, so `GET /servo?angle=90` is a
+    /// move rather than a read of `/servo`
+    fn matches(&self, path: &str, query: Option<&str>) -> bool {
+        match self {
+            PathMatch::Exact(exact) => path == *exact && query.is_none(),
+            PathMatch::Prefix(prefix) => path.starts_with(prefix),
+        }
+    }
+}
+
+/// One entry of a route table such as [`SERVO_ROUTES`]
+pub struct Route {
+    /// Method answered, upper-case; `None` passes every method to the handler
+    pub method: Option<&'static str>,
+    pub path: PathMatch,
+    /// Require the device's [`api_token`](Device::api_token)
+    pub auth: bool,
+    pub handler: fn(&Request) -> String,
+}
+
+/// Routes for reading and moving the servos, tried after the firmware's own
+/// (so `/servo/preset/...` there wins over the `/servo` prefix here)
+///
+/// More specific entries must come before prefixes that cover them (e.g.
+/// `/servo/pulse/` before `/servo`).
+pub static SERVO_ROUTES: &[Route] = &[
+    Route { method: Some("GET"), path: PathMatch::Exact("/status"), auth: false, handler: |r| json_ok(&r.device.status_json()) },
+    Route { method: Some("GET"), path: PathMatch::Exact("/health"), auth: false, handler: |_| json_ok(r#"{"healthy": true}"#) },
+    Route { method: Some("GET"), path: PathMatch::Exact("/servo"), auth: false, handler: route_servo_read },
+    Route { method: Some("GET"), path: PathMatch::Exact("/servo/"), auth: false, handler: route_servo_read },
+    Route { method: Some("POST"), path: PathMatch::Exact("/servo"), auth: true, handler: |r| handle_servo_post(r.device, r.body) },
+    Route { method: Some("POST"), path: PathMatch::Exact("/servo/"), auth: true, handler: |r| handle_servo_post(r.device, r.body) },
+    Route { method: Some("GET"), path: PathMatch::Prefix("/servo/pulse/"), auth: true, handler: route_servo_pulse },
+    Route { method: Some("GET"), path: PathMatch::Prefix("/servo/sweep"), auth: true, handler: route_servo_sweep },
+    Route { method: Some("GET"), path: PathMatch::Prefix("/servo/by/"), auth: true, handler: route_servo_relative },
+    Route { method: Some("GET"), path: PathMatch::Prefix("/servo"), auth: true, handler: route_servo_move },
+];
+
+fn route_servo_read(request: &Request) -> String {
+    if TRAILING_SLASH_RETURNS_ANGLE {
+        json_ok(&build_angle_json(request.device, 0))
+    } else {
+        let body = r#"{"error": "Missing angle. Use /servo/<0-180>, e.g. /servo/90"}"#;
+        build_response("400 Bad Request", "application/json", body)
+    }
+}
+
+fn route_servo_pulse(request: &Request) -> String {
+    match parse_pulse_target(request.path) {
+        Some((angle, hold_ms)) => handle_servo_pulse(request.device, angle, hold_ms),
+        None => {
+            let body = r#"{"error": "Use /servo/pulse/<angle>/<hold_ms>"}"#;
+            build_response("400 Bad Request", "application/json", body)
+        }
+    }
+}
+
+fn route_servo_relative(request: &Request) -> String {
+    match parse_relative_target(request.path) {
+        Some(delta) => handle_servo_relative(request.device, 0, delta),
+        None => {
+            let body = r#"{"error": "Use /servo/by/<delta>, e.g. /servo/by/-5"}"#;
+            build_response("400 Bad Request", "application/json", body)
+        }
+    }
+}
+
+/// `GET /servo/sweep?from=<angle>&to=<angle>&step_ms=<ms>`: sweep servo 0
+/// from one angle to another, `step_ms` (default 15) per degree
+fn route_servo_sweep(request: &Request) -> String {
+    let device = request.device;
+    let query = request.query.unwrap_or("");
+    let from = form_param(query, "from").and_then(|v| v.parse::<u8>().ok());
+    let to = form_param(query, "to").and_then(|v| v.parse::<u8>().ok());
+    let step_ms = form_param(query, "step_ms").map_or(Some(15), |v| v.parse::<u16>().ok());
+    let (Some(from), Some(to), Some(step_ms)) = (from, to, step_ms) else {
+        let body = r#"{"error": "Use /servo/sweep?from=<0-180>&to=<0-180>&step_ms=<ms>"}"#;
+        return build_response("400 Bad Request", "application/json", body);
+    };
+    if !(1..=MAX_SWEEP_STEP_MS).contains(&step_ms) {
+        let body = format!(r#"{{"error": "step_ms must be between 1 and {}"}}"#, MAX_SWEEP_STEP_MS);
+        return build_response("400 Bad Request", "application/json", &body);
+    }
+    let (from, to) = match (validate_move(device, 0, from), validate_move(device, 0, to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+    device.send(ServoCommand::sweep(0, from, to, step_ms, CommandSource::Http));
+    let body = format!(r#"{{"sweeping": true, "from": {}, "to": {}, "step_ms": {}}}"#, from, to, step_ms);
+    build_response(move_accepted_status(device), "application/json", &body)
+}
+
+/// Every other `/servo...` GET: detach, angle reads and absolute moves
+fn route_servo_move(request: &Request) -> String {
+    let (device, path) = (request.device, request.path);
+    if let Some(servo) = parse_detach_target(path) {
+        handle_servo_detach(device, servo)
+    } else if let Some(servo) = parse_empty_angle(path, request.query).filter(|_| EMPTY_ANGLE_RETURNS_ANGLE) {
+        if (servo as usize) < device.servo_count() {
+            json_ok(&build_angle_json(device, servo as usize))
+        } else {
+            unknown_servo(device, servo)
+        }
+    } else if has_extra_segments(path) {
+        let body = r#"{"error": "Too many path segments. Use /servo/<angle> or /servo/<index>/<angle>"}"#;
+        build_response("400 Bad Request", "application/json", body)
+    } else if let Some((servo, delta)) = parse_bare_negative(path) {
+        if BARE_NEGATIVE_IS_RELATIVE {
+            handle_servo_relative(device, servo, delta)
+        } else {
+            let body = r#"{"error": "Negative angles are not valid absolute angles. Use /servo/by/<delta> for a relative move"}"#;
+            build_response("400 Bad Request", "application/json", body)
+        }
+    } else {
+        match parse_servo_target(path, request.query) {
+            Ok((servo, angle)) => handle_servo_set(device, servo, angle),
+            Err(error) => build_response("400 Bad Request", "application/json", error.body()),
+        }
+    }
+}
+
+/// Handle an incoming HTTP request and return a response
+///
+/// `tables` are tried in order, and each table's routes in order; the first
+/// whose path and method match handles the request, after checking the
+/// device's API token if the route needs it. A path that matches only with
+/// other methods answers `405`, any other path `404`.
+pub fn handle_request(raw: &str, device: &dyn Device, tables: &[&[Route]]) -> String {
+    let Some((method, path)) = parse_request(raw) else {
+        return build_response("400 Bad Request", "text/plain", "Bad Request");
+    };
+    // Fragments are client-side only, but some tools send them anyway
+    let path = path.split('#').next().unwrap_or(path);
+    // Methods are case-sensitive per spec, but some minimal clients send `get`
+    let method = method.to_ascii_uppercase();
+    // HEAD is answered like GET without the body, but only by the open read
+    // routes, since some GETs move the servo
+    let head = method == "HEAD";
+    // Route on the decoded path, so `/servo%2F90` and `angle=%39%30` match too;
+    // the query is split off first and its values decoded one by one
+    let (path, query) = split_target(path);
+    let method = if head { "GET" } else { &method };
+    let request = Request { method, path: &path, query, body: request_body(raw), raw, device };
+
+    if request.method == "OPTIONS" && CORS_ALLOW_ORIGIN.is_some() {
+        return handle_preflight();
+    }
+    if request.method == "GET" && request.path == FAVICON_PATH {
+        return build_response("204 No Content", "image/x-icon", "");
+    }
+
+    let mut path_matched = false;
+    for route in tables.iter().flat_map(|table| table.iter()) {
+        if !route.path.matches(request.path, request.query) {
+            continue;
+        }
+        if head && !route.auth && route.method.is_none_or(|method| method == "GET") {
+            return without_body((route.handler)(&request));
+        }
+        if !head && route.method.is_none_or(|method| method == request.method) {
+            if route.auth && !authorized(request.raw, device.api_token()) {
+                return unauthorized();
+            }
+            return (route.handler)(&request);
+        }
+        path_matched = true;
+    }
+    if path_matched {
+        method_not_allowed()
+    } else {
+        not_found(request.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::buffers::RX_BUFFER_SIZE;
+    use crate::command::{limit_angle, ServoAction};
+
+    /// Two servos at 90°, ready, limited to 10-170 and recording what is sent
+    struct FakeDevice {
+        range: (u8, u8),
+        strict: bool,
+        ready: Result<(), NotReady>,
+        token: Option<&'static str>,
+        sent: RefCell<Vec<ServoCommand>>,
+    }
+
+    impl Default for FakeDevice {
+        fn default() -> Self {
+            Self { range: (10, 170), strict: false, ready: Ok(()), token: None, sent: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl Device for FakeDevice {
+        fn servo_count(&self) -> usize {
+            2
+        }
+        fn current_angle(&self, _servo: usize) -> u8 {
+            90
+        }
+        fn target_angle(&self, _servo: usize) -> u8 {
+            90
+        }
+        fn is_moving(&self, _servo: usize) -> bool {
+            false
+        }
+        fn check_ready(&self) -> Result<(), NotReady> {
+            self.ready
+        }
+        fn allowed_range(&self, _servo: usize) -> (u8, u8) {
+            self.range
+        }
+        fn apply_limits(&self, _servo: usize, angle: u8) -> Result<u8, (u8, u8)> {
+            limit_angle(angle, self.range, self.strict)
+        }
+        fn moves_held(&self) -> bool {
+            false
+        }
+        fn send(&self, command: ServoCommand) {
+            self.sent.borrow_mut().push(command);
+        }
+        fn status_json(&self) -> String {
+            String::from(r#"{"angle": 90, "ready": true}"#)
+        }
+        fn api_token(&self) -> Option<&str> {
+            self.token
+        }
+    }
+
+    fn get(device: &FakeDevice, raw: &str) -> String {
+        handle_request(raw, device, &[SERVO_ROUTES])
+    }
+
+    #[test]
+    fn known_routes_are_handled() {
+        let device = FakeDevice::default();
+        let response = get(&device, "GET /health HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 200);
+        assert_eq!(request_body(&response), r#"{"healthy": true}"#);
+
+        let response = get(&device, "GET /servo/1/45 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 200);
+        assert_eq!(request_body(&response), r#"{"servo": 1, "angle": 45}"#);
+        assert_eq!(*device.sent.borrow(), [ServoCommand::set(1, 45, CommandSource::Http)]);
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let device = FakeDevice::default();
+        let response = get(&device, "GET /nope HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 404);
+        assert_eq!(request_body(&response), r#"{"error": "Not Found", "path": "/nope"}"#);
+    }
+
+    #[test]
+    fn wrong_methods_are_not_allowed() {
+        let device = FakeDevice::default();
+        assert_eq!(status_code(&get(&device, "DELETE /servo/90 HTTP/1.1\r\n\r\n")), 405);
+        assert_eq!(status_code(&get(&device, "POST /status HTTP/1.1\r\n\r\n")), 405);
+        assert!(device.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn angles_outside_the_limits_are_clamped() {
+        let device = FakeDevice::default();
+        let response = get(&device, "GET /servo/175 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 200);
+        assert_eq!(request_body(&response), r#"{"servo": 0, "angle": 170}"#);
+        let response = get(&device, "GET /servo/by/-100 HTTP/1.1\r\n\r\n");
+        assert_eq!(request_body(&response), r#"{"servo": 0, "angle": 10}"#);
+        let sent: Vec<_> = device.sent.borrow().iter().map(|command| command.action).collect();
+        assert_eq!(sent, [ServoAction::Set(170), ServoAction::Set(10)]);
+    }
+
+    #[test]
+    fn strict_limits_refuse_angles_outside_the_range() {
+        let device = FakeDevice { strict: true, ..FakeDevice::default() };
+        let response = get(&device, "GET /servo/175 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 400);
+        assert_eq!(
+            request_body(&response),
+            r#"{"error": "Angle outside the allowed range 10-170", "min": 10, "max": 170}"#
+        );
+        let post = "POST /servo HTTP/1.1\r\nContent-Length: 12\r\n\r\n{\"angle\": 5}";
+        assert_eq!(status_code(&get(&device, post)), 400);
+        assert!(device.sent.borrow().is_empty());
+        // In-range moves, and relative ones saturating at the range, still pass
+        assert_eq!(status_code(&get(&device, "GET /servo/170 HTTP/1.1\r\n\r\n")), 200);
+        assert_eq!(status_code(&get(&device, "GET /servo/by/-200 HTTP/1.1\r\n\r\n")), 200);
+        let sent: Vec<_> = device.sent.borrow().iter().map(|command| command.action).collect();
+        assert_eq!(sent, [ServoAction::Set(170), ServoAction::Set(10)]);
+    }
+
+    #[test]
+    fn angles_beyond_180_are_refused() {
+        let device = FakeDevice::default();
+        assert_eq!(status_code(&get(&device, "GET /servo/300 HTTP/1.1\r\n\r\n")), 400);
+        let post = "POST /servo HTTP/1.1\r\nContent-Length: 14\r\n\r\n{\"angle\": 181}";
+        assert_eq!(status_code(&get(&device, post)), 400);
+        assert!(device.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn unknown_servos_are_not_found() {
+        let device = FakeDevice::default();
+        let response = get(&device, "GET /servo/2/90 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 404);
+        assert!(request_body(&response).contains("Valid indices are 0-1"));
+    }
+
+    #[test]
+    fn garbage_and_truncated_request_lines_are_bad_requests() {
+        let device = FakeDevice::default();
+        for raw in ["", "\r\n\r\n", "GET", "GET\r\n\r\n", "\u{1}\u{7f}\u{2}", "    \r\n"] {
+            let response = get(&device, raw);
+            assert_eq!(status_code(&response), 400, "{:?}", raw);
+            assert_eq!(request_body(&response), "Bad Request");
+        }
+        assert!(device.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn moves_wait_for_readiness() {
+        let device = FakeDevice { ready: Err(NotReady::Starting), ..FakeDevice::default() };
+        let response = get(&device, "GET /servo/90 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 503);
+        assert!(response.contains("Retry-After: 1\r\n"));
+        let device = FakeDevice { ready: Err(NotReady::Estop), ..FakeDevice::default() };
+        assert_eq!(status_code(&get(&device, "GET /servo/90 HTTP/1.1\r\n\r\n")), 423);
+        assert!(device.sent.borrow().is_empty());
+        let device = FakeDevice { ready: Err(NotReady::LowPower), ..FakeDevice::default() };
+        let response = get(&device, "GET /servo/90 HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&response), 503);
+        assert!(request_body(&response).contains(r#""reason": "low_power""#));
+        assert!(device.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn moves_need_the_token_when_one_is_set() {
+        let device = FakeDevice { token: Some("secret"), ..FakeDevice::default() };
+        assert_eq!(status_code(&get(&device, "GET /servo/90 HTTP/1.1\r\n\r\n")), 401);
+        let authorized = "GET /servo/90 HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n";
+        assert_eq!(status_code(&get(&device, authorized)), 200);
+        // Reads stay open
+        assert_eq!(status_code(&get(&device, "GET /servo/ HTTP/1.1\r\n\r\n")), 200);
+    }
+
+    #[test]
+    fn content_length_counts_bytes() {
+        let response = build_response("200 OK", "text/plain; charset=utf-8", "90°");
+        assert_eq!("90°".len(), 4);
+        assert_eq!(find_header(&response, "Content-Length"), Some("4"));
+        assert_eq!(request_body(&response), "90°");
+        let response = build_response("200 OK", "text/plain; charset=utf-8", "°");
+        assert_eq!(find_header(&response, "Content-Length"), Some("2"));
+    }
+
+    #[test]
+    fn headers_come_in_a_fixed_order() {
+        let response = with_connection_header(build_response("200 OK", "application/json", "{}"), false);
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\
+             Access-Control-Allow-Origin: *\r\n\r\n{}"
+        );
+        let response = build_response_with_headers("200 OK", "text/html", "Accept-Ranges: bytes\r\n", "");
+        let response = with_connection_header(response, true);
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nKeep-Alive: timeout=2, max=16\r\nContent-Type: text/html\r\n\
+             Content-Length: 0\r\nAccess-Control-Allow-Origin: *\r\nAccept-Ranges: bytes\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn unterminated_headers_are_too_large() {
+        let mut request = "GET /status HTTP/1.1\r\nCookie: ".to_string();
+        while request.len() < RX_BUFFER_SIZE + 1 {
+            request.push('a');
+        }
+        let response = oversize_response(&request).unwrap();
+        assert_eq!(status_code(&response), 431);
+        assert!(oversize_response("GET /status HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn truncated_bodies_are_too_large() {
+        let request = "POST /servo HTTP/1.1\r\nContent-Length: 4096\r\n\r\nangle=90&";
+        assert_eq!(status_code(&oversize_response(request).unwrap()), 413);
+        let request = "POST /servo HTTP/1.1\r\nContent-Length: 8\r\n\r\nangle=90";
+        assert!(oversize_response(request).is_none());
+    }
+
+    #[test]
+    fn http_1_0_requests_with_bare_newlines_close() {
+        let request = "GET /health HTTP/1.0\n\n";
+        assert_eq!(parse_request(request), Some(("GET", "/health")));
+        assert!(!wants_keep_alive(request));
+        assert_eq!(status_code(&get(&FakeDevice::default(), request)), 200);
+        assert!(wants_keep_alive("GET /health HTTP/1.0\nConnection: keep-alive\n\n"));
+        assert!(wants_keep_alive("GET /health HTTP/1.1\n\n"));
+    }
+
+    #[test]
+    fn head_writes_the_get_headers_without_the_body() {
+        let device = FakeDevice::default();
+        let get_response = get(&device, "GET /status HTTP/1.1\r\n\r\n");
+        let head_response = get(&device, "HEAD /status HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code(&head_response), 200);
+        assert!(head_response.ends_with("\r\n\r\n"));
+        assert_eq!(request_body(&head_response), "");
+        let get_length = request_body(&get_response).len();
+        assert!(get_length > 0);
+        assert_eq!(find_header(&head_response, "Content-Length"), Some(get_length.to_string().as_str()));
+        assert_eq!(head_response, get_response[..get_response.len() - get_length]);
+    }
+
+    #[test]
+    fn head_never_moves_a_servo() {
+        let device = FakeDevice::default();
+        assert_eq!(status_code(&get(&device, "HEAD /servo/90 HTTP/1.1\r\n\r\n")), 405);
+        assert!(device.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn earlier_tables_win() {
+        static FIRST: &[Route] =
+            &[Route { method: None, path: PathMatch::Prefix("/servo/preset/"), auth: false, handler: |_| json_ok("{}") }];
+        let device = FakeDevice::default();
+        let response = handle_request("GET /servo/preset/home HTTP/1.1\r\n\r\n", &device, &[FIRST, SERVO_ROUTES]);
+        assert_eq!(request_body(&response), "{}");
+        assert!(device.sent.borrow().is_empty());
+    }
+}
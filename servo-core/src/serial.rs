@@ -0,0 +1,304 @@
+/// A parsed serial command line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialCommand {
+    /// Move a servo to an angle
+    Angle { servo: u8, angle: u8 },
+    /// Move servo 0 by this many degrees from the angle it is heading to
+    Relative(i16),
+    /// Move servo 0 to the middle of its travel
+    Center,
+    /// Move servo 0 to an angle for `hold_ms`, then back
+    Pulse(u8, u32),
+    /// Sweep servo 0 from one angle to another at `step_ms` per degree
+    Sweep { from: u8, to: u8, step_ms: u16 },
+    /// Read-only query, answered with one JSON line
+    Get(Query),
+    /// Switch the style of confirmations and errors
+    Format(OutputFormat),
+    /// Nudge servo 0's trim by this many degrees
+    TrimNudge(i8),
+    /// Persist the current trim
+    TrimSave,
+    /// Let the potentiometer (if fitted) move servo 0, or ignore it
+    Pot(bool),
+    /// Set servo 0's pulse width for 0° (`min`) or 180° (`max`)
+    Calibrate(PulseEnd, u16),
+    /// Print servo 0's pulse range and PWM frequency
+    CalibrationShow,
+    /// Erase stored config and reboot; carries whether it was confirmed
+    FactoryReset(bool),
+    /// List the commands and show the current angle
+    Help,
+}
+
+/// Output style for confirmations, errors and status lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Readable sentences, with typed characters echoed back (default)
+    Human,
+    /// One JSON object per line and no echo, for host software
+    Json,
+}
+
+/// Which end of the pulse range a `cal` command sets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PulseEnd {
+    Min,
+    Max,
+}
+
+/// Widest pulse `cal` accepts in microseconds; the servo also clamps to its
+/// PWM period
+pub const MAX_CALIBRATION_PULSE_US: u16 = 3000;
+
+/// Read operations mirroring the HTTP API
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Query {
+    /// `get angle` - same as `GET /servo/`
+    Angle,
+    /// `get status` - same as `GET /status`
+    Status,
+    /// `get net` or `status` - network details
+    Net,
+}
+
+/// Commands that are a single fixed word; add new ones here
+pub const KEYWORDS: &[(&str, SerialCommand)] = &[
+    ("help", SerialCommand::Help),
+    ("?", SerialCommand::Help),
+    ("status", SerialCommand::Get(Query::Net)),
+    ("center", SerialCommand::Center),
+];
+
+/// What [`parse_servo_command`] accepts, from the firmware's configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Servo indexes run `0..servos`
+    pub servos: usize,
+    /// Longest `pulse` hold
+    pub max_hold_ms: u32,
+    /// Slowest `sweep` pace per degree
+    pub max_step_ms: u16,
+    /// Word that must follow `factory-reset`
+    pub reset_confirmation: &'static str,
+}
+
+/// Parse a servo command from input
+/// Accepts formats like: "90", "servo 90", "angle 90", "s90", "a90", "s1 90", "+10", "-10",
+/// "pulse <angle> <hold_ms>", "sweep <from> <to> <step_ms>", "trim +1|-1|save", "format json|human", the queries "get angle",
+/// "get status", "get net", and the [`KEYWORDS`]
+pub fn parse_servo_command(input: &str, limits: &Limits) -> Option<SerialCommand> {
+    let input = input.trim();
+
+    if let Some(&(_, command)) = KEYWORDS.iter().find(|(word, _)| *word == input) {
+        return Some(command);
+    }
+
+    // Try "pulse <angle> <hold_ms>"
+    if let Some(args) = input.strip_prefix("pulse ") {
+        let (angle, hold_ms) = args.trim().split_once(' ')?;
+        let angle = angle.parse::<u8>().ok().filter(|a| *a <= 180)?;
+        let hold_ms = hold_ms.trim().parse::<u32>().ok().filter(|ms| (1..=limits.max_hold_ms).contains(ms))?;
+        return Some(SerialCommand::Pulse(angle, hold_ms));
+    }
+
+    // Try "sweep <from> <to> <step_ms>"
+    if let Some(args) = input.strip_prefix("sweep ") {
+        let mut args = args.split_whitespace().map(|arg| arg.parse::<u16>().ok());
+        let (Some(Some(from)), Some(Some(to)), Some(Some(step_ms)), None) = (args.next(), args.next(), args.next(), args.next()) else {
+            return None;
+        };
+        if from > 180 || to > 180 || !(1..=limits.max_step_ms).contains(&step_ms) {
+            return None;
+        }
+        return Some(SerialCommand::Sweep { from: from as u8, to: to as u8, step_ms });
+    }
+
+    // Try "+10" / "-10"; checked before plain numbers, which accept a '+'
+    if input.starts_with(['+', '-']) {
+        let delta = input.strip_prefix('+').unwrap_or(input);
+        return delta.parse::<i16>().ok().filter(|d| d.abs() <= 180).map(SerialCommand::Relative);
+    }
+
+    // Try "trim +1" / "trim -1" / "trim save"
+    if let Some(arg) = input.strip_prefix("trim ") {
+        return match arg.trim() {
+            "save" => Some(SerialCommand::TrimSave),
+            delta => {
+                let delta = delta.strip_prefix('+').unwrap_or(delta);
+                delta.parse::<i8>().ok().map(SerialCommand::TrimNudge)
+            }
+        };
+    }
+
+    // Try "cal min <us>" / "cal max <us>" / "cal show"
+    if let Some(args) = input.strip_prefix("cal ") {
+        let args = args.trim();
+        if args == "show" {
+            return Some(SerialCommand::CalibrationShow);
+        }
+        let (end, pulse_us) = args.split_once(' ')?;
+        let end = match end {
+            "min" => PulseEnd::Min,
+            "max" => PulseEnd::Max,
+            _ => return None,
+        };
+        let pulse_us = pulse_us.trim().parse::<u16>().ok().filter(|us| (1..=MAX_CALIBRATION_PULSE_US).contains(us))?;
+        return Some(SerialCommand::Calibrate(end, pulse_us));
+    }
+
+    // Try "factory-reset <confirmation>"
+    if let Some(rest) = input.strip_prefix("factory-reset") {
+        return Some(SerialCommand::FactoryReset(rest.trim() == limits.reset_confirmation));
+    }
+
+    // Try "pot on" / "pot off"
+    if let Some(arg) = input.strip_prefix("pot ") {
+        return match arg.trim() {
+            "on" => Some(SerialCommand::Pot(true)),
+            "off" => Some(SerialCommand::Pot(false)),
+            _ => None,
+        };
+    }
+
+    // Try "format json" / "format human"
+    if let Some(format) = input.strip_prefix("format ") {
+        return match format.trim() {
+            "json" => Some(SerialCommand::Format(OutputFormat::Json)),
+            "human" => Some(SerialCommand::Format(OutputFormat::Human)),
+            _ => None,
+        };
+    }
+
+    // Try read queries
+    if let Some(what) = input.strip_prefix("get ") {
+        return match what.trim() {
+            "angle" => Some(SerialCommand::Get(Query::Angle)),
+            "status" => Some(SerialCommand::Get(Query::Status)),
+            "net" => Some(SerialCommand::Get(Query::Net)),
+            _ => None,
+        };
+    }
+
+    // Try direct number
+    if let Ok(angle) = input.parse::<u8>()
+        && angle <= 180
+    {
+        return Some(SerialCommand::Angle { servo: 0, angle });
+    }
+
+    // Try "servo X" or "s X" or "sX", and "servo N X" or "sN X" for servo N
+    for prefix in ["servo ", "angle ", "s ", "a ", "s", "a"] {
+        if let Some(rest) = input.strip_prefix(prefix) {
+            let (servo, angle) = match rest.trim().split_once(' ') {
+                Some((servo, angle)) => (servo.parse::<u8>().ok(), angle.trim()),
+                None => (Some(0), rest.trim()),
+            };
+            let servo = servo.filter(|s| (*s as usize) < limits.servos);
+            if let (Some(servo), Ok(angle)) = (servo, angle.parse::<u8>())
+                && angle <= 180
+            {
+                return Some(SerialCommand::Angle { servo, angle });
+            }
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits { servos: 2, max_hold_ms: 10_000, max_step_ms: 1_000, reset_confirmation: "erase-everything" };
+
+    fn parse(input: &str) -> Option<SerialCommand> {
+        parse_servo_command(input, &LIMITS)
+    }
+
+    #[test]
+    fn parses_angles() {
+        for input in ["90", " 90 ", "servo 90", "angle 90", "s 90", "a 90", "s90", "a90"] {
+            assert_eq!(parse(input), Some(SerialCommand::Angle { servo: 0, angle: 90 }), "{input:?}");
+        }
+        assert_eq!(parse("0"), Some(SerialCommand::Angle { servo: 0, angle: 0 }));
+        assert_eq!(parse("180"), Some(SerialCommand::Angle { servo: 0, angle: 180 }));
+        assert_eq!(parse("181"), None);
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn parses_servo_indexes() {
+        assert_eq!(parse("servo 1 45"), Some(SerialCommand::Angle { servo: 1, angle: 45 }));
+        assert_eq!(parse("s1 45"), Some(SerialCommand::Angle { servo: 1, angle: 45 }));
+        assert_eq!(parse("servo 2 45"), None);
+        assert_eq!(parse("servo 1 181"), None);
+    }
+
+    #[test]
+    fn parses_relative_moves() {
+        assert_eq!(parse("+10"), Some(SerialCommand::Relative(10)));
+        assert_eq!(parse("-10"), Some(SerialCommand::Relative(-10)));
+        assert_eq!(parse("-180"), Some(SerialCommand::Relative(-180)));
+        assert_eq!(parse("-181"), None);
+        assert_eq!(parse("+x"), None);
+    }
+
+    #[test]
+    fn parses_pulses_within_the_hold_limit() {
+        assert_eq!(parse("pulse 45 500"), Some(SerialCommand::Pulse(45, 500)));
+        assert_eq!(parse("pulse 45 10000"), Some(SerialCommand::Pulse(45, 10_000)));
+        assert_eq!(parse("pulse 45 10001"), None);
+        assert_eq!(parse("pulse 45 0"), None);
+        assert_eq!(parse("pulse 181 500"), None);
+        assert_eq!(parse("pulse 45"), None);
+    }
+
+    #[test]
+    fn parses_sweeps_within_the_step_limit() {
+        assert_eq!(parse("sweep 0 180 10"), Some(SerialCommand::Sweep { from: 0, to: 180, step_ms: 10 }));
+        assert_eq!(parse("sweep  180  0  1000"), Some(SerialCommand::Sweep { from: 180, to: 0, step_ms: 1_000 }));
+        assert_eq!(parse("sweep 0 180 1001"), None);
+        assert_eq!(parse("sweep 0 181 10"), None);
+        assert_eq!(parse("sweep 0 180"), None);
+        assert_eq!(parse("sweep 0 180 10 5"), None);
+    }
+
+    #[test]
+    fn parses_trim_and_calibration() {
+        assert_eq!(parse("trim +1"), Some(SerialCommand::TrimNudge(1)));
+        assert_eq!(parse("trim -2"), Some(SerialCommand::TrimNudge(-2)));
+        assert_eq!(parse("trim save"), Some(SerialCommand::TrimSave));
+        assert_eq!(parse("trim x"), None);
+        assert_eq!(parse("cal min 600"), Some(SerialCommand::Calibrate(PulseEnd::Min, 600)));
+        assert_eq!(parse("cal max 3000"), Some(SerialCommand::Calibrate(PulseEnd::Max, 3000)));
+        assert_eq!(parse("cal max 3001"), None);
+        assert_eq!(parse("cal mid 1500"), None);
+        assert_eq!(parse("cal show"), Some(SerialCommand::CalibrationShow));
+    }
+
+    #[test]
+    fn factory_reset_needs_the_confirmation() {
+        assert_eq!(parse("factory-reset erase-everything"), Some(SerialCommand::FactoryReset(true)));
+        assert_eq!(parse("factory-reset"), Some(SerialCommand::FactoryReset(false)));
+        assert_eq!(parse("factory-reset yes"), Some(SerialCommand::FactoryReset(false)));
+    }
+
+    #[test]
+    fn parses_keywords_and_settings() {
+        assert_eq!(parse("help"), Some(SerialCommand::Help));
+        assert_eq!(parse("?"), Some(SerialCommand::Help));
+        assert_eq!(parse("center"), Some(SerialCommand::Center));
+        assert_eq!(parse("status"), Some(SerialCommand::Get(Query::Net)));
+        assert_eq!(parse("get angle"), Some(SerialCommand::Get(Query::Angle)));
+        assert_eq!(parse("get status"), Some(SerialCommand::Get(Query::Status)));
+        assert_eq!(parse("get time"), None);
+        assert_eq!(parse("pot on"), Some(SerialCommand::Pot(true)));
+        assert_eq!(parse("pot off"), Some(SerialCommand::Pot(false)));
+        assert_eq!(parse("format json"), Some(SerialCommand::Format(OutputFormat::Json)));
+        assert_eq!(parse("format human"), Some(SerialCommand::Format(OutputFormat::Human)));
+        assert_eq!(parse("format xml"), None);
+    }
+}
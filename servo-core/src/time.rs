@@ -0,0 +1,58 @@
+use alloc::format;
+use alloc::string::String;
+
+/// Format Unix time as an ISO 8601 UTC timestamp, e.g. `2024-05-01T12:00:00Z`
+pub fn iso8601(unix_s: u32) -> String {
+    let days = (unix_s / 86_400) as i64;
+    let secs = unix_s % 86_400;
+    // Days to civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_the_epoch() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn formats_leap_days() {
+        // 2024-02-29T23:59:59Z
+        assert_eq!(iso8601(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(iso8601(1_709_251_200), "2024-03-01T00:00:00Z");
+        // 2000 is a leap year, 2100 isn't
+        assert_eq!(iso8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601(4_107_542_400), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn formats_year_ends() {
+        assert_eq!(iso8601(1_735_689_599), "2024-12-31T23:59:59Z");
+        assert_eq!(iso8601(1_735_689_600), "2025-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn formats_the_last_u32_second() {
+        assert_eq!(iso8601(u32::MAX), "2106-02-07T06:28:15Z");
+    }
+}
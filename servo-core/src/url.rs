@@ -0,0 +1,155 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Decode `%XX` escapes in a request path (without its query; `+` is kept)
+///
+/// Malformed escapes (`%4`, `%zz`) are kept as they are, and a result that
+/// isn't valid UTF-8 falls back to the raw path.
+pub fn percent_decode(path: &str) -> String {
+    decode(path, false)
+}
+
+/// Decode one key or value of a form body or query string: `%XX` escapes,
+/// and `+` as a space
+///
+/// Split the pairs apart first, so an escaped `&` or `=` stays part of the
+/// text. Malformed escapes are kept as in [`percent_decode`].
+pub fn decode_component(text: &str) -> String {
+    decode(text, true)
+}
+
+/// Decoded value of the first pair whose decoded name is `key` in a form body
+/// or query string
+///
+/// The raw text is split into pairs before anything is decoded, so values
+/// may contain `%26` (`&`) and `%3D` (`=`). A bare `key` has an empty value.
+pub fn form_param(form: &str, key: &str) -> Option<String> {
+    form.split('&').find_map(|pair| {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (decode_component(name) == key).then(|| decode_component(value))
+    })
+}
+
+fn decode(text: &str, plus_is_space: bool) -> String {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match (bytes.get(i + 1).and_then(|&b| hex(b)), bytes.get(i + 2).and_then(|&b| hex(b))) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 3;
+                    continue;
+                }
+                _ => out.push(b'%'),
+            },
+            b'+' if plus_is_space => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| String::from(text))
+}
+
+/// Parts of an `http://` URL
+#[derive(Debug, PartialEq, Eq)]
+pub struct Url<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+/// Split an `http://host[:port][/path]` URL into its parts
+pub fn parse_url(url: &str) -> Option<Url<'_>> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some(Url { host, port, path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("/servo%2F90"), "/servo/90");
+        assert_eq!(percent_decode("%39%30"), "90");
+        assert_eq!(percent_decode("/presets/caf%C3%A9"), "/presets/café");
+    }
+
+    #[test]
+    fn plus_is_a_space_only_in_query_components() {
+        assert_eq!(percent_decode("/a+b"), "/a+b");
+        assert_eq!(decode_component("a+b"), "a b");
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        assert_eq!(percent_decode("/%4"), "/%4");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+        assert_eq!(percent_decode("/100%"), "/100%");
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_the_raw_path() {
+        assert_eq!(percent_decode("/%FF"), "/%FF");
+    }
+
+    #[test]
+    fn decodes_form_pairs_after_splitting() {
+        let form = "ssid=Caf%C3%A9+Wifi&password=a%26b%3Dc&user%20name=x+y";
+        assert_eq!(form_param(form, "ssid").as_deref(), Some("Café Wifi"));
+        assert_eq!(form_param(form, "password").as_deref(), Some("a&b=c"));
+        assert_eq!(form_param(form, "user name").as_deref(), Some("x y"));
+        assert_eq!(form_param(form, "b"), None);
+        assert_eq!(form_param("flag&x=1", "flag").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn decodes_components() {
+        assert_eq!(decode_component("a+b%2Bc"), "a b+c");
+        assert_eq!(decode_component("100%"), "100%");
+        assert_eq!(decode_component("%FF"), "%FF");
+    }
+
+    #[test]
+    fn finds_query_params() {
+        let query = "servo=1&angle=90&flag";
+        assert_eq!(form_param(query, "servo").as_deref(), Some("1"));
+        assert_eq!(form_param(query, "angle").as_deref(), Some("90"));
+        assert_eq!(form_param(query, "flag").as_deref(), Some(""));
+        assert_eq!(form_param(query, "missing"), None);
+        assert_eq!(form_param("angle=1&angle=2", "angle").as_deref(), Some("1"));
+        assert_eq!(form_param("", "angle"), None);
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(parse_url("http://example.com"), Some(Url { host: "example.com", port: 80, path: "/" }));
+        assert_eq!(
+            parse_url("http://10.0.0.2:8080/hook?x=1"),
+            Some(Url { host: "10.0.0.2", port: 8080, path: "/hook?x=1" })
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        assert_eq!(parse_url("https://example.com/"), None);
+        assert_eq!(parse_url("http:///path"), None);
+        assert_eq!(parse_url("http://host:port/"), None);
+        assert_eq!(parse_url("http://host:70000/"), None);
+        assert_eq!(parse_url("example.com"), None);
+    }
+}
@@ -0,0 +1,61 @@
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::{Level, Output};
+
+use crate::state;
+
+/// How long the LED stays lit (or, while it is on, goes dark) for each
+/// applied command
+pub const ACTIVITY_LED_PULSE_MS: u64 = 50;
+
+/// Also show the WiFi state: a slow blink while connecting, solid once
+/// connected. With `false` the LED only flashes on commands.
+pub const LED_SHOWS_CONNECTION: bool = true;
+
+/// Half-period of the blink while WiFi is connecting
+const CONNECTING_BLINK_MS: u64 = 500;
+
+/// How often a solid LED re-checks the WiFi state
+const CONNECTED_POLL_MS: u64 = 1_000;
+
+/// Raised by the main loop whenever a command is applied
+static ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Request a flash; flashes requested while one is in progress merge into it
+pub fn flash() {
+    ACTIVITY.signal(());
+}
+
+/// Drive the status LED: connection state (see [`LED_SHOWS_CONNECTION`]),
+/// with a quick flash per applied command
+#[embassy_executor::task]
+pub async fn activity_led_task(mut led: Output<'static>) {
+    let mut lit = false;
+    loop {
+        if !LED_SHOWS_CONNECTION {
+            ACTIVITY.wait().await;
+            pulse(&mut led).await;
+            continue;
+        }
+        let wait_ms = if state::wifi_connected() {
+            lit = true;
+            CONNECTED_POLL_MS
+        } else {
+            lit = !lit;
+            CONNECTING_BLINK_MS
+        };
+        led.set_level(Level::from(lit));
+        if let Either::Second(()) = select(Timer::after(Duration::from_millis(wait_ms)), ACTIVITY.wait()).await {
+            pulse(&mut led).await;
+        }
+    }
+}
+
+/// Invert the LED for [`ACTIVITY_LED_PULSE_MS`]
+async fn pulse(led: &mut Output<'static>) {
+    led.toggle();
+    Timer::after(Duration::from_millis(ACTIVITY_LED_PULSE_MS)).await;
+    led.toggle();
+}
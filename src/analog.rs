@@ -0,0 +1,91 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_time::{Duration, Timer};
+use esp_hal::analog::adc::{Adc, AdcPin};
+use esp_hal::peripherals::ADC1;
+use esp_hal::Blocking;
+
+use crate::command::{apply_limits, check_ready, send_command, CommandSource, ServoCommand};
+
+/// Wiper pin taken by [`pot_task`]; must be the pin `pot_pin!` in `main.rs`
+/// takes
+#[cfg(feature = "esp32")]
+pub type PotPin = esp_hal::peripherals::GPIO35<'static>;
+#[cfg(feature = "esp32c3")]
+pub type PotPin = esp_hal::peripherals::GPIO3<'static>;
+
+/// Full-scale raw reading (12-bit)
+const ADC_MAX: u32 = 4095;
+
+/// Weight of each new sample in the running average, as a shift: each
+/// sample moves the average 1/2^n of the way (higher = smoother, slower)
+const SMOOTHING_SHIFT: u32 = 2;
+
+/// Degrees the knob must move from the last sent angle before a new one is
+/// sent, so ADC noise doesn't make the servo jitter
+const DEADBAND_DEG: u8 = 2;
+
+/// Whether the knob controls the servo; toggled with the serial `pot` command
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the knob controls the servo
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Map a potentiometer on [`PotPin`] to servo 0's angle, sampling every
+/// `sample_ms`
+///
+/// Only turning the knob sends a command: the position it rests at is taken
+/// as the baseline, so HTTP and serial moves stand until someone actually
+/// turns it. Readings are averaged and need to move [`DEADBAND_DEG`] before
+/// a new angle is queued, as [`CommandSource::Pot`].
+#[embassy_executor::task]
+pub async fn pot_task(
+    mut adc: Adc<'static, ADC1<'static>, Blocking>,
+    mut pin: AdcPin<PotPin, ADC1<'static>>,
+    sample_ms: u64,
+) {
+    log::info!("Pot: controlling servo 0, sampled every {} ms", sample_ms);
+    // Running average, scaled up by the smoothing shift to keep precision
+    let mut average: Option<u32> = None;
+    let mut last_sent: Option<u8> = None;
+
+    loop {
+        Timer::after(Duration::from_millis(sample_ms)).await;
+
+        let raw = loop {
+            match adc.read_oneshot(&mut pin) {
+                Ok(raw) => break raw as u32,
+                Err(_) => Timer::after(Duration::from_micros(50)).await,
+            }
+        };
+        let scaled = match average {
+            Some(average) => average - (average >> SMOOTHING_SHIFT) + raw,
+            None => raw << SMOOTHING_SHIFT,
+        };
+        average = Some(scaled);
+        let angle = ((scaled >> SMOOTHING_SHIFT).min(ADC_MAX) * 180 / ADC_MAX) as u8;
+
+        // Take the resting position as the baseline while disabled, so
+        // re-enabling doesn't jump the servo to the knob
+        let Some(sent) = last_sent.filter(|_| is_enabled()) else {
+            last_sent = Some(angle);
+            continue;
+        };
+        if angle.abs_diff(sent) < DEADBAND_DEG {
+            continue;
+        }
+        last_sent = Some(angle);
+        if check_ready().is_err() {
+            continue;
+        }
+        if let Ok(angle) = apply_limits(0, angle) {
+            send_command(ServoCommand::set(0, angle, CommandSource::Pot));
+        }
+    }
+}
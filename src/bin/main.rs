@@ -1,25 +1,44 @@
 #![no_std]
 #![no_main]
+// Settings are plain constants, so a check against one that is at its
+// default (`0`, ...) is expected to be constant
+#![allow(clippy::absurd_extreme_comparisons)]
 
 extern crate alloc;
 
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
 use embassy_executor::Spawner;
-use embassy_net::{Runner, StackResources};
-use embassy_time::{Duration, Timer};
 use embassy_futures::select::{select, Either};
+use embassy_net::{Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_time::{with_deadline, Duration, Instant, Timer};
 use esp_alloc as _;
 use esp_backtrace as _;
 use esp_hal::{
+    analog::adc::{Adc, AdcConfig, Attenuation},
     clock::CpuClock,
+    gpio::{Input, InputConfig, Level, Output, OutputConfig, Pin, Pull},
     ledc::Ledc,
     rng::Rng,
+    rtc_cntl::Rtc,
     timer::timg::TimerGroup,
-    uart::{Uart, Config as UartConfig},
 };
+#[cfg(feature = "esp32")]
+use esp_hal::ledc::HighSpeed;
+#[cfg(feature = "esp32c3")]
+use esp_hal::ledc::LowSpeed;
+#[cfg(feature = "esp32c3")]
+use esp_hal::interrupt::software::SoftwareInterruptControl;
+#[cfg(feature = "serial")]
+use esp_hal::uart::Uart;
 use esp_println::println;
+use esp_storage::FlashStorage;
 use esp_radio::wifi::{
+    AccessPointConfig,
     ClientConfig,
     ModeConfig,
+    ScanConfig,
     WifiController,
     WifiDevice,
     WifiEvent,
@@ -27,15 +46,310 @@ use esp_radio::wifi::{
     sta_state,
 };
 use static_cell::StaticCell;
-use esp32_http_servo::http_server::{http_server_task, SERVO_ANGLE};
-use esp32_http_servo::serial_cmd::{serial_input_task, SERIAL_SERVO_ANGLE};
-use esp32_http_servo::servo::{ServoController, init_servo_timer};
+use esp32_http_servo::activity_led::{self, activity_led_task};
+use esp32_http_servo::analog::pot_task;
+use esp32_http_servo::buttons::button_task;
+use esp32_http_servo::command::{
+    queue_during_estop, CommandSource, EstopPolicy, PriorityArbiter, ReverseDwell, ServoAction, ServoCommand,
+    ESTOP_POLICY, MAX_SERVOS, PUBLIC_MAX_ANGLE, PUBLIC_MIN_ANGLE, REVERSE_DWELL_MS, SERVO_COMMAND,
+    SERVO_COMMAND_DEPTH, STRICT_LIMITS,
+};
+use esp32_http_servo::current_monitor::current_monitor_task;
+use esp32_http_servo::diag::{self, ResetReason};
+use esp32_http_servo::http_server::{self, http_server_task, AP_HTTP_WORKERS, HTTP_WORKERS};
+use esp32_http_servo::liveness::{self, LIVENESS_TIMEOUT_MS, WATCHDOG_TIMEOUT_S};
+use esp32_http_servo::mdns;
+use esp32_http_servo::sntp;
+use esp32_http_servo::move_log;
+use esp32_http_servo::nvs;
+use esp32_http_servo::ota;
+use esp32_http_servo::park;
+use esp32_http_servo::power::{self, IDLE_SLEEP_AFTER_MS};
+#[cfg(feature = "serial")]
+use esp32_http_servo::serial_cmd::{self, serial_input_task};
+use esp32_http_servo::servo::{actuator, AngleActuator, ServoController, ServoTimer, MAX_FREQ_HZ, SERVO_FREQ_HZ};
+#[cfg(feature = "esp32")]
+use esp32_http_servo::servo::init_servo_timer;
+#[cfg(feature = "esp32c3")]
+use esp32_http_servo::servo::{init_low_speed_clock, init_servo_timer_low};
+use esp32_http_servo::state::{self, NotReady};
+use esp32_http_servo::telemetry;
+use esp32_http_servo::webhook;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 esp_bootloader_esp_idf::esp_app_desc!();
 
 const SSID: &str = env!("WIFI_SSID");
 const PASSWORD: &str = env!("WIFI_PASSWORD");
+// Fail the build on credentials the WiFi driver would reject at boot
+const _: () = assert!(nvs::check_wifi_credentials(SSID, PASSWORD).is_ok(), "WIFI_SSID must be 1-32 bytes and WIFI_PASSWORD empty or 8-64 bytes");
+
+/// More networks to try after `SSID`, from cfg.toml (`wifi_networks`, as
+/// `ssid:password` pairs separated by `;`)
+const WIFI_NETWORKS: Option<&str> = option_env!("WIFI_NETWORKS");
+
+/// Static IPv4 setup from cfg.toml (`static_ip` as `a.b.c.d/prefix`, with
+/// optional `static_gateway` and `static_dns`); DHCP when `static_ip` is unset
+const STATIC_IP: Option<&str> = option_env!("STATIC_IP");
+const STATIC_GATEWAY: Option<&str> = option_env!("STATIC_GATEWAY");
+const STATIC_DNS: Option<&str> = option_env!("STATIC_DNS");
+
+/// Wait before reconnect attempts: starts at the initial delay after a
+/// disconnect or failed attempt and doubles with every further failure, up
+/// to the max
+const WIFI_RETRY_INITIAL_MS: u64 = 1_000;
+const WIFI_RETRY_MAX_MS: u64 = 60_000;
+
+/// Failed station connects before the fallback access point opens
+/// (0 = never open it)
+const AP_FALLBACK_AFTER_ATTEMPTS: u32 = 5;
+
+/// Open network the fallback access point broadcasts, and the device's
+/// address on it (`/24`)
+const AP_SSID: &str = "esp-servo-setup";
+const AP_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+
+/// Angle to start at after a brown-out reset
+///
+/// A servo stalled against a load can pull the supply down and brown out the
+/// board; starting somewhere that doesn't fight the load avoids a reset loop.
+const BROWNOUT_SAFE_ANGLE: u8 = 90;
+
+/// Delay after re-attaching a detached servo before applying the next move,
+/// giving the PWM output time to stabilize
+const ATTACH_SETTLE_MS: u64 = 20;
+
+/// Servo resolution in microseconds of pulse width (0 = no quantization)
+///
+/// Set this to the servo's dead band width from its datasheet, e.g. 10 for an SG90.
+const SERVO_PULSE_STEP_US: u32 = 0;
+
+/// Monitor servo supply current on GPIO34 (ADC1; GPIO4 on the ESP32-C3) and
+/// detach on sustained overload
+///
+/// Requires a current-sense resistor in the servo ground lead, see `current_monitor.rs`.
+const CURRENT_MONITOR_ENABLED: bool = false;
+
+/// Flash an LED on GPIO2 (the on-board LED of most dev kits; GPIO8 on the
+/// ESP32-C3) for every applied command and show the WiFi state; change the
+/// pin in [`led_pin!`]
+///
+/// The flash length and whether the WiFi state is shown are
+/// `ACTIVITY_LED_PULSE_MS` and `LED_SHOWS_CONNECTION` in `activity_led.rs`.
+const ACTIVITY_LED_ENABLED: bool = false;
+
+/// Jog servo 0 with two push buttons to ground: GPIO32 moves it down and
+/// GPIO33 up (GPIO9 and GPIO10 on the ESP32-C3), by `BUTTON_STEP_DEG` per
+/// press; change the pins in [`button_pins!`]
+const BUTTONS_ENABLED: bool = false;
+const BUTTON_STEP_DEG: u8 = 5;
+
+/// Position servo 0 with a potentiometer wiper on GPIO35 (GPIO3 on the
+/// ESP32-C3; ends to 3.3V and GND), sampled every `POT_SAMPLE_MS`; change the
+/// pin in [`pot_pin!`]
+///
+/// Shares ADC1 with the current monitor, so only one of the two can be on.
+const POT_ENABLED: bool = false;
+const POT_SAMPLE_MS: u64 = 50;
+const _: () = assert!(!(POT_ENABLED && CURRENT_MONITOR_ENABLED), "POT_ENABLED and CURRENT_MONITOR_ENABLED both need ADC1");
+
+/// Angle every servo is moved to once WiFi has been lost for
+/// `PARK_GRACE_MS`, so a mechanism isn't left at an awkward position
+/// (`None` = stay put)
+const PARK_ANGLE: Option<u8> = None;
+const PARK_GRACE_MS: u64 = 30_000;
+
+/// Start each servo at the angle it was last moved to instead of 90°
+///
+/// Moves mark the config dirty; the NVS writer coalesces them and writes at
+/// most once per `NVS_MIN_WRITE_INTERVAL_MS`, so rapid updates don't wear the
+/// flash. A move in the last few seconds before power loss may not be saved.
+const RESTORE_LAST_ANGLE: bool = true;
+
+/// Mirror each servo's direction (see `ServoController::set_inverted`), e.g.
+/// for a tilt servo mounted mirror-image to the pan servo
+const SERVO_INVERTED: [bool; MAX_SERVOS] = [false, false];
+
+/// Time allowed for the servo to reach its initial angle from anywhere in its
+/// range (an SG90 turns 60° in about 0.1 s, so 180° takes roughly 0.3 s)
+const HOMING_SETTLE_MS: u64 = 500;
+
+/// Start the HTTP server only once homing has finished, so early clients can't
+/// command a servo whose position is still being established
+const HTTP_AFTER_HOMING: bool = true;
+
+/// PWM frequency of the servo timer: 50 Hz for analog servos; digital ones
+/// take up to their datasheet maximum (often 300-333 Hz) for a quicker,
+/// stiffer response. The pulse widths stay the same; only the period shrinks.
+const SERVO_PWM_FREQ_HZ: u32 = SERVO_FREQ_HZ;
+const _: () = assert!(SERVO_PWM_FREQ_HZ > 0 && SERVO_PWM_FREQ_HZ <= MAX_FREQ_HZ, "SERVO_PWM_FREQ_HZ is beyond what 14-bit LEDC can drive");
+
+/// Interval between position updates while sweeping (one 50Hz PWM period)
+const SWEEP_STEP_MS: u32 = 20;
+
+/// How long the main loop waits after an angle command for more from the same
+/// servo and source, applying only the last (0 = only coalesce queued ones)
+///
+/// Smooths a dragged slider, which sends dozens of angles a second, while
+/// delaying a single command by at most this much.
+const COALESCE_WINDOW_MS: u64 = 20;
+
+/// Shortest time between two angle moves of one servo (0 = no limit)
+///
+/// A hard floor on actuation rate, protecting cheap servos from stalling or
+/// overheating on rapid large jumps. Moves arriving sooner are coalesced to
+/// the latest and applied once the interval has passed.
+const MIN_MOVE_INTERVAL_MS: u64 = 50;
+
+/// Attempts at `esp_radio::init` before restarting the device
+const RADIO_INIT_ATTEMPTS: u32 = 5;
+
+/// Delay before the first radio init retry; doubles on each further retry
+const RADIO_INIT_BACKOFF_MS: u64 = 200;
+
+/// Hardware limits for the servo (degrees, inclusive)
+///
+/// This is the calibration authority; the range exposed to HTTP/serial users
+/// is `PUBLIC_MIN_ANGLE`/`PUBLIC_MAX_ANGLE` in `command.rs`. Limits stored in
+/// the config blob (see `nvs.rs`) take precedence.
+const SAFE_MIN_ANGLE: u8 = 0;
+const SAFE_MAX_ANGLE: u8 = 180;
+
+/// How often the WiFi signal strength reported in `/status` is refreshed
+const RSSI_POLL_MS: u64 = 5_000;
+
+/// Signal strength below which a weak-signal warning is logged; it is
+/// logged again only after the signal recovers by `RSSI_WARN_HYSTERESIS_DB`
+const RSSI_WARN_DBM: i8 = -80;
+const RSSI_WARN_HYSTERESIS_DB: i8 = 5;
+
+/// Signal strength below which the board looks for a stronger access point
+/// with the same SSID, as in a mesh (0 = never roam)
+const ROAM_RSSI_DBM: i8 = -72;
+
+/// How much stronger another access point must be before switching to it,
+/// so two similar ones don't make the board flap between them
+const ROAM_HYSTERESIS_DB: i8 = 8;
+
+/// Shortest time between roaming scans; a scan briefly pauses traffic
+const ROAM_SCAN_INTERVAL_MS: u64 = 60_000;
+
+/// TCP port the HTTP server listens on
+const HTTP_PORT: u16 = 80;
+
+/// LEDC speed group the servos run in: HighSpeed where the chip has it, as on
+/// the ESP32, otherwise LowSpeed
+#[cfg(feature = "esp32")]
+type ServoSpeed = HighSpeed;
+#[cfg(feature = "esp32c3")]
+type ServoSpeed = LowSpeed;
+
+/// Heap for the allocator, in bytes
+///
+/// Holds response strings, the OTA sector buffer and other transient
+/// allocations; the HTTP buffers and network stack are static. Check the
+/// `Heap:` line at boot or `heap_used` in `/status` when resizing it.
+const HEAP_SIZE: usize = 72 * 1024;
+
+/// Signal pins of servo 0 (pan) and servo 1 (tilt): the one place to change
+/// to move a servo, e.g. `$p.GPIO5` instead of `$p.GPIO18`
+///
+/// Pins are peripherals taken by value, so a mistake fails the build instead
+/// of misbehaving: an input-only pin (GPIO34-39) can't carry PWM and has no
+/// output impl, and reusing a pin taken elsewhere (GPIO21 and GPIO22 under
+/// serial flow control, GPIO2 for the LED, ...) is a use of a moved value.
+/// Stay off GPIO6-11 (the flash) and preferably the strapping pins 0, 2, 12
+/// and 15. The pins in use are listed in the boot summary.
+///
+/// On the ESP32-C3 the servos default to GPIO6 and GPIO7: GPIO18 and GPIO19
+/// are its USB pins, and GPIO12-17 its flash.
+#[cfg(feature = "esp32")]
+macro_rules! servo_pins {
+    ($p:ident) => {
+        ($p.GPIO18, $p.GPIO19)
+    };
+}
+#[cfg(feature = "esp32c3")]
+macro_rules! servo_pins {
+    ($p:ident) => {
+        ($p.GPIO6, $p.GPIO7)
+    };
+}
+
+/// Wiper pin of the potentiometer, one of the ADC1 pins (GPIO32-39, or
+/// GPIO0-4 on the ESP32-C3) other than the current monitor's
+///
+/// `PotPin` in `analog.rs` names the same pin for `pot_task`; change both
+/// together, or the build fails.
+#[cfg(feature = "esp32")]
+macro_rules! pot_pin {
+    ($p:ident) => {
+        $p.GPIO35
+    };
+}
+#[cfg(feature = "esp32c3")]
+macro_rules! pot_pin {
+    ($p:ident) => {
+        $p.GPIO3
+    };
+}
+
+/// Current-sense pin, an ADC1 pin like [`pot_pin!`]; `SensePin` in
+/// `current_monitor.rs` names the same pin
+#[cfg(feature = "esp32")]
+macro_rules! sense_pin {
+    ($p:ident) => {
+        $p.GPIO34
+    };
+}
+#[cfg(feature = "esp32c3")]
+macro_rules! sense_pin {
+    ($p:ident) => {
+        $p.GPIO4
+    };
+}
+
+/// Activity LED pin
+#[cfg(feature = "esp32")]
+macro_rules! led_pin {
+    ($p:ident) => {
+        $p.GPIO2
+    };
+}
+#[cfg(feature = "esp32c3")]
+macro_rules! led_pin {
+    ($p:ident) => {
+        $p.GPIO8
+    };
+}
+
+/// Down and up button pins
+#[cfg(feature = "esp32")]
+macro_rules! button_pins {
+    ($p:ident) => {
+        ($p.GPIO32, $p.GPIO33)
+    };
+}
+#[cfg(feature = "esp32c3")]
+macro_rules! button_pins {
+    ($p:ident) => {
+        ($p.GPIO9, $p.GPIO10)
+    };
+}
+
+/// UART0 RTS and CTS pins for serial hardware flow control
+#[cfg(all(feature = "esp32", feature = "serial"))]
+macro_rules! flow_control_pins {
+    ($p:ident) => {
+        ($p.GPIO22, $p.GPIO21)
+    };
+}
+#[cfg(all(feature = "esp32c3", feature = "serial"))]
+macro_rules! flow_control_pins {
+    ($p:ident) => {
+        ($p.GPIO1, $p.GPIO5)
+    };
+}
 
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
@@ -50,59 +364,202 @@ async fn main(spawner: Spawner) -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
+    let reset_reason = diag::record_reset_reason();
+    log::info!("Reset reason: {}", reset_reason.as_str());
+
     // Initialize heap allocator
-    esp_alloc::heap_allocator!(size: 72 * 1024);
+    esp_alloc::heap_allocator!(size: HEAP_SIZE);
+    if let Some(panic) = diag::last_panic() {
+        log::warn!("Last reset was caused by a panic: {}", panic);
+    }
 
     // Initialize timer and software interrupt for esp-rtos
     let timg0 = TimerGroup::new(peripherals.TIMG0);
+    #[cfg(feature = "esp32")]
     esp_rtos::start(timg0.timer0);
+    #[cfg(feature = "esp32c3")]
+    esp_rtos::start(timg0.timer0, SoftwareInterruptControl::new(peripherals.SW_INTERRUPT).software_interrupt0);
 
-    // Initialize UART for serial commands (uses USB-serial on most dev boards)
-    let uart0 = Uart::new(
-        peripherals.UART0,
-        UartConfig::default(),
-    ).unwrap();
-    
-    // Spawn serial command task
-    spawner.spawn(serial_input_task(uart0)).ok();
+    // Load the persisted config blob, falling back to defaults
+    nvs::init(FlashStorage::new(peripherals.FLASH));
+    let config = match nvs::load() {
+        Some(config) => {
+            log::info!(
+                "Loaded config: sweep duration {} ms, {} preset(s)",
+                config.sweep_duration_ms,
+                config.presets.len()
+            );
+            config
+        }
+        None => {
+            log::info!("No stored config, using defaults");
+            nvs::Config::default()
+        }
+    };
+    config.apply();
+    spawner.spawn(nvs::nvs_writer_task()).ok();
+
+    // Initialize UART for serial commands (uses USB-serial on most dev boards);
+    // without the `serial` feature UART0 and the flow control pins stay free
+    #[cfg(feature = "serial")]
+    {
+        let uart0 = Uart::new(
+            peripherals.UART0,
+            serial_cmd::uart_config(),
+        ).unwrap().into_async();
+        let uart0 = if serial_cmd::SERIAL_HW_FLOW_CONTROL {
+            let (rts, cts) = flow_control_pins!(peripherals);
+            uart0.with_rts(rts).with_cts(cts)
+        } else {
+            uart0
+        };
 
-    // Initialize LEDC for servo PWM control on GPIO18
+        // Spawn serial command task
+        spawner.spawn(serial_input_task(uart0)).ok();
+    }
+
+    // Initialize LEDC for servo PWM control
     let ledc = mk_static!(Ledc<'static>, Ledc::new(peripherals.LEDC));
-    let servo_timer = mk_static!(
-        esp_hal::ledc::timer::Timer<'static, esp_hal::ledc::HighSpeed>,
-        init_servo_timer(ledc)
+    #[cfg(feature = "esp32c3")]
+    init_low_speed_clock(ledc);
+    let ledc: &'static Ledc<'static> = ledc;
+    // Servos with a different frequency need their own timer (Timer1-Timer3)
+    #[cfg(feature = "esp32")]
+    let servo_timer = init_servo_timer(ledc, esp_hal::ledc::timer::Number::Timer0, SERVO_PWM_FREQ_HZ);
+    #[cfg(feature = "esp32c3")]
+    let servo_timer = init_servo_timer_low(ledc, esp_hal::ledc::timer::Number::Timer0, SERVO_PWM_FREQ_HZ);
+    let servo_timer: &'static ServoTimer<'static, ServoSpeed> = mk_static!(ServoTimer<'static, ServoSpeed>, servo_timer);
+    state::set_pwm_freq_hz(servo_timer.freq_hz());
+    // Pan and tilt on their own channels of the shared timer
+    let (pan_pin, tilt_pin) = servo_pins!(peripherals);
+    let mut pan = ServoController::new(
+        servo_timer,
+        esp_hal::ledc::channel::Number::Channel0,
+        pan_pin,
     );
-    let mut servo = ServoController::new(servo_timer, peripherals.GPIO18);
-    
-    // Set initial position to center (90 degrees)
-    servo.set_angle(90);
-    println!("Servo initialized on GPIO18 at 90 degrees");
+    let mut tilt = ServoController::new(
+        servo_timer,
+        esp_hal::ledc::channel::Number::Channel1,
+        tilt_pin,
+    );
+
+    // Set initial position to the saved angle or center (90 degrees), or the
+    // safe angle after a brown-out
+    if reset_reason == ResetReason::BrownOut {
+        log::warn!("Recovered from brown-out, starting at safe angle");
+    }
+    let initial_angle = |index: usize| match config.angles[index] {
+        _ if reset_reason == ResetReason::BrownOut => BROWNOUT_SAFE_ANGLE,
+        Some(angle) if RESTORE_LAST_ANGLE => angle,
+        _ => 90,
+    };
+    configure_servo(&mut pan, 0, &config, initial_angle(0));
+    configure_servo(&mut tilt, 1, &config, initial_angle(1));
+    let homing_done = Instant::now() + Duration::from_millis(HOMING_SETTLE_MS);
+    // Every control path refuses moves until then
+    state::set_homed_at(homing_done);
+
+    // GPIOs of the optional inputs and outputs, for the boot summary
+    let mut extra_pins = ExtraPins::default();
+
+    // Both take ADC1, which can only be moved once; the assert on the
+    // settings keeps them from both being on
+    match (CURRENT_MONITOR_ENABLED, POT_ENABLED) {
+        (true, _) => {
+            let pin = sense_pin!(peripherals);
+            extra_pins.current_sense = Some(pin.number());
+            let mut adc_config = AdcConfig::new();
+            let sense_pin = adc_config.enable_pin(pin, Attenuation::_11dB);
+            let adc = Adc::new(peripherals.ADC1, adc_config);
+            spawner.spawn(current_monitor_task(adc, sense_pin)).ok();
+        }
+        (_, true) => {
+            let pin = pot_pin!(peripherals);
+            extra_pins.pot = Some(pin.number());
+            let mut adc_config = AdcConfig::new();
+            let wiper_pin = adc_config.enable_pin(pin, Attenuation::_11dB);
+            let adc = Adc::new(peripherals.ADC1, adc_config);
+            spawner.spawn(pot_task(adc, wiper_pin, POT_SAMPLE_MS)).ok();
+        }
+        _ => {}
+    }
 
-    // Initialize esp-radio controller
-    let esp_radio_controller = mk_static!(esp_radio::Controller<'static>, esp_radio::init().unwrap());
+    if let Some(angle) = PARK_ANGLE {
+        spawner.spawn(park::park_task(angle, PARK_GRACE_MS)).ok();
+    }
+
+    if ACTIVITY_LED_ENABLED {
+        let pin = led_pin!(peripherals);
+        extra_pins.led = Some(pin.number());
+        let led = Output::new(pin, Level::Low, OutputConfig::default());
+        spawner.spawn(activity_led_task(led)).ok();
+    }
+
+    if BUTTONS_ENABLED {
+        let config = InputConfig::default().with_pull(Pull::Up);
+        let (down, up) = button_pins!(peripherals);
+        extra_pins.buttons = Some((down.number(), up.number()));
+        let (down, up) = (Input::new(down, config), Input::new(up, config));
+        spawner.spawn(button_task(down, up, BUTTON_STEP_DEG)).ok();
+    }
+
+    // Controller table indexed by `ServoCommand::servo`
+    let mut servos: [Option<ServoController<'static, ServoSpeed>>; MAX_SERVOS] = [const { None }; MAX_SERVOS];
+    servos[0] = Some(pan);
+    servos[1] = Some(tilt);
+    print_banner(&servos, servo_timer.freq_hz(), reset_reason, &extra_pins);
+
+    // Initialize esp-radio controller, retrying in case the radio isn't ready yet
+    let mut attempt = 1;
+    let radio = loop {
+        match esp_radio::init() {
+            Ok(radio) => break radio,
+            Err(e) if attempt < RADIO_INIT_ATTEMPTS => {
+                let backoff_ms = RADIO_INIT_BACKOFF_MS << (attempt - 1);
+                log::warn!(
+                    "Radio init failed (attempt {}/{}): {:?}, retrying in {} ms",
+                    attempt, RADIO_INIT_ATTEMPTS, e, backoff_ms
+                );
+                Timer::after(Duration::from_millis(backoff_ms)).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                log::error!("Radio init failed after {} attempts: {:?}, restarting", attempt, e);
+                esp_hal::system::software_reset();
+            }
+        }
+    };
+    let esp_radio_controller = mk_static!(esp_radio::Controller<'static>, radio);
 
-    // Initialize WiFi
-    let (controller, interfaces) = esp_radio::wifi::new(
+    // Initialize WiFi. This consumes the WIFI peripheral, so it can't be retried
+    // in place; a restart retries the whole radio bring-up instead.
+    let (controller, interfaces) = match esp_radio::wifi::new(
         esp_radio_controller,
         peripherals.WIFI,
         esp_radio::wifi::Config::default(),
-    )
-    .unwrap();
+    ) {
+        Ok(wifi) => wifi,
+        Err(e) => {
+            log::error!("WiFi init failed: {:?}, restarting", e);
+            esp_hal::system::software_reset();
+        }
+    };
 
     let wifi_interface = interfaces.sta;
 
     // Configure DHCP
-    let net_config = embassy_net::Config::dhcpv4(Default::default());
+    let net_config = net_config();
 
     // Generate random seed for network stack
     let rng = Rng::new();
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
     // Initialize network stack
+    // Sockets: DHCP, DNS, mDNS, SNTP, HTTP server (one per HTTP_WORKERS), telemetry and webhook clients, plus one spare
     let (stack, runner) = embassy_net::new(
         wifi_interface,
         net_config,
-        mk_static!(StackResources<5>, StackResources::<5>::new()),
+        mk_static!(StackResources<10>, StackResources::<10>::new()),
         seed,
     );
 
@@ -110,6 +567,293 @@ async fn main(spawner: Spawner) -> ! {
     spawner.spawn(connection(controller)).ok();
     spawner.spawn(net_task(runner)).ok();
 
+    // SoftAP interface, only brought up as a fallback (see `connection`).
+    // There is no DHCP server on it; clients set a static address.
+    let ap_config = StaticConfigV4 { address: Ipv4Cidr::new(AP_ADDRESS, 24), gateway: None, dns_servers: Default::default() };
+    let (ap_stack, ap_runner) = embassy_net::new(
+        interfaces.ap,
+        embassy_net::Config::ipv4_static(ap_config),
+        mk_static!(StackResources<{ AP_HTTP_WORKERS + 1 }>, StackResources::<{ AP_HTTP_WORKERS + 1 }>::new()),
+        seed.rotate_left(32),
+    );
+    spawner.spawn(net_task(ap_runner)).ok();
+    for buffers in http_server::take_ap_buffers() {
+        spawner.spawn(http_server_task(ap_stack, HTTP_PORT, buffers, http_server::TIMEOUTS)).ok();
+    }
+
+    // The rest of the network services start once the station has an
+    // address; servo commands (serial, and HTTP on the fallback AP) are
+    // handled in the meantime
+    spawner.spawn(station_services_task(spawner, stack, homing_done)).ok();
+
+    diag::log_heap();
+
+    // Sleep through long idle periods, e.g. on solar power
+    if IDLE_SLEEP_AFTER_MS > 0 {
+        spawner.spawn(power::idle_sleep_task(Rtc::new(peripherals.LPWR), peripherals.GPIO0)).ok();
+    }
+
+    // Watch the consume loop below for stalls
+    liveness::tick();
+    if LIVENESS_TIMEOUT_MS > 0 {
+        spawner.spawn(liveness::liveness_task()).ok();
+    }
+    // Reset the chip if the executor or the consume loop wedges
+    if WATCHDOG_TIMEOUT_S > 0 {
+        spawner.spawn(liveness::watchdog_task(TimerGroup::new(peripherals.TIMG1).wdt)).ok();
+    }
+
+    let mut arbiter = PriorityArbiter::new();
+    let mut dwell = ReverseDwell::new();
+    // When each servo last moved, for MIN_MOVE_INTERVAL_MS
+    let mut last_move: [Option<Instant>; MAX_SERVOS] = [None; MAX_SERVOS];
+    // Commands received but not handled yet, taken in order before waiting
+    // again: one that interrupted a pulse hold or sweep or ended a coalesced
+    // run goes first, ones that arrived during a hold or sweep without
+    // interrupting it go last
+    let mut pending: VecDeque<ServoCommand> = VecDeque::new();
+    // Dwell of the last ServoAction::Step, waited out before the next command
+    let mut step_dwell_ms: Option<u32> = None;
+
+    // Main loop - handle servo angle updates from HTTP or serial
+    loop {
+        if let Some(dwell_ms) = step_dwell_ms.take() {
+            Timer::after(Duration::from_millis(dwell_ms as u64)).await;
+        }
+        // Wait for a command from any source (HTTP, serial, ...)
+        let mut command = match pending.pop_front() {
+            Some(command) => command,
+            None => SERVO_COMMAND.receive().await,
+        };
+        liveness::tick();
+        // A burst of angle commands for the same servo only needs its last one;
+        // anything else ends the run and is handled next, in order
+        let mut coalesce_until = Instant::now() + Duration::from_millis(COALESCE_WINDOW_MS);
+        // Too soon after the servo's last move: keep coalescing until it may move again
+        let throttled_until = last_move
+            .get(command.servo as usize)
+            .copied()
+            .flatten()
+            .map(|at| at + Duration::from_millis(MIN_MOVE_INTERVAL_MS))
+            .filter(|until| MIN_MOVE_INTERVAL_MS > 0 && *until > Instant::now());
+        if let (ServoAction::Set(_), Some(until)) = (command.action, throttled_until)
+            && until > coalesce_until
+        {
+            log::info!("Throttling servo {}: at most one move per {} ms", command.servo, MIN_MOVE_INTERVAL_MS);
+            coalesce_until = until;
+        }
+        while let ServoAction::Set(_) = command.action {
+            let queued = pending.pop_front().or_else(|| SERVO_COMMAND.try_receive().ok());
+            let next = match queued {
+                Some(next) => next,
+                None => match with_deadline(coalesce_until, SERVO_COMMAND.receive()).await {
+                    Ok(next) => next,
+                    Err(_) => break,
+                },
+            };
+            if matches!(next.action, ServoAction::Set(_)) && next.servo == command.servo && next.source == command.source {
+                command = next;
+            } else {
+                pending.push_front(next);
+                break;
+            }
+        }
+        let index = command.servo as usize;
+        let Some(servo) = servos.get_mut(index).and_then(Option::as_mut) else {
+            log::warn!("Ignoring command for unconfigured servo {}", index);
+            continue;
+        };
+        if !arbiter.admit(&command, Instant::now()) {
+            log::info!("Dropping {} command for servo {}: higher-priority command in progress", command.source.as_str(), index);
+            continue;
+        }
+        match command.action {
+            ServoAction::Set(_) | ServoAction::Step { .. } | ServoAction::Pulse { .. } | ServoAction::Sweep { .. }
+                if state::readiness().is_err() =>
+            {
+                // Not ready (fault, estop, ...): keep the servo relaxed until it is
+                if servo.is_attached() {
+                    servo.detach();
+                    state::set_attached(index, false);
+                }
+                match (state::readiness(), ESTOP_POLICY) {
+                    (Err(NotReady::Estop), EstopPolicy::Drop) => {
+                        log::debug!("Dropping move on servo {}: emergency stop engaged", index)
+                    }
+                    (Err(NotReady::Estop), EstopPolicy::QueueLatest) => {
+                        log::info!("Holding move on servo {} until the emergency stop is cleared", index);
+                        queue_during_estop(command);
+                    }
+                    (Err(reason), _) => log::warn!("Refusing move on servo {}: not ready ({})", index, reason.as_str()),
+                    (Ok(()), _) => {}
+                }
+            }
+            ServoAction::Set(angle) | ServoAction::Step { angle, .. } => {
+                if let ServoAction::Step { dwell_ms, .. } = command.action {
+                    step_dwell_ms = Some(dwell_ms);
+                }
+                let previous = servo.get_angle().unwrap_or(angle);
+                if !dwell.admit(index, previous, angle, Instant::now()) {
+                    log::info!(
+                        "Refusing reverse on servo {} to {} degrees: within the {} ms dwell",
+                        index, angle, REVERSE_DWELL_MS
+                    );
+                    continue;
+                }
+                if !servo.is_attached() {
+                    // Resume pulses at the old position and let the PWM settle before moving
+                    servo.attach();
+                    state::set_attached(index, true);
+                    Timer::after(Duration::from_millis(ATTACH_SETTLE_MS)).await;
+                } else if servo.get_angle() == Some(angle) {
+                    // Idempotent command: nothing to write to the hardware
+                    state::record_move(index, angle, command.source);
+                    activity_led::flash();
+                    log::debug!("Servo {} already at {} degrees (source: {})", index, angle, command.source.as_str());
+                    continue;
+                }
+                // A different command ended the coalesced run early; still keep the floor
+                if let Some(until) = throttled_until.filter(|until| *until > Instant::now()) {
+                    log::info!("Throttling servo {}: move to {} degrees held for {} ms", index, angle, (until - Instant::now()).as_millis());
+                    Timer::at(until).await;
+                }
+                let sweep_ms = state::sweep_duration_ms();
+                state::set_target(index, angle);
+                let reached = if sweep_ms > 0 {
+                    let (reached, interrupt) =
+                        sweep(servo, index, previous, angle, sweep_ms, command.source, &arbiter, &mut pending).await;
+                    if let Some(interrupt) = interrupt {
+                        pending.push_front(interrupt);
+                    }
+                    reached
+                } else {
+                    servo.set_angle(angle);
+                    state::record_move(index, angle, command.source);
+                    angle
+                };
+                state::set_target(index, reached);
+                last_move[index] = Some(Instant::now());
+                webhook::check_triggers(command.servo, previous, reached);
+                move_log::record(command.servo, reached, command.source);
+                activity_led::flash();
+                if RESTORE_LAST_ANGLE {
+                    nvs::request_save();
+                }
+                if reached == angle {
+                    log::info!("Servo {} moved to {} degrees (source: {})", index, angle, command.source.as_str());
+                } else {
+                    log::info!("Servo {} sweep to {} interrupted at {} degrees", index, angle, reached);
+                }
+            }
+            ServoAction::Pulse { angle, hold_ms } => {
+                let rest = servo.get_angle().unwrap_or(angle);
+                if !dwell.admit(index, rest, angle, Instant::now()) {
+                    log::info!(
+                        "Refusing reverse on servo {} to {} degrees: within the {} ms dwell",
+                        index, angle, REVERSE_DWELL_MS
+                    );
+                    continue;
+                }
+                if !servo.is_attached() {
+                    servo.attach();
+                    state::set_attached(index, true);
+                    Timer::after(Duration::from_millis(ATTACH_SETTLE_MS)).await;
+                }
+                servo.set_angle(angle);
+                state::record_move(index, angle, command.source);
+                state::set_target(index, angle);
+                webhook::check_triggers(command.servo, rest, angle);
+                move_log::record(command.servo, angle, command.source);
+                activity_led::flash();
+                log::info!("Servo {} pulsed to {} degrees for {} ms (source: {})", index, angle, hold_ms, command.source.as_str());
+
+                // Hold, unless a command that may take over this servo arrives
+                // first; that one replaces the return move
+                let hold = Timer::after(Duration::from_millis(hold_ms as u64));
+                match select(hold, interrupting_command(index, &arbiter, &mut pending)).await {
+                    Either::First(()) => {
+                        servo.set_angle(rest);
+                        state::record_move(index, rest, command.source);
+                        state::set_target(index, rest);
+                        webhook::check_triggers(command.servo, angle, rest);
+                        move_log::record(command.servo, rest, command.source);
+                        if RESTORE_LAST_ANGLE {
+                            nvs::request_save();
+                        }
+                        log::info!("Servo {} returned to {} degrees", index, rest);
+                    }
+                    Either::Second(next) => {
+                        log::info!("Servo {} pulse interrupted by {} command", index, next.source.as_str());
+                        pending.push_front(next);
+                    }
+                }
+            }
+            ServoAction::Sweep { from, to, step_ms } => {
+                if !servo.is_attached() {
+                    servo.attach();
+                    state::set_attached(index, true);
+                    Timer::after(Duration::from_millis(ATTACH_SETTLE_MS)).await;
+                }
+                // Travel to the start at the sweep's pace, then sweep
+                let mut reached = servo.get_angle().unwrap_or(from);
+                for target in [from, to] {
+                    let leg_start = reached;
+                    state::set_target(index, target);
+                    let duration_ms = leg_start.abs_diff(target) as u32 * step_ms as u32;
+                    let (leg_end, interrupt) =
+                        sweep(servo, index, leg_start, target, duration_ms, command.source, &arbiter, &mut pending).await;
+                    reached = leg_end;
+                    webhook::check_triggers(command.servo, leg_start, reached);
+                    if let Some(interrupt) = interrupt {
+                        pending.push_front(interrupt);
+                        break;
+                    }
+                }
+                state::set_target(index, reached);
+                move_log::record(command.servo, reached, command.source);
+                activity_led::flash();
+                if RESTORE_LAST_ANGLE {
+                    nvs::request_save();
+                }
+                if reached == to {
+                    log::info!("Servo {} swept {} -> {} degrees at {} ms/degree (source: {})", index, from, to, step_ms, command.source.as_str());
+                } else {
+                    log::info!("Servo {} sweep {} -> {} interrupted at {} degrees", index, from, to, reached);
+                }
+            }
+            ServoAction::Trim(trim) => {
+                servo.set_trim(trim);
+                state::set_trim(index, servo.trim());
+                if let (true, Some(angle)) = (servo.is_attached(), servo.get_angle()) {
+                    // Re-apply so the new trim is visible straight away
+                    servo.set_angle(angle);
+                }
+                log::info!("Servo {} trim set to {:+} degrees", index, servo.trim());
+            }
+            ServoAction::PulseRange { min_us, max_us } => {
+                servo.set_pulse_range(min_us as u32, max_us as u32);
+                publish_pulse_range(servo, index);
+                if let (true, Some(angle)) = (servo.is_attached(), servo.get_angle()) {
+                    // Re-apply so the new range is visible straight away
+                    servo.set_angle(angle);
+                }
+                let (min_us, max_us) = servo.pulse_range();
+                log::info!("Servo {} pulse range set to {} - {} us", index, min_us, max_us);
+            }
+            ServoAction::Detach => {
+                servo.detach();
+                state::set_attached(index, false);
+                activity_led::flash();
+                log::info!("Servo {} detached (source: {})", index, command.source.as_str());
+            }
+        }
+    }
+}
+
+/// Wait for the station to get an address, then start the services that
+/// need it: HTTP handlers, mDNS, SNTP, telemetry and webhooks
+#[embassy_executor::task]
+async fn station_services_task(spawner: Spawner, stack: Stack<'static>, homing_done: Instant) {
     // Wait for link to be up
     loop {
         if stack.is_link_up() {
@@ -118,74 +862,434 @@ async fn main(spawner: Spawner) -> ! {
         Timer::after(Duration::from_millis(500)).await;
     }
 
-    println!("Waiting to get IP address...");
+    if STATIC_IP.is_none() {
+        log::info!("Waiting to get IP address...");
+    }
     loop {
         if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}", config.address);
+            log::info!("Got IP: {}", config.address);
+            state::set_ip_address(Some(config.address.address()));
             break;
         }
         Timer::after(Duration::from_millis(500)).await;
     }
 
-    println!("WiFi connected successfully!");
+    log::info!("WiFi connected successfully!");
+    // Reaching the network is the test an updated firmware has to pass
+    ota::confirm_running_image();
 
-    // Spawn HTTP server
-    spawner.spawn(http_server_task(stack)).ok();
+    // Usually long over by now, since WiFi takes longer than homing
+    if HTTP_AFTER_HOMING && Instant::now() < homing_done {
+        log::info!("Waiting for homing to finish before starting HTTP server...");
+        Timer::at(homing_done).await;
+    }
 
-    // Main loop - handle servo angle updates from HTTP or serial
-    loop {
-        // Wait for angle signal from either HTTP or serial
-        let angle = match select(SERVO_ANGLE.wait(), SERIAL_SERVO_ANGLE.wait()).await {
-            Either::First(angle) => angle,
-            Either::Second(angle) => angle,
-        };
-        servo.set_angle(angle);
-        println!("Servo moved to {} degrees", angle);
+    // Spawn HTTP connection handlers, each with its own buffers
+    for buffers in http_server::take_station_buffers() {
+        spawner.spawn(http_server_task(stack, HTTP_PORT, buffers, http_server::TIMEOUTS)).ok();
+    }
+    log::info!("HTTP server accepting connections");
+
+    // Answer <hostname>.local lookups
+    spawner.spawn(mdns::mdns_task(stack)).ok();
+
+    // Keep the wall clock synced for timestamps
+    spawner.spawn(sntp::sntp_task(stack)).ok();
+
+    // Spawn telemetry push if a collector is configured
+    if let Some(url) = telemetry::TELEMETRY_URL {
+        spawner.spawn(telemetry::telemetry_task(stack, url)).ok();
+    }
+
+    // Spawn webhook delivery if a webhook is configured
+    if let Some(url) = webhook::WEBHOOK_URL {
+        spawner.spawn(webhook::webhook_task(stack, url)).ok();
     }
 }
 
+/// Network configuration: the static address from cfg.toml if one is set and
+/// valid, DHCP otherwise
+fn net_config() -> embassy_net::Config {
+    let Some(ip) = STATIC_IP else {
+        log::info!("Network: DHCP");
+        return embassy_net::Config::dhcpv4(Default::default());
+    };
+    let parsed = ip.split_once('/').and_then(|(address, prefix)| {
+        Some(Ipv4Cidr::new(address.parse().ok()?, prefix.parse().ok().filter(|len| *len <= 32)?))
+    });
+    let Some(address) = parsed else {
+        log::warn!("Network: invalid static_ip {:?} (expected a.b.c.d/prefix), using DHCP", ip);
+        return embassy_net::Config::dhcpv4(Default::default());
+    };
+    let gateway = STATIC_GATEWAY.and_then(|gateway| gateway.parse().ok());
+    let mut config = StaticConfigV4 { address, gateway, dns_servers: Default::default() };
+    if let Some(dns) = STATIC_DNS.and_then(|dns| dns.parse().ok()) {
+        let _ = config.dns_servers.push(dns);
+    }
+    log::info!("Network: static IP {}, gateway {:?}, DNS {:?}", address, gateway, config.dns_servers);
+    embassy_net::Config::ipv4_static(config)
+}
+
+/// GPIOs taken by the optional features set up in `main` (`None` = off),
+/// for the boot summary
+#[derive(Default)]
+struct ExtraPins {
+    current_sense: Option<u8>,
+    pot: Option<u8>,
+    /// Down and up buttons
+    buttons: Option<(u8, u8)>,
+    led: Option<u8>,
+}
+
+/// Print a one-block summary of the running configuration
+fn print_banner(
+    servos: &[Option<ServoController<'static, ServoSpeed>>],
+    freq_hz: u32,
+    reset_reason: ResetReason,
+    extra_pins: &ExtraPins,
+) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let on_gpio = |gpio: Option<u8>| gpio.map_or(String::from("off"), |gpio| alloc::format!("on, GPIO{}", gpio));
+
+    println!("==================================================");
+    println!(" esp32-http-servo v{}", env!("CARGO_PKG_VERSION"));
+    println!(" Reset reason:   {}", reset_reason.as_str());
+    for (index, servo) in servos.iter().enumerate() {
+        let Some(servo) = servo else { continue };
+        let hardware = servo.hardware();
+        let (hw_min, hw_max) = servo.limits();
+        println!(
+            " Servo {}:        GPIO{}, LEDC channel {}, timer {}, {} Hz, {}-{} degrees",
+            index, hardware.gpio, hardware.channel, hardware.timer, freq_hz, hw_min, hw_max
+        );
+    }
+    println!(" Public range:   {}-{} degrees (strict: {})", PUBLIC_MIN_ANGLE, PUBLIC_MAX_ANGLE, on_off(STRICT_LIMITS));
+    println!(" Servo slots:    {}", MAX_SERVOS);
+    println!(
+        " Network:        WiFi station, {}, SSID(s) \"{}\"",
+        if STATIC_IP.is_some() { "static IP" } else { "DHCP" },
+        wifi_networks().iter().map(|(ssid, _)| ssid.as_str()).collect::<Vec<_>>().join("\", \"")
+    );
+    println!(" mDNS:           {}.local", mdns::MDNS_HOSTNAME);
+    println!(" HTTP:           port {}, {} handler(s)", HTTP_PORT, HTTP_WORKERS);
+    #[cfg(feature = "serial")]
+    println!(" Serial:         {} baud, flow control {}", serial_cmd::SERIAL_BAUD_RATE, on_off(serial_cmd::SERIAL_HW_FLOW_CONTROL));
+    #[cfg(not(feature = "serial"))]
+    println!(" Serial:         disabled");
+    println!(" Sweep duration: {} ms", state::sweep_duration_ms());
+    println!(" Current monitor: {}", on_gpio(extra_pins.current_sense));
+    println!(" Pot:            {}", on_gpio(extra_pins.pot));
+    match extra_pins.buttons {
+        Some((down, up)) => println!(" Buttons:        on, GPIO{} down, GPIO{} up, {} degrees per press", down, up, BUTTON_STEP_DEG),
+        None => println!(" Buttons:        off"),
+    }
+    println!(" Activity LED:   {}", on_gpio(extra_pins.led));
+    match PARK_ANGLE {
+        Some(angle) => println!(" Park:           {} degrees after {} s without WiFi", angle, PARK_GRACE_MS / 1000),
+        None => println!(" Park:           off"),
+    }
+    if IDLE_SLEEP_AFTER_MS > 0 {
+        println!(" Idle sleep:     after {} s, wake on GPIO0", IDLE_SLEEP_AFTER_MS / 1000);
+    } else {
+        println!(" Idle sleep:     off");
+    }
+    println!(" Telemetry:      {}", telemetry::TELEMETRY_URL.unwrap_or("off"));
+    println!(" API token:      {}", on_off(http_server::API_TOKEN.is_some()));
+    println!(" Webhook:        {}", webhook::WEBHOOK_URL.unwrap_or("off"));
+    println!(" diag-delay:     {}", on_off(cfg!(feature = "diag-delay")));
+    println!("==================================================");
+}
+
+/// Publish the pulse range servo `index` actually uses, after clamping
+fn publish_pulse_range(servo: &ServoController<'static, ServoSpeed>, index: usize) {
+    let (min_us, max_us) = servo.pulse_range();
+    state::set_pulse_range(index, min_us as u16, max_us as u16);
+}
+
+/// Apply the stored limits and trim to servo `index`, publish its state and
+/// drive it to `initial_angle`
+fn configure_servo(servo: &mut ServoController<'static, ServoSpeed>, index: usize, config: &nvs::Config, initial_angle: u8) {
+    let (min_angle, max_angle) = config.limits[index].unwrap_or((SAFE_MIN_ANGLE, SAFE_MAX_ANGLE));
+    servo.set_limits(min_angle, max_angle);
+    servo.set_pulse_step(SERVO_PULSE_STEP_US);
+    servo.set_trim(config.trims[index]);
+    servo.set_inverted(SERVO_INVERTED[index]);
+    state::set_limits(index, min_angle, max_angle);
+    state::set_limits_configured(index, config.limits[index].is_some());
+    state::set_trim(index, servo.trim());
+    publish_pulse_range(servo, index);
+    state::set_hardware(index, servo.hardware());
+
+    servo.set_angle(initial_angle);
+    state::record_move(index, initial_angle, CommandSource::Auto);
+    state::set_target(index, initial_angle);
+    state::set_attached(index, true);
+    log::info!("Servo {} initialized on GPIO{} at {} degrees", index, servo.hardware().gpio, initial_angle);
+}
+
+/// Move `servo` from `from` to `to` in steps spread over `duration_ms`
+///
+/// Returns the angle reached and, if a command that may take over the servo
+/// arrived mid-sweep (see [`interrupting_command`]), that command; the sweep
+/// stops where it is so the new command takes over.
+#[allow(clippy::too_many_arguments)]
+async fn sweep(
+    servo: &mut impl AngleActuator,
+    index: usize,
+    from: u8,
+    to: u8,
+    duration_ms: u32,
+    source: CommandSource,
+    arbiter: &PriorityArbiter,
+    pending: &mut VecDeque<ServoCommand>,
+) -> (u8, Option<ServoCommand>) {
+    let moved = |angle| {
+        state::record_move(index, angle, source);
+        liveness::tick();
+    };
+    let wait_step = async || {
+        let step_delay = Timer::after(Duration::from_millis(SWEEP_STEP_MS as u64));
+        match select(step_delay, interrupting_command(index, arbiter, pending)).await {
+            Either::First(()) => None,
+            Either::Second(next) => Some(next),
+        }
+    };
+    actuator::sweep(servo, from, to, duration_ms / SWEEP_STEP_MS, moved, wait_step).await
+}
+
+/// Wait for a command that should cut a pulse hold or sweep of servo `index`
+/// short: one for the same servo that `arbiter` would admit
+///
+/// Commands for other servos, and ones the arbiter would drop, go to the back
+/// of `pending` to be handled once the hold or sweep is over; past
+/// [`SERVO_COMMAND_DEPTH`] of them the oldest is discarded, as when the queue
+/// itself is full.
+async fn interrupting_command(
+    index: usize,
+    arbiter: &PriorityArbiter,
+    pending: &mut VecDeque<ServoCommand>,
+) -> ServoCommand {
+    actuator::interrupting_command(
+        index,
+        async || SERVO_COMMAND.receive().await,
+        |next| arbiter.would_admit(next, Instant::now()),
+        pending,
+        SERVO_COMMAND_DEPTH,
+        |dropped| log::warn!("Command queue full, dropping oldest ({} for servo {})", dropped.source.as_str(), dropped.servo),
+    )
+    .await
+}
+
 #[embassy_executor::task]
 async fn connection(mut controller: WifiController<'static>) {
-    println!("Start connection task");
-    println!("Device capabilities: {:?}", controller.capabilities());
+    log::debug!("Start connection task");
+    log::debug!("Device capabilities: {:?}", controller.capabilities());
+    // Consecutive failed connects, and whether the fallback AP is open
+    let mut failures = 0;
+    let mut ap_active = false;
+    // Network joined last, and the access point to pin the next connect to
+    // after deciding to roam
+    let mut current_ssid: Option<String> = None;
+    let mut roam_bssid: Option<[u8; 6]> = None;
     
     loop {
-        match sta_state() {
-            WifiStaState::Connected => {
-                // Wait until we're no longer connected
-                controller
-                    .wait_for_event(WifiEvent::StaDisconnected)
-                    .await;
-                Timer::after(Duration::from_millis(5000)).await
+        if sta_state() == WifiStaState::Connected {
+            // Sample the signal strength until we're no longer connected
+            let mut weak = false;
+            let mut last_roam_scan = Instant::now();
+            loop {
+                let rssi = controller.rssi().ok().map(|dbm| dbm.clamp(-128, -1) as i8);
+                state::set_rssi(rssi);
+                match rssi {
+                    Some(dbm) if !weak && dbm < RSSI_WARN_DBM => {
+                        log::warn!("weak WiFi signal ({} dBm)", dbm);
+                        weak = true;
+                    }
+                    Some(dbm) if weak && dbm >= RSSI_WARN_DBM + RSSI_WARN_HYSTERESIS_DB => {
+                        log::info!("WiFi signal recovered ({} dBm)", dbm);
+                        weak = false;
+                    }
+                    _ => {}
+                }
+                if let (Some(dbm), Some(ssid)) = (rssi, current_ssid.as_deref()) {
+                    let scan_due = last_roam_scan.elapsed() >= Duration::from_millis(ROAM_SCAN_INTERVAL_MS);
+                    if ROAM_RSSI_DBM != 0 && dbm < ROAM_RSSI_DBM && scan_due {
+                        last_roam_scan = Instant::now();
+                        if let Some(bssid) = stronger_ap(&mut controller, ssid, dbm).await {
+                            roam_bssid = Some(bssid);
+                            let _ = controller.disconnect_async().await;
+                            break;
+                        }
+                    }
+                }
+                let disconnected = controller.wait_for_event(WifiEvent::StaDisconnected);
+                if let Either::Second(()) = select(Timer::after(Duration::from_millis(RSSI_POLL_MS)), disconnected).await {
+                    break;
+                }
+            }
+            state::set_rssi(None);
+            state::set_wifi_connected(false);
+            if roam_bssid.is_none() {
+                log::info!("WiFi disconnected, reconnecting in {} ms", WIFI_RETRY_INITIAL_MS);
+                Timer::after(Duration::from_millis(WIFI_RETRY_INITIAL_MS)).await
             }
-            _ => {}
         }
         
+        let networks = wifi_networks();
         if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = ModeConfig::Client(
-                ClientConfig::default()
-                    .with_ssid(SSID.try_into().unwrap())
-                    .with_password(PASSWORD.try_into().unwrap()),
-            );
-            controller.set_config(&client_config).unwrap();
-            println!("Starting WiFi...");
+            controller.set_config(&wifi_mode(ap_active, &networks[0], None)).unwrap();
+            log::info!("Starting WiFi...");
             controller.start_async().await.unwrap();
-            println!("WiFi started!");
+            log::info!("WiFi started!");
         }
         
-        println!("Connecting to WiFi network: {}", SSID);
+        // Only attempt a connect when a scan confirms one of the networks
+        // is in range
+        let result = match pick_network(&mut controller, &networks, failures).await {
+            Some(network) => {
+                // A roam is tried once; if that access point fails, any will do
+                let bssid = roam_bssid.take().filter(|_| current_ssid.as_deref() == Some(network.0.as_str()));
+                if let Err(e) = controller.set_config(&wifi_mode(ap_active, network, bssid)) {
+                    log::warn!("Failed to configure WiFi network {}: {:?}", network.0, e);
+                }
+                current_ssid = Some(network.0.clone());
+                log::info!("Connecting to WiFi network: {}", network.0);
+                controller.connect_async().await.map_err(|e| log::warn!("Failed to connect to WiFi: {:?}", e))
+            }
+            None => Err(()),
+        };
         
-        match controller.connect_async().await {
-            Ok(_) => println!("WiFi connected!"),
-            Err(e) => {
-                println!("Failed to connect to WiFi: {:?}", e);
-                Timer::after(Duration::from_millis(5000)).await
+        match result {
+            Ok(()) => {
+                log::info!("WiFi connected!");
+                state::set_wifi_connected(true);
+                failures = 0;
+                if ap_active {
+                    // Restarts in station-only mode on the next pass
+                    log::info!("Closing fallback access point {}", AP_SSID);
+                    ap_active = false;
+                    state::set_ap_active(false);
+                    let _ = controller.stop_async().await;
+                }
+            }
+            Err(()) => {
+                failures += 1;
+                if !ap_active && AP_FALLBACK_AFTER_ATTEMPTS > 0 && failures >= AP_FALLBACK_AFTER_ATTEMPTS {
+                    log::info!("Opening fallback access point {} at {}", AP_SSID, AP_ADDRESS);
+                    ap_active = true;
+                    state::set_ap_active(true);
+                    let _ = controller.stop_async().await;
+                }
+                let delay_ms = (WIFI_RETRY_INITIAL_MS << (failures - 1).min(16)).min(WIFI_RETRY_MAX_MS);
+                log::info!("WiFi retry {} in {} ms", failures, delay_ms);
+                Timer::after(Duration::from_millis(delay_ms)).await
             }
         }
     }
 }
 
-#[embassy_executor::task]
+/// WiFi networks to try, in order of preference, as `(ssid, password)`: the
+/// one set with `POST /wifi`, then the ones compiled in from `cfg.toml`
+fn wifi_networks() -> Vec<(String, String)> {
+    let mut networks: Vec<(String, String)> = state::wifi_credentials().into_iter().collect();
+    let configured = core::iter::once((SSID, PASSWORD)).chain(
+        WIFI_NETWORKS
+            .unwrap_or("")
+            .split(';')
+            .filter_map(|entry| entry.trim().split_once(':'))
+            .filter(|(ssid, _)| !ssid.is_empty()),
+    );
+    // Drop anything the driver would reject, rather than panicking in
+    // `wifi_mode` later
+    let configured = configured.filter(|(ssid, password)| match nvs::check_wifi_credentials(ssid, password) {
+        Ok(()) => true,
+        Err(reason) => {
+            log::warn!("WiFi: skipping network {:?} from wifi_networks: {}", ssid, reason);
+            false
+        }
+    });
+    for (ssid, password) in configured {
+        if !networks.iter().any(|(known, _)| known == ssid) {
+            networks.push((String::from(ssid), String::from(password)));
+        }
+    }
+    networks
+}
+
+/// Choose the network for the next connect: the most preferred one a scan
+/// can see
+///
+/// `None` when the scan sees none of them. If the scan itself fails, the
+/// networks are tried in turn by `attempt` instead.
+async fn pick_network<'n>(
+    controller: &mut WifiController<'static>,
+    networks: &'n [(String, String)],
+    attempt: u32,
+) -> Option<&'n (String, String)> {
+    match controller.scan_with_config_async(ScanConfig::default()).await {
+        Ok(visible) => {
+            let found = networks.iter().find_map(|network| {
+                let ap = visible.iter().find(|ap| ap.ssid.as_str() == network.0)?;
+                Some((network, ap.signal_strength))
+            });
+            if let Some((network, rssi)) = found {
+                log::info!("WiFi scan: chose {} ({} dBm)", network.0, rssi);
+            } else {
+                log::info!("WiFi scan: none of the {} configured network(s) in range", networks.len());
+            }
+            found.map(|(network, _)| network)
+        }
+        Err(e) => {
+            log::warn!("WiFi scan failed: {:?}", e);
+            Some(&networks[attempt as usize % networks.len()])
+        }
+    }
+}
+
+/// Look for an access point broadcasting `ssid` at least
+/// [`ROAM_HYSTERESIS_DB`] stronger than the current `rssi`, returning its BSSID
+///
+/// The access point in use shows up in the scan at about `rssi`, so anything
+/// that much stronger is a different one.
+async fn stronger_ap(controller: &mut WifiController<'static>, ssid: &str, rssi: i8) -> Option<[u8; 6]> {
+    let visible = match controller.scan_with_config_async(ScanConfig::default()).await {
+        Ok(visible) => visible,
+        Err(e) => {
+            log::warn!("WiFi roaming scan failed: {:?}", e);
+            return None;
+        }
+    };
+    let best = visible.iter().filter(|ap| ap.ssid.as_str() == ssid).max_by_key(|ap| ap.signal_strength)?;
+    if best.signal_strength < rssi.saturating_add(ROAM_HYSTERESIS_DB) {
+        return None;
+    }
+    log::info!(
+        "WiFi roaming: {} dBm here, {} dBm at {:02x?} for {}, switching",
+        rssi, best.signal_strength, best.bssid, ssid
+    );
+    Some(best.bssid)
+}
+
+/// Station config for `network`, plus the fallback access point alongside
+/// it when `ap`; `bssid` pins it to one access point of that network
+fn wifi_mode(ap: bool, network: &(String, String), bssid: Option<[u8; 6]>) -> ModeConfig {
+    let (ssid, password) = network;
+    // Every network was checked with `nvs::check_wifi_credentials`, so the
+    // driver accepts it: the build-time ones by `wifi_networks`, the stored
+    // one before it was saved
+    let client = ClientConfig::default().with_ssid(ssid.as_str().into()).with_password(password.as_str().into());
+    let client = match bssid {
+        Some(bssid) => client.with_bssid(bssid),
+        None => client,
+    };
+    if ap {
+        ModeConfig::ApSta(client, AccessPointConfig::default().with_ssid(AP_SSID.into()))
+    } else {
+        ModeConfig::Client(client)
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     runner.run().await
 }
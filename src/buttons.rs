@@ -0,0 +1,54 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::Input;
+
+use crate::command::{apply_limits, check_ready, send_command, CommandSource, ServoCommand, PUBLIC_MAX_ANGLE, PUBLIC_MIN_ANGLE};
+use crate::{power, state};
+
+/// How long a press must stay down to count, filtering contact bounce
+const DEBOUNCE_MS: u64 = 20;
+
+/// Wait for a debounced press of `button` (active low)
+async fn pressed(button: &mut Input<'static>) {
+    loop {
+        button.wait_for_falling_edge().await;
+        Timer::after(Duration::from_millis(DEBOUNCE_MS)).await;
+        if button.is_low() {
+            return;
+        }
+    }
+}
+
+/// Jog servo 0 by `step_deg` with two buttons, without any network
+///
+/// Both buttons are active low (wired to ground, internal pull-ups) and wake
+/// the task by interrupt. Each press moves from the angle the servo is heading
+/// to, saturating at the public range, and is queued like any other command,
+/// as [`CommandSource::Button`].
+#[embassy_executor::task]
+pub async fn button_task(mut down: Input<'static>, mut up: Input<'static>, step_deg: u8) {
+    log::info!("Buttons: jog servo 0 by {} degrees", step_deg);
+    loop {
+        let delta = match select(pressed(&mut down), pressed(&mut up)).await {
+            Either::First(()) => -(step_deg as i16),
+            Either::Second(()) => step_deg as i16,
+        };
+        power::note_activity();
+        if let Err(reason) = check_ready() {
+            log::warn!("Button: refused, servos not ready ({})", reason.as_str());
+        } else {
+            let from = state::target_angle(0) as i16;
+            let angle = (from + delta).clamp(PUBLIC_MIN_ANGLE as i16, PUBLIC_MAX_ANGLE as i16) as u8;
+            match apply_limits(0, angle) {
+                Ok(angle) => {
+                    log::info!("Button: Setting servo to {} degrees", angle);
+                    send_command(ServoCommand::set(0, angle, CommandSource::Button));
+                }
+                Err((min, max)) => log::warn!("Button: refused, {} is outside the allowed range {}-{}", angle, min, max),
+            }
+        }
+        // Wait for the release so a held button jogs once
+        let button = if delta < 0 { &mut down } else { &mut up };
+        button.wait_for_high().await;
+    }
+}
@@ -0,0 +1,198 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_time::{Duration, Instant};
+use servo_core::arbiter;
+use servo_core::command::limit_angle;
+pub use servo_core::command::{CommandSource, ServoAction, ServoCommand, MAX_PULSE_HOLD_MS, MAX_SWEEP_STEP_MS};
+
+use crate::state;
+
+/// Number of servos the firmware is built for
+///
+/// Sizes the per-servo state arrays, the controller table in `main.rs` and the
+/// index range accepted by `/servo/<index>/<angle>`. Each servo costs only a
+/// few bytes of RAM (its `ServoController` plus three bytes of shared state),
+/// so the practical limit is the eight HighSpeed LEDC channels.
+pub const MAX_SERVOS: usize = 2;
+
+/// User-facing angle range accepted at the protocol boundary (HTTP, serial)
+///
+/// Kept separate from the hardware limits set with `ServoController::set_limits`
+/// in `main.rs`: operators can expose e.g. 10-170 to users while the hardware
+/// configuration keeps the full calibrated travel.
+pub const PUBLIC_MIN_ANGLE: u8 = 0;
+pub const PUBLIC_MAX_ANGLE: u8 = 180;
+
+/// Refuse commands outside the allowed range instead of clamping them
+///
+/// Strict is the default, so a client always learns when its command could
+/// not be honored exactly. Disable it to clamp such commands into the range.
+pub const STRICT_LIMITS: bool = true;
+
+/// Range a client may command on `servo`: the public range narrowed by the
+/// servo's hardware limits
+pub fn allowed_range(servo: usize) -> (u8, u8) {
+    let (hw_min, hw_max) = state::limits(servo);
+    let min = PUBLIC_MIN_ANGLE.max(hw_min);
+    let max = PUBLIC_MAX_ANGLE.min(hw_max).max(min);
+    (min, max)
+}
+
+/// Apply the allowed range to a requested angle
+///
+/// Returns the angle to apply, clamped into range, or in strict mode the
+/// allowed `(min, max)` range if the angle falls outside it.
+pub fn apply_limits(servo: usize, angle: u8) -> Result<u8, (u8, u8)> {
+    limit_angle(angle, allowed_range(servo), STRICT_LIMITS)
+}
+
+/// Check at a command entry point whether a move may be accepted
+///
+/// Refuses while [`state::readiness`] reports a reason, except that an
+/// emergency stop only refuses under [`EstopPolicy::Reject`]; the other
+/// policies accept the move and let the main loop drop or hold it.
+pub fn check_ready() -> Result<(), state::NotReady> {
+    match state::readiness() {
+        Err(state::NotReady::Estop) if ESTOP_POLICY != EstopPolicy::Reject => Ok(()),
+        readiness => readiness,
+    }
+}
+
+/// Commands that can wait in [`SERVO_COMMAND`] before the oldest is discarded
+pub const SERVO_COMMAND_DEPTH: usize = 8;
+
+/// Queue carrying servo commands from every control source to the main loop
+///
+/// HTTP, serial and any other source all go through this one path, so a
+/// command from any of them interrupts or replaces the current one the same
+/// way. Commands are applied in the order they were sent; unlike a `Signal`,
+/// a command from one task can't silently overwrite another task's.
+///
+/// There are two ways in, differing only when the queue is full:
+/// [`send_command`] drops the oldest queued command and never blocks, which
+/// suits live control where the newest input matters most; [`queue_command`]
+/// waits for room, so nothing already queued is lost. Back-to-back
+/// [`ServoAction::Set`]s for one servo from one source are still coalesced to
+/// the last by the main loop; use [`ServoAction::Step`] for moves that must
+/// each be made.
+pub static SERVO_COMMAND: Channel<CriticalSectionRawMutex, ServoCommand, SERVO_COMMAND_DEPTH> = Channel::new();
+
+/// Queue `command` for the main loop, waiting for room if the queue is full
+///
+/// For scripted sequences, where dropping a move would be worse than the
+/// sender stalling. Don't call it from tasks that must stay responsive.
+pub async fn queue_command(command: ServoCommand) {
+    SERVO_COMMAND.send(command).await;
+}
+
+/// Queue `command` for the main loop
+///
+/// Never blocks: if the queue is full, the oldest queued command is discarded
+/// to make room, since a newer command is the more relevant one.
+pub fn send_command(command: ServoCommand) {
+    if let Err(TrySendError::Full(command)) = SERVO_COMMAND.try_send(command) {
+        if let Ok(dropped) = SERVO_COMMAND.try_receive() {
+            log::warn!("Command queue full, dropping oldest ({} for servo {})", dropped.source.as_str(), dropped.servo);
+        }
+        let _ = SERVO_COMMAND.try_send(command);
+    }
+}
+
+/// How long an applied command blocks lower-priority commands for the same servo
+pub const PRIORITY_HOLD: Duration = Duration::from_millis(500);
+
+/// Drops commands that would override a recent higher-priority command
+///
+/// Each applied command "holds" its servo for [`PRIORITY_HOLD`]; during that
+/// window only commands of equal or higher priority (or detaches) are admitted.
+pub struct PriorityArbiter(arbiter::PriorityArbiter<MAX_SERVOS>);
+
+impl PriorityArbiter {
+    pub const fn new() -> Self {
+        Self(arbiter::PriorityArbiter::new(PRIORITY_HOLD.as_millis()))
+    }
+
+    /// Decide whether `command` may be applied now, recording the hold if so
+    pub fn admit(&mut self, command: &ServoCommand, now: Instant) -> bool {
+        self.0.admit(command.servo as usize, command.source.priority(), Self::always(command), now.as_millis())
+    }
+
+    /// Whether [`admit`](Self::admit) would let `command` through now,
+    /// without recording anything
+    pub fn would_admit(&self, command: &ServoCommand, now: Instant) -> bool {
+        self.0.would_admit(command.servo as usize, command.source.priority(), Self::always(command), now.as_millis())
+    }
+
+    /// Detaching only ever makes the servo safer, so it is never held off
+    fn always(command: &ServoCommand) -> bool {
+        command.action == ServoAction::Detach
+    }
+}
+
+impl Default for PriorityArbiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Minimum time a servo must stay put before it may move in the opposite
+/// direction (0 = disabled)
+///
+/// Protects gear trains that shouldn't be slammed back and forth. Unlike rate
+/// limiting this only affects reversals; moves that continue in the same
+/// direction are always allowed.
+pub const REVERSE_DWELL_MS: u64 = 0;
+
+/// Rejects moves that would reverse a servo within [`REVERSE_DWELL_MS`]
+pub struct ReverseDwell(arbiter::ReverseDwell<MAX_SERVOS>);
+
+impl ReverseDwell {
+    pub const fn new() -> Self {
+        Self(arbiter::ReverseDwell::new(REVERSE_DWELL_MS))
+    }
+
+    /// Decide whether `servo` may move from `from` to `to` now, recording the
+    /// move if so
+    pub fn admit(&mut self, servo: usize, from: u8, to: u8, now: Instant) -> bool {
+        self.0.admit(servo, from, to, now.as_millis())
+    }
+}
+
+impl Default for ReverseDwell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What happens to move commands received while the emergency stop is engaged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EstopPolicy {
+    /// Refuse them; HTTP answers `423 Locked`
+    Reject,
+    /// Accept them but discard them without telling the client
+    Drop,
+    /// Keep the most recent one and apply it when the stop is cleared
+    ///
+    /// Clearing the stop then moves the servo straight away, possibly to a
+    /// position commanded long before; only use this where that is safe.
+    QueueLatest,
+}
+
+/// Policy applied to move commands while the emergency stop is engaged
+pub const ESTOP_POLICY: EstopPolicy = EstopPolicy::Reject;
+
+/// Latest move received during an emergency stop under [`EstopPolicy::QueueLatest`]
+static ESTOP_QUEUE: Mutex<CriticalSectionRawMutex, Cell<Option<ServoCommand>>> =
+    Mutex::new(Cell::new(None));
+
+/// Hold `command` until the emergency stop is cleared, replacing any older one
+pub fn queue_during_estop(command: ServoCommand) {
+    ESTOP_QUEUE.lock(|queue| queue.set(Some(command)));
+}
+
+/// Take the command held during the emergency stop, if any
+pub fn take_estop_queue() -> Option<ServoCommand> {
+    ESTOP_QUEUE.lock(|queue| queue.take())
+}
@@ -0,0 +1,74 @@
+use esp_hal::analog::adc::{Adc, AdcPin};
+use esp_hal::peripherals::ADC1;
+use esp_hal::Blocking;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::command::{CommandSource, ServoCommand, send_command};
+use crate::state;
+
+/// Sense pin taken by [`current_monitor_task`]; must be the pin
+/// `sense_pin!` in `main.rs` takes
+#[cfg(feature = "esp32")]
+pub type SensePin = esp_hal::peripherals::GPIO34<'static>;
+#[cfg(feature = "esp32c3")]
+pub type SensePin = esp_hal::peripherals::GPIO4<'static>;
+
+/// Raw ADC reading (12-bit, 11dB attenuation) above which the servo is
+/// considered overloaded
+///
+/// For a shunt of R ohms and a stall current of I amps the sense voltage is
+/// I * R; with 11dB attenuation one count is roughly 0.8mV. E.g. a 0.1 ohm
+/// shunt and a 1A limit give 100mV, about 125 counts (add an amplifier stage
+/// for better resolution).
+pub const OVERLOAD_THRESHOLD_RAW: u16 = 1500;
+
+/// How long the current must stay above the threshold before tripping
+pub const OVERLOAD_TRIP_TIME: Duration = Duration::from_millis(300);
+
+/// Interval between ADC samples
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Servo whose supply current is sensed
+const MONITORED_SERVO: u8 = 0;
+
+/// Watch the servo supply current and trip a latched fault on sustained overload
+///
+/// On trip the servo is detached and further moves are refused until the
+/// fault is cleared with `POST /fault/clear`.
+#[embassy_executor::task]
+pub async fn current_monitor_task(
+    mut adc: Adc<'static, ADC1<'static>, Blocking>,
+    mut pin: AdcPin<SensePin, ADC1<'static>>,
+) {
+    log::info!(
+        "Current monitor: threshold {} raw, trip after {} ms",
+        OVERLOAD_THRESHOLD_RAW,
+        OVERLOAD_TRIP_TIME.as_millis()
+    );
+
+    let mut over_since: Option<Instant> = None;
+
+    loop {
+        Timer::after(SAMPLE_INTERVAL).await;
+
+        let raw = loop {
+            match adc.read_oneshot(&mut pin) {
+                Ok(raw) => break raw,
+                Err(_) => Timer::after(Duration::from_micros(50)).await,
+            }
+        };
+
+        if state::is_faulted() || raw < OVERLOAD_THRESHOLD_RAW {
+            over_since = None;
+            continue;
+        }
+
+        let since = *over_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= OVERLOAD_TRIP_TIME {
+            log::warn!("Overload: current {} raw for {} ms, detaching servo", raw, since.elapsed().as_millis());
+            state::set_fault(true);
+            send_command(ServoCommand::detach(MONITORED_SERVO, CommandSource::Auto));
+            over_since = None;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use esp_hal::rtc_cntl::{reset_reason, SocResetReason};
+use esp_hal::system::Cpu;
+use esp_println::println;
+
+/// Why the chip last reset, grouped into the causes that matter remotely
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn,
+    Software,
+    Watchdog,
+    BrownOut,
+    DeepSleep,
+    Other,
+}
+
+impl ResetReason {
+    /// Name used in logs and JSON responses
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "power-on",
+            ResetReason::Software => "software",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::BrownOut => "brown-out",
+            ResetReason::DeepSleep => "deep-sleep",
+            ResetReason::Other => "other",
+        }
+    }
+
+    fn from_soc(reason: SocResetReason) -> Self {
+        match reason {
+            SocResetReason::ChipPowerOn => ResetReason::PowerOn,
+            SocResetReason::CoreSw | SocResetReason::Cpu0Sw => ResetReason::Software,
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt => ResetReason::Watchdog,
+            SocResetReason::SysBrownOut => ResetReason::BrownOut,
+            SocResetReason::CoreDeepSleep => ResetReason::DeepSleep,
+            _ => ResetReason::Other,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ResetReason::PowerOn,
+            1 => ResetReason::Software,
+            2 => ResetReason::Watchdog,
+            3 => ResetReason::BrownOut,
+            4 => ResetReason::DeepSleep,
+            _ => ResetReason::Other,
+        }
+    }
+}
+
+/// Reset reason captured at boot
+static RESET_REASON: AtomicU8 = AtomicU8::new(ResetReason::Other as u8);
+
+/// Read the reset reason register and store it, along with the message of
+/// a panic that caused it; call once at boot
+pub fn record_reset_reason() -> ResetReason {
+    let reason = reset_reason(Cpu::ProCpu)
+        .map(ResetReason::from_soc)
+        .unwrap_or(ResetReason::Other);
+    RESET_REASON.store(reason as u8, Ordering::Relaxed);
+
+    // SAFETY: single-threaded at boot, before anything can panic again
+    let record = unsafe { core::ptr::read_volatile(&raw const PANIC_RECORD) };
+    // RTC memory is only kept across resets; after power-on it is garbage
+    if record.magic == PANIC_MAGIC && reason == ResetReason::Software {
+        LAST_PANIC.lock(|last| *last.borrow_mut() = record);
+    }
+    // SAFETY: as above
+    unsafe { core::ptr::write_volatile(&raw mut PANIC_RECORD.magic, 0) };
+    reason
+}
+
+/// Reset reason captured at boot by [`record_reset_reason`]
+pub fn reset_reason_at_boot() -> ResetReason {
+    ResetReason::from_u8(RESET_REASON.load(Ordering::Relaxed))
+}
+
+/// Heap bytes `(used, total)`; the total is `HEAP_SIZE` in `main.rs`
+pub fn heap_usage() -> (usize, usize) {
+    let used = esp_alloc::HEAP.used();
+    (used, used + esp_alloc::HEAP.free())
+}
+
+/// Log heap usage, e.g. `Heap: 12KB used / 72KB total`
+pub fn log_heap() {
+    let (used, total) = heap_usage();
+    log::info!("Heap: {}KB used / {}KB total", used / 1024, total / 1024);
+}
+
+/// Marks a valid [`PanicRecord`] ("PANC")
+const PANIC_MAGIC: u32 = 0x5041_4E43;
+
+/// Longest panic message kept, in bytes
+const MAX_PANIC_LEN: usize = 192;
+
+/// A panic message, as saved across the reset that follows it
+#[derive(Clone, Copy)]
+struct PanicRecord {
+    magic: u32,
+    len: usize,
+    message: [u8; MAX_PANIC_LEN],
+}
+
+// SAFETY: plain integers and bytes, valid for any bit pattern; `len` is
+// clamped before use
+unsafe impl esp_hal::Persistable for PanicRecord {}
+
+impl PanicRecord {
+    const EMPTY: Self = Self { magic: 0, len: 0, message: [0; MAX_PANIC_LEN] };
+}
+
+/// Appends to a [`PanicRecord`], silently dropping what doesn't fit
+impl Write for PanicRecord {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(MAX_PANIC_LEN - self.len);
+        self.message[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Written by the panic handler; RTC fast memory survives the software reset
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut PANIC_RECORD: PanicRecord = PanicRecord::EMPTY;
+
+/// The panic that caused the last reset, copied out of RTC memory at boot
+static LAST_PANIC: Mutex<CriticalSectionRawMutex, RefCell<PanicRecord>> = Mutex::new(RefCell::new(PanicRecord::EMPTY));
+
+/// Message and location of the panic that caused the last reset, if it was one
+pub fn last_panic() -> Option<alloc::string::String> {
+    LAST_PANIC.lock(|last| {
+        let last = last.borrow();
+        if last.magic != PANIC_MAGIC {
+            return None;
+        }
+        // The message may have been cut mid-character
+        let bytes = &last.message[..last.len.min(MAX_PANIC_LEN)];
+        let text = core::str::from_utf8(bytes).unwrap_or_else(|e| core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""));
+        Some(alloc::string::String::from(text))
+    })
+}
+
+/// Print the panic, save it for [`last_panic`] and restart
+///
+/// Replaces esp-backtrace's handler, which halts: an unattended controller
+/// should come back on its own, and report why it went away.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("\n====================== PANIC ======================");
+    println!("{}", info);
+    let mut record = PanicRecord { magic: PANIC_MAGIC, ..PanicRecord::EMPTY };
+    let _ = write!(record, "{}", info);
+    // SAFETY: nothing else runs once the handler has been entered
+    unsafe { core::ptr::write_volatile(&raw mut PANIC_RECORD, record) };
+    println!("Restarting");
+    esp_hal::system::software_reset()
+}
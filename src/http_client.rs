@@ -0,0 +1,83 @@
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::{ConnectError, Error as TcpError, TcpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::Duration;
+pub use servo_core::url::{parse_url, Url};
+
+/// Timeout for connecting, sending and reading the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum HttpClientError {
+    /// Only `http://host[:port][/path]` URLs are supported
+    InvalidUrl,
+    /// Host name could not be resolved
+    Dns,
+    Connect(ConnectError),
+    Io(TcpError),
+    /// Response did not start with a valid HTTP status line
+    BadResponse,
+}
+
+/// Resolve `host`, accepting IPv4 literals without a DNS query
+async fn resolve(stack: Stack<'static>, host: &str) -> Result<IpAddress, HttpClientError> {
+    if let Ok(addr) = host.parse::<Ipv4Address>() {
+        return Ok(IpAddress::Ipv4(addr));
+    }
+    let addrs = stack
+        .dns_query(host, DnsQueryType::A)
+        .await
+        .map_err(|_| HttpClientError::Dns)?;
+    addrs.first().copied().ok_or(HttpClientError::Dns)
+}
+
+/// Write all of `bytes`, handling partial writes
+async fn write_all(socket: &mut TcpSocket<'_>, mut bytes: &[u8]) -> Result<(), TcpError> {
+    while !bytes.is_empty() {
+        let written = socket.write(bytes).await?;
+        bytes = &bytes[written..];
+    }
+    Ok(())
+}
+
+/// POST a JSON `body` to `url` and return the response status code
+///
+/// The response body is not read. `rx_buffer`/`tx_buffer` back the socket and
+/// can be small (a few hundred bytes) since the request is streamed out.
+pub async fn post_json(
+    stack: Stack<'static>,
+    url: &str,
+    body: &str,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+) -> Result<u16, HttpClientError> {
+    let url = parse_url(url).ok_or(HttpClientError::InvalidUrl)?;
+    let addr = resolve(stack, url.host).await?;
+
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    socket.set_timeout(Some(REQUEST_TIMEOUT));
+    socket
+        .connect(IpEndpoint::new(addr, url.port))
+        .await
+        .map_err(HttpClientError::Connect)?;
+
+    let request = alloc::format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        body.len(),
+        body
+    );
+    write_all(&mut socket, request.as_bytes()).await.map_err(HttpClientError::Io)?;
+
+    // Only the status line matters: "HTTP/1.1 200 ..."
+    let mut response = [0u8; 16];
+    let n = socket.read(&mut response).await.map_err(HttpClientError::Io)?;
+    socket.close();
+
+    core::str::from_utf8(&response[..n])
+        .ok()
+        .and_then(|line| line.get(9..12))
+        .and_then(|code| code.parse().ok())
+        .ok_or(HttpClientError::BadResponse)
+}
@@ -1,145 +1,1187 @@
-use embassy_net::tcp::TcpSocket;
-use embassy_net::Stack;
-use embassy_time::Duration;
-use esp_println::println;
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
-
-/// Buffer sizes for HTTP server
-const RX_BUFFER_SIZE: usize = 1024;
-const TX_BUFFER_SIZE: usize = 1024;
-
-/// Signal for servo angle updates
-pub static SERVO_ANGLE: Signal<CriticalSectionRawMutex, u8> = Signal::new();
-
-/// Simple HTTP response builder
-fn build_response(status: &str, content_type: &str, body: &str) -> alloc::string::String {
+use core::sync::atomic::Ordering;
+
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use portable_atomic::AtomicU32;
+use servo_core::buffers::BufferPool;
+pub use servo_core::buffers::ConnectionBuffers;
+use servo_core::http::{find_header, headers_end, json_escape, parse_request};
+use servo_core::router::{
+    self, accepts_html, authorized, build_response, cors_headers, json_ok, method_not_allowed, not_found,
+    oversize_response, serve_static, status_code, unauthorized, wants_keep_alive, with_connection_header, Device,
+    PathMatch, Request, Route, FAVICON_PATH, KEEP_ALIVE_IDLE_TIMEOUT_MS, MAX_REQUESTS_PER_CONNECTION, SERVO_ROUTES,
+};
+pub use servo_core::router::ANGLE_FIELD;
+use servo_core::url::form_param;
+use static_cell::ConstStaticCell;
+
+use crate::command::{
+    allowed_range, apply_limits, check_ready, take_estop_queue, CommandSource, EstopPolicy, ServoAction, ServoCommand,
+    ESTOP_POLICY, MAX_SERVOS, PUBLIC_MAX_ANGLE, PUBLIC_MIN_ANGLE, send_command,
+};
+use crate::diag;
+use crate::move_log;
+use crate::nvs;
+use crate::ota::{OtaError, OtaWriter};
+use crate::power;
+use crate::presets::{self, PresetError};
+use crate::sntp;
+use crate::state::{self, NotReady};
+
+/// Number of connection handlers serving requests concurrently
+///
+/// Each handler owns one socket and one [`ConnectionBuffers`] from the static
+/// pool (about 3 KB); every extra handler needs one more socket in the
+/// `StackResources` in `main.rs`. The pool is static, so it doesn't come out
+/// of the 72 KB heap; only each response `String` (at most a few hundred
+/// bytes, except the control page) is heap-allocated per handler.
+pub const HTTP_WORKERS: usize = 3;
+
+/// Connection handlers serving the fallback access point, with a pool of
+/// their own
+pub const AP_HTTP_WORKERS: usize = 1;
+
+/// Every connection handler, station and access point
+pub const HTTP_HANDLERS: usize = HTTP_WORKERS + AP_HTTP_WORKERS;
+
+/// One set of buffers per handler, sized at compile time
+static STATION_BUFFERS: ConstStaticCell<BufferPool<HTTP_WORKERS>> = ConstStaticCell::new(BufferPool::new());
+static AP_BUFFERS: ConstStaticCell<BufferPool<AP_HTTP_WORKERS>> = ConstStaticCell::new(BufferPool::new());
+
+/// Take the buffers of the station handlers, one set per worker
+///
+/// Each pool can be taken only once (a second take panics), so no two
+/// handlers can ever alias the same memory.
+pub fn take_station_buffers() -> [&'static mut ConnectionBuffers; HTTP_WORKERS] {
+    STATION_BUFFERS.take().take_all()
+}
+
+/// Take the buffers of the access point handlers, one set per worker
+pub fn take_ap_buffers() -> [&'static mut ConnectionBuffers; AP_HTTP_WORKERS] {
+    AP_BUFFERS.take().take_all()
+}
+
+/// Optional pause after each served connection before accepting the next one
+///
+/// Not needed in normal operation; leave at 0 for best back-to-back latency.
+const POST_CONNECTION_DELAY_MS: u64 = 0;
+
+/// How long a connection may sit idle waiting for a request to start
+///
+/// Applies before the first byte of a request arrives, so it is the one to
+/// raise for clients that hold a connection open before sending.
+const REQUEST_IDLE_TIMEOUT_MS: u64 = 10_000;
+
+/// How long to wait for the rest of a request once it has started
+///
+/// Kept short so a client that stalls mid-request frees the handler quickly;
+/// raise it for slow mobile links.
+const REQUEST_READ_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest one connection may stay open over all its requests (0 = no limit)
+///
+/// Kept-alive connections are otherwise only bounded by
+/// [`MAX_REQUESTS_PER_CONNECTION`]; an `/events` stream is exempt.
+const CONNECTION_TIMEOUT_MS: u64 = 30_000;
+
+/// Timeouts of a connection handler, passed to [`http_server_task`]
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    /// Wait for the first request on a new connection
+    pub idle_ms: u64,
+    /// Wait for each further chunk once a request has started
+    pub read_ms: u64,
+    /// Wait for the next request on a kept-alive connection
+    pub keep_alive_idle_ms: u64,
+    /// Total time a connection may stay open (0 = no limit)
+    pub connection_ms: u64,
+}
+
+/// Timeouts from the constants above, used by every handler by default
+pub const TIMEOUTS: Timeouts = Timeouts {
+    idle_ms: REQUEST_IDLE_TIMEOUT_MS,
+    read_ms: REQUEST_READ_TIMEOUT_MS,
+    keep_alive_idle_ms: KEEP_ALIVE_IDLE_TIMEOUT_MS,
+    connection_ms: CONNECTION_TIMEOUT_MS,
+};
+
+/// Time allowed for a response to reach the client before a requested reboot
+const REBOOT_FLUSH_MS: u64 = 200;
+
+/// Pause after a failed `accept` so a persistent error can't busy-loop,
+/// doubling on each consecutive failure up to the max
+const ACCEPT_ERROR_BACKOFF_MS: u64 = 100;
+const ACCEPT_ERROR_BACKOFF_MAX_MS: u64 = 5_000;
+
+/// Bearer token required on routes that move the servos or change settings
+/// (`api_token` in `cfg.toml`; unset = no authentication)
+///
+/// Requests must send `Authorization: Bearer <token>`. This keeps casual LAN
+/// clients out, but the token travels in plain HTTP.
+pub const API_TOKEN: Option<&str> = option_env!("API_TOKEN");
+
+/// Interval of the comment line sent on an idle `GET /events` stream, which
+/// keeps proxies from timing it out and detects clients that have gone away
+const EVENTS_PING_MS: u64 = 15_000;
+
+/// Bytes per chunk when streaming a page with [`write_chunked`]; at most
+/// one TX buffer's worth, so each chunk goes out in a single write
+const STREAM_CHUNK_SIZE: usize = 512;
+
+/// Control page served at `GET /` and `/index.html`
+///
+/// Moves servo 0 with `POST /servo` and polls `GET /servo/` for the live
+/// angle. With [`API_TOKEN`] set, open it as `/?token=<token>` so its moves
+/// are authorized. Reads the `angle` field, so update it if [`ANGLE_FIELD`] changes.
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>ESP32 Servo</title>
+<style>
+body { font-family: sans-serif; max-width: 28em; margin: 2em auto; padding: 0 1em; }
+input[type=range] { width: 100%; }
+button { font-size: 1em; padding: 0.4em 1em; margin-right: 0.5em; }
+#error { color: #b00; }
+</style>
+</head>
+<body>
+<h1>ESP32 Servo</h1>
+<p>Current angle: <strong id="angle">?</strong>&deg;</p>
+<input id="slider" type="range" min="0" max="180" value="90">
+<p>
+<button onclick="move(0)">0&deg;</button>
+<button onclick="move(90)">90&deg;</button>
+<button onclick="move(180)">180&deg;</button>
+</p>
+<p id="error"></p>
+<script>
+const slider = document.getElementById("slider");
+const error = document.getElementById("error");
+let dragging = false;
+async function move(angle) {
+  try {
+    const token = new URLSearchParams(location.search).get("token") || "";
+    const r = await fetch("/servo", { method: "POST", headers: { "Content-Type": "application/json", "Authorization": "Bearer " + token }, body: JSON.stringify({ angle: angle }) });
+    const body = await r.json();
+    error.textContent = r.ok ? "" : body.error;
+  } catch (e) {
+    error.textContent = "Device unreachable";
+  }
+}
+async function poll() {
+  try {
+    const body = await (await fetch("/servo/")).json();
+    document.getElementById("angle").textContent = body.angle;
+    if (!dragging) slider.value = body.angle;
+  } catch (e) {}
+  setTimeout(poll, 500);
+}
+slider.addEventListener("input", () => { dragging = true; });
+slider.addEventListener("change", () => { dragging = false; move(Number(slider.value)); });
+poll();
+</script>
+</body>
+</html>
+"#;
+
+/// WiFi setup page, served at `GET /wifi` and, while the fallback access
+/// point is open, at `GET /` for browsers
+///
+/// Submits to `POST /wifi`; like the control page, open it with
+/// `?token=<token>` when [`API_TOKEN`] is set.
+const WIFI_SETUP_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Servo WiFi setup</title>
+<style>
+body { font-family: sans-serif; max-width: 24em; margin: 2em auto; padding: 0 1em; }
+input, button { display: block; width: 100%; margin: 0.5em 0; font-size: 1.1em; }
+#result { min-height: 1.2em; }
+</style>
+</head>
+<body>
+<h1>WiFi setup</h1>
+<form id="form">
+<input name="ssid" placeholder="Network name" maxlength="32" required>
+<input name="password" type="password" placeholder="Password (blank for an open network)" maxlength="64">
+<button type="submit">Save and reboot</button>
+</form>
+<p id="result"></p>
+<script>
+document.getElementById("form").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const result = document.getElementById("result");
+  const token = new URLSearchParams(location.search).get("token") || "";
+  try {
+    const r = await fetch("/wifi", { method: "POST", headers: { "Authorization": "Bearer " + token }, body: new URLSearchParams(new FormData(event.target)) });
+    const body = await r.json();
+    result.textContent = r.ok ? "Saved. The device is rebooting and will join " + body.ssid + "." : body.error;
+  } catch (e) {
+    result.textContent = "Device unreachable";
+  }
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Body served at `GET /` to clients that ask for JSON rather than HTML
+const INDEX_BODY: &str = r#"{"status": "ok", "message": "ESP32 Servo Controller", "endpoints": ["/servo/<angle>", "/servo?angle=<0-180>"]}"#;
+
+/// Build the JSON body for `GET /status` (also pushed by the telemetry task)
+///
+/// The top-level `angle`/`last_source` describe servo 0; `servos` lists all.
+pub fn build_status_json() -> alloc::string::String {
+    let (heap_used, heap_total) = diag::heap_usage();
+    let mut servos = alloc::string::String::new();
+    for servo in 0..MAX_SERVOS {
+        if servo > 0 {
+            servos.push_str(", ");
+        }
+        let (hw_min, hw_max) = state::limits(servo);
+        servos.push_str(&alloc::format!(
+            r#"{{"{}": {}, "target": {}, "moving": {}, "last_source": {}, "public_range": [{}, {}], "hardware_range": [{}, {}]}}"#,
+            ANGLE_FIELD,
+            state::current_angle(servo),
+            state::target_angle(servo),
+            state::is_moving(servo),
+            source_json(state::last_source(servo)),
+            PUBLIC_MIN_ANGLE,
+            PUBLIC_MAX_ANGLE,
+            hw_min,
+            hw_max
+        ));
+    }
+    let readiness = state::readiness();
     alloc::format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        status,
-        content_type,
-        body.len(),
-        body
+        r#"{{"{}": {}, "last_source": {}, "servos": [{}], "ready": {}, "not_ready_reason": {}, "fault": {}, "estop": {}, "sweep_duration_ms": {}, "net": {}, "uptime_ms": {}, "unix_time": {}, "time": {}, "heap_free": {}, "heap_used": {}, "heap_total": {}}}"#,
+        ANGLE_FIELD,
+        state::current_angle(0),
+        source_json(state::last_source(0)),
+        servos,
+        readiness.is_ok(),
+        match readiness {
+            Ok(()) => alloc::string::String::from("null"),
+            Err(reason) => alloc::format!(r#""{}""#, reason.as_str()),
+        },
+        state::is_faulted(),
+        state::is_estopped(),
+        state::sweep_duration_ms(),
+        build_net_json(),
+        embassy_time::Instant::now().as_millis(),
+        match sntp::unix_time() {
+            Some(unix_s) => alloc::format!("{}", unix_s),
+            None => alloc::string::String::from("null"),
+        },
+        match sntp::unix_time() {
+            Some(unix_s) => alloc::format!(r#""{}""#, sntp::iso8601(unix_s)),
+            None => alloc::string::String::from("null"),
+        },
+        heap_total - heap_used,
+        heap_used,
+        heap_total
     )
 }
 
-/// Parse the HTTP request and extract the method and path
-fn parse_request(request: &str) -> Option<(&str, &str)> {
-    let first_line = request.lines().next()?;
-    let mut parts = first_line.split_whitespace();
-    let method = parts.next()?;
-    let path = parts.next()?;
-    Some((method, path))
+/// Build the JSON body for `GET /diag/servo`: the hardware mapping and live
+/// state of every servo
+fn build_servo_diag_json() -> alloc::string::String {
+    let mut servos = alloc::string::String::new();
+    for servo in 0..MAX_SERVOS {
+        if servo > 0 {
+            servos.push_str(", ");
+        }
+        let hardware = match state::hardware(servo) {
+            Some(hw) => alloc::format!(
+                r#""gpio": {}, "channel": {}, "timer": {}"#,
+                hw.gpio, hw.channel, hw.timer
+            ),
+            None => alloc::string::String::from(r#""gpio": null, "channel": null, "timer": null"#),
+        };
+        servos.push_str(&alloc::format!(
+            r#"{{"servo": {}, {}, "{}": {}, "attached": {}}}"#,
+            servo,
+            hardware,
+            ANGLE_FIELD,
+            state::current_angle(servo),
+            state::is_attached(servo)
+        ));
+    }
+    alloc::format!(r#"{{"servos": [{}]}}"#, servos)
+}
+
+/// Build the JSON body for `GET /diag/moves`: recent moves, oldest first
+fn build_moves_json() -> alloc::string::String {
+    let (entries, total) = move_log::snapshot();
+    let mut moves = alloc::string::String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            moves.push_str(", ");
+        }
+        moves.push_str(&alloc::format!(
+            r#"{{"at_ms": {}, "servo": {}, "{}": {}, "source": "{}"}}"#,
+            entry.at_ms,
+            entry.servo,
+            ANGLE_FIELD,
+            entry.angle,
+            entry.source.as_str()
+        ));
+    }
+    alloc::format!(r#"{{"total_moves": {}, "moves": [{}]}}"#, total, moves)
+}
+
+/// Build the JSON describing the network connection
+pub fn build_net_json() -> alloc::string::String {
+    let rssi = match state::rssi() {
+        Some(dbm) => alloc::format!("{}", dbm),
+        None => alloc::string::String::from("null"),
+    };
+    match state::ip_address() {
+        Some(ip) => alloc::format!(r#"{{"connected": true, "ip": "{}", "rssi_dbm": {}}}"#, ip, rssi),
+        None => alloc::format!(r#"{{"connected": false, "ip": null, "rssi_dbm": {}}}"#, rssi),
+    }
+}
+
+/// Responses sent since boot, by status class (`1xx` to `5xx`)
+static RESPONSES_BY_CLASS: [AtomicU32; 5] = [const { AtomicU32::new(0) }; 5];
+
+/// Count a response for `GET /metrics`
+fn count_response(status: u16) {
+    if let Some(counter) = (status / 100).checked_sub(1).and_then(|class| RESPONSES_BY_CLASS.get(class as usize)) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Build the body for `GET /metrics` in the Prometheus text format
+fn build_metrics() -> alloc::string::String {
+    use core::fmt::Write;
+
+    let mut out = alloc::string::String::new();
+    let (heap_used, heap_total) = diag::heap_usage();
+    let _ = write!(
+        out,
+        "# HELP servo_uptime_seconds Time since boot.\n# TYPE servo_uptime_seconds gauge\nservo_uptime_seconds {}\n",
+        Instant::now().as_secs()
+    );
+    out.push_str("# HELP servo_http_responses_total HTTP responses sent since boot, by status class.\n");
+    out.push_str("# TYPE servo_http_responses_total counter\n");
+    for (class, counter) in RESPONSES_BY_CLASS.iter().enumerate() {
+        let _ = writeln!(out, "servo_http_responses_total{{class=\"{}xx\"}} {}", class + 1, counter.load(Ordering::Relaxed));
+    }
+    out.push_str("# HELP servo_angle_degrees Angle each servo was last driven to.\n# TYPE servo_angle_degrees gauge\n");
+    for servo in 0..MAX_SERVOS {
+        let _ = writeln!(out, "servo_angle_degrees{{servo=\"{}\"}} {}", servo, state::current_angle(servo));
+    }
+    let _ = write!(
+        out,
+        "# HELP servo_heap_free_bytes Free heap.\n# TYPE servo_heap_free_bytes gauge\nservo_heap_free_bytes {}\n",
+        heap_total - heap_used
+    );
+    // Absent while disconnected, rather than a made-up value
+    if let Some(dbm) = state::rssi() {
+        let _ = write!(out, "# HELP servo_wifi_rssi_dbm WiFi signal strength.\n# TYPE servo_wifi_rssi_dbm gauge\nservo_wifi_rssi_dbm {}\n", dbm);
+    }
+    out
+}
+
+/// JSON value for an optional command source
+fn source_json(source: Option<CommandSource>) -> alloc::string::String {
+    match source {
+        Some(source) => alloc::format!(r#""{}""#, source.as_str()),
+        None => alloc::string::String::from("null"),
+    }
+}
+
+/// JSON body listing all stored presets
+fn presets_json() -> alloc::string::String {
+    let mut items = alloc::string::String::new();
+    for (i, preset) in presets::list().iter().enumerate() {
+        if i > 0 {
+            items.push_str(", ");
+        }
+        items.push_str(&alloc::format!(
+            r#"{{"name": "{}", "{}": {}}}"#,
+            json_escape(&preset.name),
+            ANGLE_FIELD,
+            preset.angle
+        ));
+    }
+    alloc::format!(r#"{{"presets": [{}]}}"#, items)
 }
 
-/// Parse angle from path like /servo/90 or /servo?angle=90
-fn parse_servo_angle(path: &str) -> Option<u8> {
-    // Try path format: /servo/90
-    if let Some(angle_str) = path.strip_prefix("/servo/") {
-        return angle_str.parse().ok();
+/// Handle the preset endpoints under /servo/preset/<name> and /servo/presets
+///
+/// Returns `None` if `path` is not a preset route.
+fn handle_preset(method: &str, path: &str) -> Option<alloc::string::String> {
+    if path == "/servo/presets" {
+        return Some(match method {
+            "GET" => build_response("200 OK", "application/json", &presets_json()),
+            _ => method_not_allowed(),
+        });
     }
-    
-    // Try query format: /servo?angle=90
-    if path.starts_with("/servo?") || path.starts_with("/servo?") {
-        for part in path.split('?').nth(1)?.split('&') {
-            if let Some(value) = part.strip_prefix("angle=") {
-                return value.parse().ok();
+
+    let name = path.strip_prefix("/servo/preset/")?;
+    Some(match method {
+        // Recall: move servo 0 to the stored angle
+        "GET" => match presets::get(name) {
+            Some(angle) => router::handle_servo_set(&Board, 0, angle),
+            None => preset_not_found(),
+        },
+        // Save servo 0's current angle
+        "POST" => match presets::save(name, state::current_angle(0)) {
+            Ok(()) => {
+                nvs::request_save();
+                build_response("200 OK", "application/json", &presets_json())
+            }
+            Err(PresetError::InvalidName) => {
+                let body = r#"{"error": "Preset names are 1-16 characters of A-Z, a-z, 0-9, '-' or '_'"}"#;
+                build_response("400 Bad Request", "application/json", body)
+            }
+            Err(PresetError::Full) => {
+                let body = r#"{"error": "Preset storage is full"}"#;
+                build_response("507 Insufficient Storage", "application/json", body)
+            }
+        },
+        "DELETE" => {
+            if presets::remove(name) {
+                nvs::request_save();
+                build_response("200 OK", "application/json", &presets_json())
+            } else {
+                preset_not_found()
             }
         }
+        _ => method_not_allowed(),
+    })
+}
+
+/// Handle `POST /config/reset`, `/config/sweep-duration` (GET) and
+/// `/config/sweep-duration/<ms>` (POST)
+///
+/// Returns `None` if `path` is not a config route.
+fn handle_config(method: &str, path: &str) -> Option<alloc::string::String> {
+    if path == "/config/reset" {
+        if method != "POST" {
+            return Some(method_not_allowed());
+        }
+        return Some(match nvs::clear() {
+            Ok(()) => {
+                // Put the live settings back to the defaults too, or the next
+                // save would write them straight back
+                nvs::Config::default().apply();
+                for servo in 0..MAX_SERVOS {
+                    state::set_limits_configured(servo, false);
+                    let action = ServoAction::Trim(0);
+                    send_command(ServoCommand { servo: servo as u8, action, source: CommandSource::Http });
+                }
+                log::info!("Stored config erased");
+                let body = r#"{"reset": true, "note": "Limits return to the compiled-in values after a reboot"}"#;
+                build_response("200 OK", "application/json", body)
+            }
+            Err(e) => {
+                log::error!("Failed to erase config: {:?}", e);
+                let body = r#"{"error": "Could not erase stored config"}"#;
+                build_response("500 Internal Server Error", "application/json", body)
+            }
+        });
     }
-    
+
+    let rest = path.strip_prefix("/config/sweep-duration")?;
+    Some(match (method, rest.strip_prefix('/')) {
+        ("GET", None) if rest.is_empty() => {
+            let body = alloc::format!(r#"{{"sweep_duration_ms": {}}}"#, state::sweep_duration_ms());
+            build_response("200 OK", "application/json", &body)
+        }
+        ("POST", Some(ms)) => match ms.parse::<u32>() {
+            Ok(ms) if ms <= state::MAX_SWEEP_DURATION_MS => {
+                state::set_sweep_duration_ms(ms);
+                nvs::request_save();
+                log::info!("Default sweep duration set to {} ms", ms);
+                let body = alloc::format!(r#"{{"sweep_duration_ms": {}}}"#, ms);
+                build_response("200 OK", "application/json", &body)
+            }
+            _ => {
+                let body = alloc::format!(
+                    r#"{{"error": "Sweep duration must be 0-{} ms"}}"#,
+                    state::MAX_SWEEP_DURATION_MS
+                );
+                build_response("400 Bad Request", "application/json", &body)
+            }
+        },
+        (_, None) if !rest.is_empty() => return None,
+        _ => method_not_allowed(),
+    })
+}
+
+/// Engage or clear the emergency stop
+///
+/// Engaging detaches the servos. Clearing applies the command held under
+/// the `QueueLatest` policy, if any.
+fn handle_estop(engage: bool) -> alloc::string::String {
+    state::set_estop(engage);
+    if engage {
+        log::warn!("Emergency stop engaged");
+        take_estop_queue();
+        for servo in 0..MAX_SERVOS {
+            send_command(ServoCommand::detach(servo as u8, CommandSource::Auto));
+        }
+    } else {
+        log::info!("Emergency stop cleared");
+        if let Some(command) = take_estop_queue() {
+            send_command(command);
+        }
+    }
+    let body = alloc::format!(r#"{{"estop": {}}}"#, engage);
+    build_response("200 OK", "application/json", &body)
+}
+
+/// Handle `POST /factory-reset?confirm=<word>`: erase all stored config and
+/// reboot once the response is sent
+fn handle_factory_reset(query: Option<&str>) -> alloc::string::String {
+    let confirmed = query
+        .and_then(|q| form_param(q, "confirm"))
+        .is_some_and(|word| word == nvs::FACTORY_RESET_CONFIRMATION);
+    if !confirmed {
+        let body = alloc::format!(
+            r#"{{"error": "Confirmation required. POST /factory-reset?confirm={}"}}"#,
+            nvs::FACTORY_RESET_CONFIRMATION
+        );
+        return build_response("400 Bad Request", "application/json", &body);
+    }
+    match nvs::factory_reset() {
+        Ok(()) => {
+            log::warn!("Factory reset: cleared {:?}, rebooting", nvs::FACTORY_RESET_CLEARS);
+            state::request_reboot();
+            let cleared = nvs::FACTORY_RESET_CLEARS
+                .iter()
+                .map(|item| alloc::format!(r#""{}""#, item))
+                .collect::<alloc::vec::Vec<_>>()
+                .join(", ");
+            let body = alloc::format!(r#"{{"cleared": [{}], "rebooting": true}}"#, cleared);
+            build_response("200 OK", "application/json", &body)
+        }
+        Err(e) => {
+            log::error!("Factory reset failed: {:?}", e);
+            let body = r#"{"error": "Could not erase stored config"}"#;
+            build_response("500 Internal Server Error", "application/json", body)
+        }
+    }
+}
+
+fn preset_not_found() -> alloc::string::String {
+    let body = r#"{"error": "Preset not found"}"#;
+    build_response("404 Not Found", "application/json", body)
+}
+
+/// Upper bound for `/diag/delay/<ms>`
+#[cfg(feature = "diag-delay")]
+const MAX_DIAG_DELAY_MS: u64 = 30_000;
+
+/// Delay requested via `GET /diag/delay/<ms>` (testing builds only), clamped
+/// to `MAX_DIAG_DELAY_MS`
+#[cfg(feature = "diag-delay")]
+fn diag_delay(path: &str) -> Option<u64> {
+    let ms: u64 = path.strip_prefix("/diag/delay/")?.parse().ok()?;
+    Some(ms.min(MAX_DIAG_DELAY_MS))
+}
+
+/// The delay endpoint does not exist without the `diag-delay` feature
+#[cfg(not(feature = "diag-delay"))]
+fn diag_delay(_path: &str) -> Option<u64> {
     None
 }
 
-/// Handle an incoming HTTP request and return a response
-fn handle_request(request: &str) -> alloc::string::String {
-    let Some((method, path)) = parse_request(request) else {
-        return build_response("400 Bad Request", "text/plain", "Bad Request");
+/// The firmware's own routes, tried before [`SERVO_ROUTES`]; see
+/// [`router::handle_request`] for how a route is picked. More specific
+/// entries must come before prefixes that cover them.
+static ROUTES: &[Route] = &[
+    Route { method: None, path: PathMatch::Exact("/servo/presets"), auth: false, handler: route_presets },
+    Route { method: None, path: PathMatch::Prefix("/servo/preset/"), auth: true, handler: route_presets },
+    Route { method: None, path: PathMatch::Prefix("/config/"), auth: true, handler: route_config },
+    Route { method: Some("POST"), path: PathMatch::Exact("/fault/clear"), auth: true, handler: route_fault_clear },
+    Route { method: Some("POST"), path: PathMatch::Exact("/factory-reset"), auth: true, handler: route_factory_reset },
+    Route { method: Some("GET"), path: PathMatch::Exact("/wifi"), auth: false, handler: route_wifi_form },
+    Route { method: Some("POST"), path: PathMatch::Exact("/wifi"), auth: true, handler: route_wifi_save },
+    Route { method: Some("POST"), path: PathMatch::Exact("/reboot"), auth: true, handler: route_reboot },
+    Route { method: Some("POST"), path: PathMatch::Exact("/estop"), auth: false, handler: |_| handle_estop(true) },
+    Route { method: Some("POST"), path: PathMatch::Exact("/estop/clear"), auth: true, handler: |_| handle_estop(false) },
+    Route { method: Some("GET"), path: PathMatch::Exact("/"), auth: false, handler: route_index },
+    Route { method: Some("GET"), path: PathMatch::Exact("/index.html"), auth: false, handler: route_index },
+    Route { method: Some("GET"), path: PathMatch::Exact("/diag/reset"), auth: false, handler: route_diag_reset },
+    Route { method: Some("GET"), path: PathMatch::Exact("/lastfault"), auth: false, handler: route_last_fault },
+    Route {
+        method: Some("GET"),
+        path: PathMatch::Exact("/metrics"),
+        auth: false,
+        handler: |_| build_response("200 OK", "text/plain; version=0.0.4", &build_metrics()),
+    },
+    Route { method: Some("GET"), path: PathMatch::Exact("/diag/moves"), auth: false, handler: |_| json_ok(&build_moves_json()) },
+    Route { method: Some("GET"), path: PathMatch::Exact("/diag/servo"), auth: false, handler: |_| json_ok(&build_servo_diag_json()) },
+    Route { method: Some("GET"), path: PathMatch::Prefix("/diag/delay/"), auth: false, handler: route_diag_delay },
+];
+
+/// The firmware's shared state and command queue, as the routes in
+/// [`router`] see them
+pub struct Board;
+
+impl Device for Board {
+    fn servo_count(&self) -> usize {
+        MAX_SERVOS
+    }
+
+    fn current_angle(&self, servo: usize) -> u8 {
+        state::current_angle(servo)
+    }
+
+    fn target_angle(&self, servo: usize) -> u8 {
+        state::target_angle(servo)
+    }
+
+    fn is_moving(&self, servo: usize) -> bool {
+        state::is_moving(servo)
+    }
+
+    fn check_ready(&self) -> Result<(), NotReady> {
+        check_ready()
+    }
+
+    fn allowed_range(&self, servo: usize) -> (u8, u8) {
+        allowed_range(servo)
+    }
+
+    fn apply_limits(&self, servo: usize, angle: u8) -> Result<u8, (u8, u8)> {
+        apply_limits(servo, angle)
+    }
+
+    fn moves_held(&self) -> bool {
+        state::is_estopped() && ESTOP_POLICY == EstopPolicy::QueueLatest
+    }
+
+    fn send(&self, command: ServoCommand) {
+        send_command(command);
+    }
+
+    fn status_json(&self) -> alloc::string::String {
+        build_status_json()
+    }
+
+    fn api_token(&self) -> Option<&str> {
+        API_TOKEN
+    }
+}
+
+/// Handle an incoming HTTP request: [`ROUTES`] first, then [`SERVO_ROUTES`]
+fn handle_request(raw: &str) -> alloc::string::String {
+    router::handle_request(raw, &Board, &[ROUTES, SERVO_ROUTES])
+}
+
+/// Build the JSON body for reading one servo's angle (`GET /servo/`)
+pub fn build_angle_json(servo: usize) -> alloc::string::String {
+    router::build_angle_json(&Board, servo)
+}
+
+fn route_presets(request: &Request) -> alloc::string::String {
+    handle_preset(request.method, request.path).unwrap_or_else(|| not_found(request.path))
+}
+
+fn route_config(request: &Request) -> alloc::string::String {
+    handle_config(request.method, request.path).unwrap_or_else(|| not_found(request.path))
+}
+
+fn route_fault_clear(_: &Request) -> alloc::string::String {
+    state::set_fault(false);
+    log::info!("Overload fault cleared");
+    json_ok(r#"{"fault": false}"#)
+}
+
+fn route_factory_reset(request: &Request) -> alloc::string::String {
+    handle_factory_reset(request.query)
+}
+
+/// Restart the device once the response has been sent
+///
+/// The server loop sees the request, closes the connection, waits
+/// `REBOOT_FLUSH_MS` for the response to leave and then resets.
+fn route_reboot(_: &Request) -> alloc::string::String {
+    log::info!("Reboot requested over HTTP");
+    state::request_reboot();
+    json_ok(r#"{"rebooting": true}"#)
+}
+
+/// The HTML control page (or the WiFi setup page while the fallback access
+/// point is open) for browsers; the JSON status for other clients at `/`
+fn route_index(request: &Request) -> alloc::string::String {
+    if request.path == "/" && !accepts_html(request.raw) {
+        serve_static(request.raw, "application/json", INDEX_BODY)
+    } else if request.path == "/" && state::ap_active() {
+        serve_static(request.raw, "text/html; charset=utf-8", WIFI_SETUP_HTML)
+    } else {
+        serve_static(request.raw, "text/html; charset=utf-8", INDEX_HTML)
+    }
+}
+
+fn route_wifi_form(request: &Request) -> alloc::string::String {
+    serve_static(request.raw, "text/html; charset=utf-8", WIFI_SETUP_HTML)
+}
+
+/// `POST /wifi` with a form body `ssid=<ssid>&password=<password>`: store the
+/// network in flash and reboot to join it
+fn route_wifi_save(request: &Request) -> alloc::string::String {
+    // Form bodies use query-string encoding; each pair is decoded on its own,
+    // so a password may contain `&` or `=`
+    let ssid = form_param(request.body, "ssid").unwrap_or_default();
+    let password = form_param(request.body, "password").unwrap_or_default();
+    let (ssid, password) = (ssid.as_str(), password.as_str());
+    if let Err(error) = nvs::check_wifi_credentials(ssid, password) {
+        let body = alloc::format!(r#"{{"error": "{}"}}"#, error);
+        return build_response("400 Bad Request", "application/json", &body);
+    }
+    log::info!("WiFi network set to {:?}, rebooting to join it", ssid);
+    state::set_wifi_credentials(Some((alloc::string::String::from(ssid), alloc::string::String::from(password))));
+    // Written by the reboot path in the server loop
+    nvs::request_save();
+    state::request_reboot();
+    let body = alloc::format!(r#"{{"ssid": "{}", "rebooting": true}}"#, json_escape(ssid));
+    json_ok(&body)
+}
+
+fn route_diag_reset(_: &Request) -> alloc::string::String {
+    json_ok(&alloc::format!(r#"{{"reset_reason": "{}"}}"#, diag::reset_reason_at_boot().as_str()))
+}
+
+fn route_last_fault(_: &Request) -> alloc::string::String {
+    let panic = match diag::last_panic() {
+        Some(message) => alloc::format!(r#""{}""#, json_escape(&message)),
+        None => alloc::string::String::from("null"),
     };
+    json_ok(&alloc::format!(r#"{{"reset_reason": "{}", "panic": {}}}"#, diag::reset_reason_at_boot().as_str(), panic))
+}
 
-    println!("HTTP {} {}", method, path);
+fn route_diag_delay(request: &Request) -> alloc::string::String {
+    match diag_delay(request.path) {
+        Some(ms) => json_ok(&alloc::format!(r#"{{"delayed_ms": {}}}"#, ms)),
+        None => not_found(request.path),
+    }
+}
 
-    match method {
-        "GET" => {
-            if path == "/" {
-                let body = r#"{"status": "ok", "message": "ESP32 Servo Controller", "endpoints": ["/servo/<angle>", "/servo?angle=<0-180>"]}"#;
-                build_response("200 OK", "application/json", body)
-            } else if path == "/health" {
-                let body = r#"{"healthy": true}"#;
-                build_response("200 OK", "application/json", body)
-            } else if path.starts_with("/servo") {
-                if let Some(angle) = parse_servo_angle(path) {
-                    if angle <= 180 {
-                        SERVO_ANGLE.signal(angle);
-                        let body = alloc::format!(r#"{{"angle": {}}}"#, angle);
-                        build_response("200 OK", "application/json", &body)
-                    } else {
-                        let body = r#"{"error": "Angle must be between 0 and 180"}"#;
-                        build_response("400 Bad Request", "application/json", body)
+/// Read one request's headers, and the body their `Content-Length` announces,
+/// into `buf`, returning its length
+///
+/// TCP may split a request across any number of segments, so reads are
+/// accumulated until the blank line ending the headers (and the body) has
+/// arrived or `buf` is full. Waits up to `idle_timeout_ms` for the request
+/// to start and then up to `read_timeout_ms` for each further chunk.
+/// Returns `None` if the client closes early, times out or the read fails.
+async fn read_request(socket: &mut TcpSocket<'_>, buf: &mut [u8], idle_timeout_ms: u64, read_timeout_ms: u64) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let timeout_ms = if len == 0 { idle_timeout_ms } else { read_timeout_ms };
+        match with_timeout(Duration::from_millis(timeout_ms), socket.read(&mut buf[len..])).await {
+            Ok(Ok(0)) => {
+                log::debug!("Client disconnected");
+                return None;
+            }
+            Ok(Ok(n)) => {
+                len += n;
+                if let Some((end, body_start)) = headers_end(&buf[..len]) {
+                    // Headers are complete; wait for the body they announce
+                    let headers = core::str::from_utf8(&buf[..end]).unwrap_or("");
+                    let body_len = find_header(headers, "Content-Length").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+                    if len >= body_start + body_len {
+                        return Some(len);
                     }
-                } else {
-                    let body = r#"{"error": "Missing or invalid angle parameter. Use /servo/90 or /servo?angle=90"}"#;
-                    build_response("400 Bad Request", "application/json", body)
                 }
-            } else {
-                let body = r#"{"error": "Not Found"}"#;
-                build_response("404 Not Found", "application/json", body)
+                if len == buf.len() {
+                    return Some(len);
+                }
+            }
+            Ok(Err(e)) => {
+                log_socket_error("read", e);
+                return None;
+            }
+            Err(_) => {
+                let phase = if len == 0 { "idle" } else { "read" };
+                log::info!("Request {} timeout after {} ms, dropping the connection", phase, timeout_ms);
+                return None;
+            }
+        }
+    }
+}
+
+/// Log one served request: request line, client, status and the time spent
+/// building and writing the response; also counts it for `/metrics`
+///
+/// e.g. `2024-05-01T12:00:00Z HTTP GET /servo/90 from 192.168.1.5 -> 200 in 3 ms`,
+/// with uptime (`+42s`) in place of the time until the clock is synced
+fn log_request(request: &str, remote: Option<IpEndpoint>, status: u16, elapsed: Duration) {
+    count_response(status);
+    let (method, path) = parse_request(request).unwrap_or(("-", "-"));
+    if status == 204 && path == FAVICON_PATH {
+        return;
+    }
+    match remote {
+        Some(remote) => log::info!(
+            "{} HTTP {} {} from {} -> {} in {} ms",
+            sntp::timestamp(),
+            method,
+            path,
+            remote.addr,
+            status,
+            elapsed.as_millis()
+        ),
+        None => log::info!("{} HTTP {} {} -> {} in {} ms", sntp::timestamp(), method, path, status, elapsed.as_millis()),
+    }
+}
+
+/// Log a failed socket read or write
+///
+/// A reset is the client closing its tab or script mid-request, which is
+/// routine, so it gets a one-line note instead of an error.
+fn log_socket_error(op: &str, e: tcp::Error) {
+    match e {
+        tcp::Error::ConnectionReset => log::debug!("Client closed the connection during {}", op),
+        #[allow(unreachable_patterns)]
+        e => log::warn!("{} error: {:?}", op, e),
+    }
+}
+
+/// Write all of `bytes`, continuing after partial writes
+async fn write_all(socket: &mut TcpSocket<'_>, bytes: &[u8]) -> Result<(), tcp::Error> {
+    let mut offset = 0;
+    while offset < bytes.len() {
+        offset += socket.write(&bytes[offset..]).await?;
+    }
+    Ok(())
+}
+
+/// Write a response with `Transfer-Encoding: chunked`, one HTTP chunk per
+/// slice of `chunks`
+///
+/// Nothing is buffered beyond the status line and headers, so a page larger
+/// than the heap or the TX buffer can be streamed straight from flash. Empty
+/// slices are skipped, since an empty chunk would end the body early.
+async fn write_chunked<'c>(
+    socket: &mut TcpSocket<'_>,
+    status: &str,
+    content_type: &str,
+    chunks: impl IntoIterator<Item = &'c [u8]>,
+    keep_alive: bool,
+) -> Result<(), tcp::Error> {
+    let head = alloc::format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\n{}\r\n",
+        status,
+        content_type,
+        cors_headers()
+    );
+    let head = with_connection_header(head, keep_alive);
+    write_all(socket, head.as_bytes()).await?;
+    for chunk in chunks.into_iter().filter(|chunk| !chunk.is_empty()) {
+        write_all(socket, alloc::format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+        write_all(socket, chunk).await?;
+        write_all(socket, b"\r\n").await?;
+    }
+    write_all(socket, b"0\r\n\r\n").await
+}
+
+/// Whether `request` opens the server-sent event stream (`GET /events`)
+fn is_event_stream(request: &str) -> bool {
+    parse_request(request).is_some_and(|(method, path)| {
+        method.eq_ignore_ascii_case("GET") && path.split(['?', '#']).next() == Some("/events")
+    })
+}
+
+/// Serve `GET /events`: push each servo's angle as a server-sent event
+/// whenever it changes, until the client goes away
+///
+/// Occupies this handler for as long as the client stays connected. The
+/// stream is never reused for other requests, so the connection closes when
+/// it ends.
+async fn stream_events(socket: &mut TcpSocket<'_>) -> Result<(), tcp::Error> {
+    let Some(mut moved) = state::MOVED.receiver() else {
+        let response = build_response("503 Service Unavailable", "application/json", r#"{"error": "Too many event streams"}"#);
+        return write_all(socket, with_connection_header(response, false).as_bytes()).await;
+    };
+    let head = alloc::format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n{}Connection: keep-alive\r\n\r\n",
+        cors_headers()
+    );
+    write_all(socket, head.as_bytes()).await?;
+
+    // `None` forces every servo's current angle out first
+    let mut sent = [None; MAX_SERVOS];
+    loop {
+        for (servo, last) in sent.iter_mut().enumerate() {
+            let angle = state::current_angle(servo);
+            if *last != Some(angle) {
+                let event = alloc::format!("data: {{\"servo\": {}, \"{}\": {}}}\n\n", servo, ANGLE_FIELD, angle);
+                write_all(socket, event.as_bytes()).await?;
+                *last = Some(angle);
             }
         }
-        _ => {
-            let body = r#"{"error": "Method Not Allowed"}"#;
-            build_response("405 Method Not Allowed", "application/json", body)
+        if let Either::Second(()) = select(moved.changed(), Timer::after(Duration::from_millis(EVENTS_PING_MS))).await {
+            write_all(socket, b": ping\n\n").await?;
         }
     }
 }
 
-/// Run the HTTP server on port 80
-#[embassy_executor::task]
-pub async fn http_server_task(stack: Stack<'static>) {
-    let mut rx_buffer = [0u8; RX_BUFFER_SIZE];
-    let mut tx_buffer = [0u8; TX_BUFFER_SIZE];
+/// Length of the headers of `buf` if it starts a `POST /ota` upload
+///
+/// Checked on the raw bytes, since the firmware body isn't text.
+fn ota_upload(buf: &[u8]) -> Option<usize> {
+    let (_, header_len) = headers_end(buf)?;
+    let head = core::str::from_utf8(&buf[..header_len]).ok()?;
+    let (method, path) = parse_request(head)?;
+    (method.eq_ignore_ascii_case("POST") && path.split(['?', '#']).next() == Some("/ota")).then_some(header_len)
+}
+
+/// Bytes between two OTA progress log lines
+const OTA_PROGRESS_STEP: u32 = 128 * 1024;
+
+fn ota_error(e: OtaError) -> alloc::string::String {
+    log::warn!("OTA: update failed: {:?}", e);
+    match e {
+        OtaError::TooLarge { size, capacity } => {
+            let body = alloc::format!(r#"{{"error": "Image is {} bytes, the OTA partition holds {}"}}"#, size, capacity);
+            build_response("413 Payload Too Large", "application/json", &body)
+        }
+        OtaError::InvalidImage(reason) => {
+            let body = alloc::format!(r#"{{"error": "Invalid firmware image: {}"}}"#, reason);
+            build_response("400 Bad Request", "application/json", &body)
+        }
+        OtaError::Unavailable | OtaError::Partition(_) => build_response(
+            "500 Internal Server Error",
+            "application/json",
+            r#"{"error": "No OTA partition available; flash with partitions.csv"}"#,
+        ),
+    }
+}
+
+/// Serve `POST /ota`: stream the body into the inactive OTA partition, then
+/// switch to it and request a reboot
+///
+/// `buf[..len]` holds what [`read_request`] has read, headers included; it is
+/// reused as the receive buffer for the rest of the image.
+async fn receive_firmware(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    len: usize,
+    header_len: usize,
+    read_timeout_ms: u64,
+) -> alloc::string::String {
+    let head = core::str::from_utf8(&buf[..header_len]).unwrap_or("");
+    if !authorized(head, API_TOKEN) {
+        return unauthorized();
+    }
+    let Some(size) = find_header(head, "Content-Length").and_then(|v| v.parse::<u32>().ok()) else {
+        return build_response("411 Length Required", "application/json", r#"{"error": "Content-Length required"}"#);
+    };
+    let mut writer = match OtaWriter::begin(size) {
+        Ok(writer) => writer,
+        Err(e) => return ota_error(e),
+    };
+    log::info!("OTA: receiving {} bytes", size);
+
+    let first = &buf[header_len..len.min(header_len + size as usize)];
+    let mut received = first.len() as u32;
+    if let Err(e) = writer.write(first) {
+        return ota_error(e);
+    }
+    while received < size {
+        let want = ((size - received) as usize).min(buf.len());
+        let n = match with_timeout(Duration::from_millis(read_timeout_ms), socket.read(&mut buf[..want])).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => {
+                log::warn!("OTA: upload stopped after {} of {} bytes", received, size);
+                return build_response("400 Bad Request", "application/json", r#"{"error": "Upload incomplete"}"#);
+            }
+        };
+        if let Err(e) = writer.write(&buf[..n]) {
+            return ota_error(e);
+        }
+        if (received + n as u32) / OTA_PROGRESS_STEP != received / OTA_PROGRESS_STEP {
+            log::info!("OTA: {} / {} bytes", received + n as u32, size);
+        }
+        received += n as u32;
+    }
+    match writer.finish() {
+        Ok(()) => {
+            log::info!("OTA: image verified, rebooting into it");
+            state::request_reboot();
+            json_ok(&alloc::format!(r#"{{"updated": true, "bytes": {}, "rebooting": true}}"#, size))
+        }
+        Err(e) => ota_error(e),
+    }
+}
+
+/// The page to stream with [`write_chunked`] instead of routing normally:
+/// a plain HTTP/1.1 GET of the control or WiFi setup page
+///
+/// Range requests and HTTP/1.0 clients, which can't take a chunked body, go
+/// through [`ROUTES`] and get the page in one piece.
+fn streamed_page(request: &str) -> Option<&'static str> {
+    let (method, path) = parse_request(request)?;
+    let http_11 = request.lines().next().is_some_and(|line| line.ends_with("HTTP/1.1"));
+    if !method.eq_ignore_ascii_case("GET") || !http_11 || find_header(request, "Range").is_some() {
+        return None;
+    }
+    match path {
+        "/" if !accepts_html(request) => None,
+        "/" if state::ap_active() => Some(WIFI_SETUP_HTML),
+        "/" | "/index.html" => Some(INDEX_HTML),
+        "/wifi" => Some(WIFI_SETUP_HTML),
+        _ => None,
+    }
+}
+
+/// Run one HTTP connection handler on `port`
+///
+/// Spawn one per worker, each with its own buffers from
+/// [`take_station_buffers`] or [`take_ap_buffers`], usually with [`TIMEOUTS`].
+#[embassy_executor::task(pool_size = HTTP_HANDLERS)]
+pub async fn http_server_task(stack: Stack<'static>, port: u16, buffers: &'static mut ConnectionBuffers, timeouts: Timeouts) {
+    let ConnectionBuffers { rx: rx_buffer, tx: tx_buffer, request: buf } = buffers;
+    let mut accept_backoff_ms = ACCEPT_ERROR_BACKOFF_MS;
 
     loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(Duration::from_secs(10)));
-
-        println!("HTTP server listening on port 80...");
-
-        if let Err(e) = socket.accept(80).await {
-            println!("Accept error: {:?}", e);
-            continue;
-        }
-
-        println!("Client connected");
-
-        let mut buf = [0u8; RX_BUFFER_SIZE];
-        match socket.read(&mut buf).await {
-            Ok(0) => {
-                println!("Client disconnected");
-            }
-            Ok(n) => {
-                if let Ok(request) = core::str::from_utf8(&buf[..n]) {
-                    let response = handle_request(request);
-                    let mut offset = 0;
-                    let bytes = response.as_bytes();
-                    while offset < bytes.len() {
-                        match socket.write(&bytes[offset..]).await {
-                            Ok(written) => offset += written,
-                            Err(e) => {
-                                println!("Write error: {:?}", e);
-                                break;
+        // While WiFi reconnects there is nothing to accept on; wait quietly
+        // instead of spinning on accept errors. The socket is recreated
+        // afterwards, so nothing from the old link is reused
+        if !stack.is_config_up() {
+            log::info!("HTTP server waiting for network...");
+            stack.wait_config_up().await;
+            log::info!("Network up, HTTP server resuming");
+        }
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
+        // Transport-level backstop; the request timeouts are applied in `read_request`
+        socket.set_timeout(Some(Duration::from_millis(timeouts.idle_ms.max(timeouts.read_ms))));
+
+        log::debug!("HTTP server listening on port {}...", port);
+
+        match select(socket.accept(port), stack.wait_config_down()).await {
+            Either::First(Ok(())) => accept_backoff_ms = ACCEPT_ERROR_BACKOFF_MS,
+            Either::First(Err(e)) => {
+                log::warn!("Accept error: {:?}, retrying in {} ms", e, accept_backoff_ms);
+                embassy_time::Timer::after(Duration::from_millis(accept_backoff_ms)).await;
+                accept_backoff_ms = (accept_backoff_ms * 2).min(ACCEPT_ERROR_BACKOFF_MAX_MS);
+                continue;
+            }
+            // Drop the listener with the link; the loop waits for it to return
+            Either::Second(()) => {
+                socket.abort();
+                continue;
+            }
+        }
+
+        log::debug!("Client connected");
+        let opened = Instant::now();
+
+        // Serve requests on this connection until it is closed, fails, idles
+        // out or reaches the per-connection cap
+        for served in 1..=MAX_REQUESTS_PER_CONNECTION {
+            // The peer is unreachable once the link drops
+            if !stack.is_config_up() {
+                break;
+            }
+            let mut idle_timeout_ms = if served == 1 { timeouts.idle_ms } else { timeouts.keep_alive_idle_ms };
+            if timeouts.connection_ms > 0 {
+                let open_ms = opened.elapsed().as_millis();
+                if open_ms >= timeouts.connection_ms {
+                    log::info!("Connection timeout after {} ms, closing", open_ms);
+                    break;
+                }
+                idle_timeout_ms = idle_timeout_ms.min(timeouts.connection_ms - open_ms);
+            }
+            let Some(n) = read_request(&mut socket, &mut buf[..], idle_timeout_ms, timeouts.read_ms).await else {
+                break;
+            };
+            power::note_activity();
+            // Firmware images are binary and far larger than the buffer
+            if let Some(header_len) = ota_upload(&buf[..n]) {
+                let started = Instant::now();
+                let response = with_connection_header(receive_firmware(&mut socket, &mut buf[..], n, header_len, timeouts.read_ms).await, false);
+                log_request("POST /ota HTTP/1.1", socket.remote_endpoint(), status_code(&response), started.elapsed());
+                if let Err(e) = write_all(&mut socket, response.as_bytes()).await {
+                    log_socket_error("write", e);
+                }
+                break;
+            }
+            let Ok(request) = core::str::from_utf8(&buf[..n]) else {
+                break;
+            };
+            // The buffer filled before the headers or the announced body
+            // ended: answer that rather than parsing a truncated request
+            let oversize = oversize_response(request);
+            let body_complete = oversize.is_none();
+            // The rest of an oversized request is still unread, so the
+            // connection can't be reused
+            let keep_alive = body_complete
+                && served < MAX_REQUESTS_PER_CONNECTION
+                && wants_keep_alive(request)
+                && !state::reboot_requested();
+            let started = Instant::now();
+            if let Some(ms) = parse_request(request).and_then(|(_, path)| diag_delay(path)) {
+                embassy_time::Timer::after(Duration::from_millis(ms)).await;
+            }
+            if body_complete && is_event_stream(request) {
+                log::info!("HTTP GET /events from {:?}: streaming", socket.remote_endpoint().map(|remote| remote.addr));
+                if let Either::First(Err(e)) = select(stream_events(&mut socket), stack.wait_config_down()).await {
+                    log_socket_error("event stream", e);
+                }
+                break;
+            }
+            let (status, written) = match streamed_page(request).filter(|_| body_complete) {
+                Some(page) => {
+                    let chunks = page.as_bytes().chunks(STREAM_CHUNK_SIZE);
+                    (200, write_chunked(&mut socket, "200 OK", "text/html; charset=utf-8", chunks, keep_alive).await)
+                }
+                None => {
+                    let response = match oversize {
+                        Some(response) => {
+                            if status_code(&response) == 431 {
+                                log::warn!("Request headers exceed {} bytes", buf.len());
+                            } else {
+                                log::warn!("Request body exceeds {} bytes", buf.len());
                             }
+                            response
                         }
-                    }
+                        None => handle_request(request),
+                    };
+                    let response = with_connection_header(response, keep_alive);
+                    (status_code(&response), write_all(&mut socket, response.as_bytes()).await)
                 }
+            };
+            log_request(request, socket.remote_endpoint(), status, started.elapsed());
+            if let Err(e) = written {
+                log_socket_error("write", e);
+                break;
             }
-            Err(e) => {
-                println!("Read error: {:?}", e);
+            if !keep_alive {
+                break;
             }
         }
 
-        socket.close();
-        // Small delay before accepting next connection
-        embassy_time::Timer::after(Duration::from_millis(100)).await;
+        if stack.is_config_up() {
+            socket.close();
+        } else {
+            // No FIN handshake is possible without a link
+            socket.abort();
+        }
+        if state::reboot_requested() {
+            // Give the stack a moment to flush the response before restarting
+            embassy_time::Timer::after(Duration::from_millis(REBOOT_FLUSH_MS)).await;
+            if let Err(e) = nvs::flush() {
+                log::error!("Failed to save config before reboot: {:?}", e);
+            }
+            esp_hal::system::software_reset();
+        }
+        if POST_CONNECTION_DELAY_MS > 0 {
+            embassy_time::Timer::after(Duration::from_millis(POST_CONNECTION_DELAY_MS)).await;
+        }
     }
 }
@@ -1,7 +1,36 @@
 #![no_std]
+// Settings are plain constants, so a check against one that is at its
+// default (`0`, `false`'s range end, ...) is expected to be constant
+#![allow(clippy::absurd_extreme_comparisons, clippy::unnecessary_min_or_max)]
+
+#[cfg(not(any(feature = "esp32", feature = "esp32c3")))]
+compile_error!("enable a chip feature: `esp32` or `esp32c3`");
+#[cfg(all(feature = "esp32", feature = "esp32c3"))]
+compile_error!("enable only one chip feature: `esp32` or `esp32c3`");
 
 extern crate alloc;
 
+pub mod activity_led;
+pub mod analog;
+pub mod buttons;
+pub mod command;
+pub mod current_monitor;
+pub mod diag;
+pub mod http_client;
 pub mod http_server;
+pub use servo_core::json;
+pub mod liveness;
+pub mod mdns;
+pub mod move_log;
+pub mod nvs;
+pub mod ota;
+pub mod park;
+pub mod power;
+pub mod presets;
+#[cfg(feature = "serial")]
 pub mod serial_cmd;
 pub mod servo;
+pub mod sntp;
+pub mod state;
+pub mod telemetry;
+pub mod webhook;
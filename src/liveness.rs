@@ -0,0 +1,112 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::peripherals::TIMG1;
+use esp_hal::timer::timg::{MwdtStage, Wdt};
+
+use crate::command::{MAX_SERVOS, SERVO_COMMAND};
+use crate::state;
+
+/// How long the servo consume loop may go without running while it has work
+/// (queued commands or a sweep in progress) before it is considered stalled
+/// (0 = monitor disabled)
+pub const LIVENESS_TIMEOUT_MS: u32 = 5_000;
+
+/// Restart the device when a stall is detected instead of only logging it
+pub const LIVENESS_RESET_ON_STALL: bool = false;
+
+/// Seconds without a feed before the hardware watchdog resets the chip
+/// (0 = watchdog disabled)
+pub const WATCHDOG_TIMEOUT_S: u64 = 30;
+
+/// Uptime in milliseconds when the consume loop last ran
+static LAST_TICK_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Record that the consume loop is running; call once per iteration and per
+/// sweep step
+pub fn tick() {
+    LAST_TICK_MS.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+}
+
+/// Milliseconds since the consume loop last ran
+pub fn since_last_tick_ms() -> u32 {
+    (Instant::now().as_millis() as u32).wrapping_sub(LAST_TICK_MS.load(Ordering::Relaxed))
+}
+
+/// Whether the consume loop has anything to do
+fn has_work() -> bool {
+    !SERVO_COMMAND.is_empty() || (0..MAX_SERVOS).any(state::is_moving)
+}
+
+/// Whether the consume loop has work but hasn't run within
+/// [`LIVENESS_TIMEOUT_MS`]
+fn is_stalled() -> bool {
+    LIVENESS_TIMEOUT_MS > 0 && has_work() && since_last_tick_ms() > LIVENESS_TIMEOUT_MS
+}
+
+/// Watch the consume loop and report when it stops making progress
+///
+/// An idle loop sleeping on an empty queue is healthy, so only a loop that has
+/// work and hasn't ticked within [`LIVENESS_TIMEOUT_MS`] counts as stalled.
+/// This is separate from the HTTP timeouts: a stalled loop still answers
+/// requests, but its moves never happen.
+#[embassy_executor::task]
+pub async fn liveness_task() {
+    log::info!("Liveness: consume loop stall threshold {} ms", LIVENESS_TIMEOUT_MS);
+    let mut stalled = false;
+    loop {
+        Timer::after(Duration::from_millis((LIVENESS_TIMEOUT_MS / 2).max(1) as u64)).await;
+        let idle_ms = since_last_tick_ms();
+        if is_stalled() {
+            if !stalled {
+                log::warn!(
+                    "servo consume loop stalled ({} ms since last run, {} commands queued)",
+                    idle_ms,
+                    SERVO_COMMAND.len()
+                );
+                stalled = true;
+            }
+            if LIVENESS_RESET_ON_STALL {
+                log::error!("Liveness: restarting");
+                Timer::after(Duration::from_millis(100)).await;
+                esp_hal::system::software_reset();
+            }
+        } else if stalled && idle_ms <= LIVENESS_TIMEOUT_MS {
+            log::info!("Liveness: consume loop running again");
+            stalled = false;
+        }
+    }
+}
+
+/// Feed the TIMG1 hardware watchdog while the firmware is healthy
+///
+/// Feeding stops, and the chip resets [`WATCHDOG_TIMEOUT_S`] later, when:
+/// - the executor is wedged (a task blocks without yielding, so this one never
+///   runs), which covers the network stack and HTTP workers sharing it
+/// - the servo consume loop in `main.rs` is stalled, by the same test as
+///   [`liveness_task`]
+///
+/// The consume loop itself can't feed the watchdog, as it sleeps for as long
+/// as no commands arrive.
+#[embassy_executor::task]
+pub async fn watchdog_task(mut wdt: Wdt<TIMG1<'static>>) {
+    wdt.set_timeout(MwdtStage::Stage0, esp_hal::time::Duration::from_secs(WATCHDOG_TIMEOUT_S));
+    wdt.enable();
+    log::info!("Watchdog: reset after {} s without a feed", WATCHDOG_TIMEOUT_S);
+    let mut starving = false;
+    loop {
+        if is_stalled() {
+            if !starving {
+                log::warn!("Watchdog: consume loop stalled, no longer feeding");
+                starving = true;
+            }
+        } else {
+            if starving {
+                log::info!("Watchdog: feeding again");
+                starving = false;
+            }
+            wdt.feed();
+        }
+        Timer::after(Duration::from_secs((WATCHDOG_TIMEOUT_S / 3).max(1))).await;
+    }
+}
@@ -0,0 +1,104 @@
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use servo_core::mdns::{asks_for_us, TYPE_A};
+
+use crate::state;
+
+/// Host name advertised over mDNS, without `.local` (`mdns_hostname` in
+/// `cfg.toml`)
+pub const MDNS_HOSTNAME: &str = match option_env!("MDNS_HOSTNAME") {
+    Some(hostname) => hostname,
+    None => "esp-servo",
+};
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+
+/// Seconds a resolver may cache our address
+const RECORD_TTL_S: u32 = 120;
+
+const CLASS_IN: u16 = 1;
+/// Set on a record's class to tell resolvers to replace older cached copies
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+
+/// Buffer sizes for the responder socket
+const RX_BUFFER_SIZE: usize = 512;
+const TX_BUFFER_SIZE: usize = 256;
+
+/// Build an mDNS response carrying our A record
+fn build_answer(id: u16, address: Ipv4Address) -> alloc::vec::Vec<u8> {
+    let mut packet = alloc::vec::Vec::with_capacity(64);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Authoritative response, no questions, one answer
+    packet.extend_from_slice(&[0x84, 0x00, 0, 0, 0, 1, 0, 0, 0, 0]);
+    for label in MDNS_HOSTNAME.split('.').chain(core::iter::once("local")) {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_A.to_be_bytes());
+    packet.extend_from_slice(&(CLASS_IN | CLASS_CACHE_FLUSH).to_be_bytes());
+    packet.extend_from_slice(&RECORD_TTL_S.to_be_bytes());
+    packet.extend_from_slice(&4u16.to_be_bytes());
+    packet.extend_from_slice(&address.octets());
+    packet
+}
+
+/// Answer mDNS queries for `<MDNS_HOSTNAME>.local` with the station address
+///
+/// Announces the address whenever the network comes up, so resolvers learn
+/// the new one after a WiFi reconnect or a DHCP change without waiting for
+/// their cache to expire.
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; RX_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; TX_BUFFER_SIZE];
+    let mut packet = [0u8; RX_BUFFER_SIZE];
+    let group = IpEndpoint::new(IpAddress::Ipv4(MDNS_GROUP), MDNS_PORT);
+
+    loop {
+        stack.wait_config_up().await;
+        let Some(address) = state::ip_address() else {
+            embassy_time::Timer::after(embassy_time::Duration::from_secs(1)).await;
+            continue;
+        };
+        // Already joined after a reconnect is fine
+        let _ = stack.join_multicast_group(MDNS_GROUP);
+
+        let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        if let Err(e) = socket.bind(MDNS_PORT) {
+            log::warn!("mDNS: bind failed: {:?}", e);
+            return;
+        }
+        log::info!("mDNS: advertising {}.local at {}", MDNS_HOSTNAME, address);
+        if let Err(e) = socket.send_to(&build_answer(0, address), group).await {
+            log::warn!("mDNS: announcement failed: {:?}", e);
+        }
+
+        loop {
+            match select(socket.recv_from(&mut packet), stack.wait_config_down()).await {
+                Either::First(Ok((len, meta))) => {
+                    if !asks_for_us(&packet[..len], MDNS_HOSTNAME) {
+                        continue;
+                    }
+                    // One-shot resolvers (not from port 5353) expect a unicast
+                    // reply carrying their query id
+                    let (id, to) = if meta.endpoint.port == MDNS_PORT {
+                        (0, group)
+                    } else {
+                        (u16::from_be_bytes([packet[0], packet[1]]), meta.endpoint)
+                    };
+                    if let Err(e) = socket.send_to(&build_answer(id, address), to).await {
+                        log::warn!("mDNS: reply failed: {:?}", e);
+                    }
+                }
+                Either::First(Err(e)) => log::warn!("mDNS: receive failed: {:?}", e),
+                Either::Second(()) => break,
+            }
+        }
+        log::info!("mDNS: network down, waiting to re-advertise");
+    }
+}
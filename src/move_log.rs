@@ -0,0 +1,57 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+use crate::command::CommandSource;
+
+/// Number of moves kept; older entries are overwritten (0 disables the log)
+pub const MOVE_LOG_LEN: usize = 32;
+
+/// One applied angle change
+#[derive(Clone, Copy, Debug)]
+pub struct MoveEntry {
+    /// Milliseconds since boot
+    pub at_ms: u64,
+    pub servo: u8,
+    pub angle: u8,
+    pub source: CommandSource,
+}
+
+struct MoveLog {
+    entries: [Option<MoveEntry>; MOVE_LOG_LEN],
+    /// Slot the next entry goes into
+    next: usize,
+    /// Moves recorded since boot, including overwritten ones
+    total: u32,
+}
+
+static LOG: Mutex<CriticalSectionRawMutex, RefCell<MoveLog>> = Mutex::new(RefCell::new(MoveLog {
+    entries: [None; MOVE_LOG_LEN],
+    next: 0,
+    total: 0,
+}));
+
+/// Record a move applied by the main loop
+pub fn record(servo: u8, angle: u8, source: CommandSource) {
+    let entry = MoveEntry { at_ms: Instant::now().as_millis(), servo, angle, source };
+    LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        log.total = log.total.wrapping_add(1);
+        if MOVE_LOG_LEN > 0 {
+            let next = log.next;
+            log.entries[next] = Some(entry);
+            log.next = (next + 1) % MOVE_LOG_LEN;
+        }
+    });
+}
+
+/// Logged moves, oldest first, and the total number of moves since boot
+pub fn snapshot() -> (alloc::vec::Vec<MoveEntry>, u32) {
+    LOG.lock(|log| {
+        let log = log.borrow();
+        let (newer, older) = log.entries.split_at(log.next);
+        let entries = older.iter().chain(newer).flatten().copied().collect();
+        (entries, log.total)
+    })
+}
@@ -0,0 +1,397 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::{FlashStorage, FlashStorageError};
+use portable_atomic::AtomicBool;
+use servo_core::record::{self, HEADER_LEN};
+
+use crate::command::MAX_SERVOS;
+use crate::presets::{self, Preset, MAX_NAME_LEN};
+use crate::state;
+
+/// Flash offsets of the two config slots: the first two sectors of the
+/// `nvs` partition in `partitions.csv`, used as raw flash (not the ESP-IDF
+/// NVS format)
+///
+/// Saves alternate between the slots, so a power cut mid-write leaves the
+/// previous config intact in the other one.
+const SLOT_OFFSETS: [u32; 2] = [0x9000, 0xA000];
+
+/// The whole `nvs` partition in `partitions.csv`, `(start, end)`; `otadata`
+/// follows it and must survive a factory reset
+const NVS_PARTITION: (u32, u32) = (0x9000, 0xD000);
+
+/// What a factory reset erases, for reporting. WiFi credentials set with
+/// `POST /wifi` are erased too; the ones from `cfg.toml` are compiled in and
+/// come back.
+pub const FACTORY_RESET_CLEARS: [&str; 6] =
+    ["presets", "servo limits", "servo trim", "last servo angles", "sweep duration", "WiFi credentials"];
+
+/// Word that must accompany a factory reset request, so it can't be
+/// triggered by accident
+pub const FACTORY_RESET_CONFIRMATION: &str = "erase-everything";
+
+/// Flash sector size; each slot is one sector
+const SLOT_SIZE: u32 = 4096;
+
+/// Longest WiFi SSID and password (WPA2 passphrase), in bytes
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+/// Shortest WPA2 passphrase; an empty password means an open network
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Check an SSID and password fit the WiFi driver's limits
+///
+/// `const` so the build-time credentials can be checked at compile time.
+pub const fn check_wifi_credentials(ssid: &str, password: &str) -> Result<(), &'static str> {
+    if ssid.is_empty() || ssid.len() > MAX_SSID_LEN {
+        return Err("ssid must be 1-32 bytes");
+    }
+    if !password.is_empty() && (password.len() < MIN_PASSWORD_LEN || password.len() > MAX_PASSWORD_LEN) {
+        return Err("password must be empty or 8-64 bytes");
+    }
+    Ok(())
+}
+
+/// Largest serialized payload: every servo's limits, trim and last angle,
+/// every preset and the WiFi credentials
+const MAX_PAYLOAD_LEN: usize =
+    6 + MAX_SERVOS * (5 + 4 + 4) + presets::MAX_PRESETS * (3 + MAX_NAME_LEN) + 3 + MAX_SSID_LEN + MAX_PASSWORD_LEN;
+
+/// Payload field tags
+const TAG_SWEEP_DURATION: u8 = 1;
+const TAG_LIMITS: u8 = 2;
+const TAG_PRESET: u8 = 3;
+const TAG_TRIM: u8 = 4;
+const TAG_ANGLE: u8 = 5;
+const TAG_WIFI: u8 = 6;
+
+/// Quiet period after the last change before the config is written, so a
+/// burst of changes costs one flash write
+pub const NVS_SETTLE_MS: u64 = 2_000;
+
+/// Minimum time between two config writes, to limit flash wear
+pub const NVS_MIN_WRITE_INTERVAL_MS: u64 = 10_000;
+
+/// Set when the live config differs from the stored one
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// Set by [`factory_reset`]: nothing is saved again before the reboot, so
+/// the live state (or the reboot path's [`flush`]) can't write back what was
+/// erased
+static ERASED_FOR_REBOOT: AtomicBool = AtomicBool::new(false);
+
+/// Wakes the writer task when a save is requested
+static SAVE_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Flash access shared by every task that persists config
+static FLASH: Mutex<CriticalSectionRawMutex, RefCell<Option<FlashStorage<'static>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Sequence number of the newest record, and the slot holding it
+static NEWEST: Mutex<CriticalSectionRawMutex, RefCell<Option<(u32, usize)>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Device configuration persisted as one blob
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Default sweep duration for plain angle commands (0 = instant)
+    pub sweep_duration_ms: u32,
+    /// Hardware limits of each servo as `(min, max)`; `None` keeps the
+    /// compile-time limits in `main.rs`
+    pub limits: [Option<(u8, u8)>; MAX_SERVOS],
+    /// Trim of each servo in degrees
+    pub trims: [i8; MAX_SERVOS],
+    /// Angle each servo was last moved to, restored at boot; `None` starts
+    /// at the default angle
+    pub angles: [Option<u8>; MAX_SERVOS],
+    /// Named positions
+    pub presets: Vec<(String, u8)>,
+    /// WiFi network as `(ssid, password)`; `None` uses the one from `cfg.toml`
+    pub wifi: Option<(String, String)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sweep_duration_ms: 0,
+            limits: [None; MAX_SERVOS],
+            trims: [0; MAX_SERVOS],
+            angles: [None; MAX_SERVOS],
+            presets: Vec::new(),
+            wifi: None,
+        }
+    }
+}
+
+impl Config {
+    /// Snapshot of the live configuration
+    pub fn capture() -> Self {
+        Self {
+            sweep_duration_ms: state::sweep_duration_ms(),
+            limits: core::array::from_fn(state::configured_limits),
+            trims: core::array::from_fn(state::trim),
+            angles: core::array::from_fn(|servo| Some(state::current_angle(servo))),
+            presets: presets::list().into_iter().map(|Preset { name, angle }| (name, angle)).collect(),
+            wifi: state::wifi_credentials(),
+        }
+    }
+
+    /// Apply the sweep duration, presets and WiFi credentials to the live state
+    ///
+    /// Limits, trims and angles are applied by `main` when it configures the
+    /// servos.
+    pub fn apply(&self) {
+        state::set_sweep_duration_ms(self.sweep_duration_ms);
+        state::set_wifi_credentials(self.wifi.clone());
+        presets::clear();
+        for (name, angle) in &self.presets {
+            let _ = presets::save(name, *angle);
+        }
+    }
+
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[TAG_SWEEP_DURATION, 4]);
+        out.extend_from_slice(&self.sweep_duration_ms.to_le_bytes());
+        for (servo, limit) in self.limits.iter().enumerate() {
+            if let Some((min, max)) = limit {
+                out.extend_from_slice(&[TAG_LIMITS, 3, servo as u8, *min, *max]);
+            }
+        }
+        for (servo, trim) in self.trims.iter().enumerate() {
+            out.extend_from_slice(&[TAG_TRIM, 2, servo as u8, *trim as u8]);
+        }
+        for (servo, angle) in self.angles.iter().enumerate() {
+            if let Some(angle) = angle {
+                out.extend_from_slice(&[TAG_ANGLE, 2, servo as u8, *angle]);
+            }
+        }
+        for (name, angle) in &self.presets {
+            out.extend_from_slice(&[TAG_PRESET, 1 + name.len() as u8, *angle]);
+            out.extend_from_slice(name.as_bytes());
+        }
+        if let Some((ssid, password)) = &self.wifi {
+            out.extend_from_slice(&[TAG_WIFI, (1 + ssid.len() + password.len()) as u8, ssid.len() as u8]);
+            out.extend_from_slice(ssid.as_bytes());
+            out.extend_from_slice(password.as_bytes());
+        }
+    }
+
+    fn deserialize(mut payload: &[u8]) -> Self {
+        let mut config = Self::default();
+        while let [tag, len, rest @ ..] = payload {
+            let len = *len as usize;
+            if rest.len() < len {
+                break;
+            }
+            let (value, next) = rest.split_at(len);
+            match (*tag, value) {
+                (TAG_SWEEP_DURATION, [a, b, c, d]) => {
+                    config.sweep_duration_ms = u32::from_le_bytes([*a, *b, *c, *d]);
+                }
+                (TAG_LIMITS, [servo, min, max]) => {
+                    if let Some(limit) = config.limits.get_mut(*servo as usize) {
+                        *limit = Some((*min, (*max).max(*min)));
+                    }
+                }
+                (TAG_TRIM, [servo, trim]) => {
+                    if let Some(slot) = config.trims.get_mut(*servo as usize) {
+                        *slot = *trim as i8;
+                    }
+                }
+                (TAG_ANGLE, [servo, angle]) => {
+                    if let Some(slot) = config.angles.get_mut(*servo as usize) {
+                        *slot = Some((*angle).min(180));
+                    }
+                }
+                (TAG_PRESET, [angle, name @ ..]) => {
+                    if let Ok(name) = core::str::from_utf8(name) {
+                        config.presets.push((String::from(name), *angle));
+                    }
+                }
+                (TAG_WIFI, [ssid_len, rest @ ..]) if (*ssid_len as usize) <= rest.len() => {
+                    let (ssid, password) = rest.split_at(*ssid_len as usize);
+                    if let (Ok(ssid), Ok(password)) = (core::str::from_utf8(ssid), core::str::from_utf8(password))
+                        && check_wifi_credentials(ssid, password).is_ok()
+                    {
+                        config.wifi = Some((String::from(ssid), String::from(password)));
+                    }
+                }
+                // Unknown tag or unexpected length: written by newer firmware
+                _ => {}
+            }
+            payload = next;
+        }
+        config
+    }
+}
+
+/// Why config could not be persisted
+#[derive(Debug)]
+pub enum NvsError {
+    /// `init` has not been called
+    Unavailable,
+    Flash(FlashStorageError),
+}
+
+/// Hand the flash to the config store; call once at boot
+pub fn init(flash: FlashStorage<'static>) {
+    FLASH.lock(|cell| *cell.borrow_mut() = Some(flash));
+}
+
+/// Run `f` with the flash, for its other users (OTA updates)
+///
+/// Holds the same lock as config saves, so the two never interleave.
+pub fn with_flash<R>(f: impl FnOnce(&mut FlashStorage<'static>) -> R) -> Result<R, NvsError> {
+    FLASH.lock(|cell| cell.borrow_mut().as_mut().map(f).ok_or(NvsError::Unavailable))
+}
+
+/// Read the record in `slot`, returning its sequence number and config if valid
+fn read_slot(flash: &mut FlashStorage<'static>, slot: usize) -> Option<(u32, Config)> {
+    let mut header = [0u8; HEADER_LEN];
+    flash.read(SLOT_OFFSETS[slot], &mut header).ok()?;
+    let header = record::parse_header(&header).filter(|header| header.len <= MAX_PAYLOAD_LEN)?;
+    let mut payload = alloc::vec![0u8; header.len];
+    flash.read(SLOT_OFFSETS[slot] + HEADER_LEN as u32, &mut payload).ok()?;
+    if !header.matches(&payload) {
+        return None;
+    }
+    Some((header.sequence, Config::deserialize(&payload)))
+}
+
+/// Read the newest valid config, or `None` if none has been saved
+pub fn load() -> Option<Config> {
+    FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut()?;
+        let (sequence, (slot, config)) = record::newest(
+            (0..SLOT_OFFSETS.len()).filter_map(|slot| read_slot(flash, slot).map(|(seq, config)| (seq, (slot, config)))),
+        )?;
+        NEWEST.lock(|n| *n.borrow_mut() = Some((sequence, slot)));
+        Some(config)
+    })
+}
+
+/// Write `config` to flash, into the slot not holding the newest record
+pub fn save(config: &Config) -> Result<(), NvsError> {
+    let mut payload = Vec::with_capacity(MAX_PAYLOAD_LEN);
+    config.serialize(&mut payload);
+
+    let (sequence, slot) = record::next_slot(NEWEST.lock(|n| *n.borrow()));
+    let record = record::encode(sequence, &payload);
+
+    FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut().ok_or(NvsError::Unavailable)?;
+        // Read-modify-write, erasing the sectors it covers first
+        Storage::write(flash, SLOT_OFFSETS[slot], &record).map_err(NvsError::Flash)
+    })?;
+    NEWEST.lock(|n| *n.borrow_mut() = Some((sequence, slot)));
+    Ok(())
+}
+
+/// Save a snapshot of the live configuration now
+///
+/// Prefer [`request_save`], which coalesces writes.
+pub fn save_current() -> Result<(), NvsError> {
+    if ERASED_FOR_REBOOT.load(Ordering::Relaxed) {
+        log::debug!("Not saving config: factory reset pending reboot");
+        return Ok(());
+    }
+    save(&Config::capture())
+}
+
+/// Schedule a save of the live configuration
+///
+/// The write happens once changes have settled for [`NVS_SETTLE_MS`], and no
+/// sooner than [`NVS_MIN_WRITE_INTERVAL_MS`] after the previous write.
+pub fn request_save() {
+    if ERASED_FOR_REBOOT.load(Ordering::Relaxed) {
+        return;
+    }
+    DIRTY.store(true, Ordering::Relaxed);
+    SAVE_REQUESTED.signal(());
+}
+
+/// Write any pending change immediately; call before rebooting or sleeping
+pub fn flush() -> Result<(), NvsError> {
+    if DIRTY.swap(false, Ordering::Relaxed) {
+        save_current()
+    } else {
+        Ok(())
+    }
+}
+
+/// Write requested config changes to flash, coalesced and rate limited
+#[embassy_executor::task]
+pub async fn nvs_writer_task() {
+    let mut last_write: Option<Instant> = None;
+    loop {
+        SAVE_REQUESTED.wait().await;
+
+        // Restart the settle period for every further request
+        let settle = Duration::from_millis(NVS_SETTLE_MS);
+        while let Either::Second(()) = select(Timer::after(settle), SAVE_REQUESTED.wait()).await {}
+
+        if let Some(at) = last_write {
+            Timer::at(at + Duration::from_millis(NVS_MIN_WRITE_INTERVAL_MS)).await;
+        }
+
+        if DIRTY.swap(false, Ordering::Relaxed) {
+            match save_current() {
+                Ok(()) => log::debug!("Config saved"),
+                Err(e) => {
+                    log::error!("Failed to save config: {:?}", e);
+                    // Leave it pending so the next request or a flush retries
+                    DIRTY.store(true, Ordering::Relaxed);
+                }
+            }
+            last_write = Some(Instant::now());
+        }
+    }
+}
+
+/// Erase both config slots, so the next boot starts from defaults
+pub fn clear() -> Result<(), NvsError> {
+    FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut().ok_or(NvsError::Unavailable)?;
+        for offset in SLOT_OFFSETS {
+            flash.erase(offset, offset + SLOT_SIZE).map_err(NvsError::Flash)?;
+        }
+        Ok(())
+    })?;
+    NEWEST.lock(|n| *n.borrow_mut() = None);
+    DIRTY.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Erase the whole `nvs` partition, including anything outside the config
+/// slots; the caller reboots afterwards
+///
+/// Saves are refused from the start, so one racing the erase can't undo it.
+pub fn factory_reset() -> Result<(), NvsError> {
+    ERASED_FOR_REBOOT.store(true, Ordering::Relaxed);
+    let erased = FLASH.lock(|cell| {
+        let mut flash = cell.borrow_mut();
+        let flash = flash.as_mut().ok_or(NvsError::Unavailable)?;
+        flash.erase(NVS_PARTITION.0, NVS_PARTITION.1).map_err(NvsError::Flash)
+    });
+    if erased.is_err() {
+        // No reboot follows, so keep saving
+        ERASED_FOR_REBOOT.store(false, Ordering::Relaxed);
+    }
+    erased?;
+    NEWEST.lock(|n| *n.borrow_mut() = None);
+    DIRTY.store(false, Ordering::Relaxed);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use alloc::vec::Vec;
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_bootloader_esp_idf::ota::OtaImageState;
+use esp_bootloader_esp_idf::ota_updater::OtaUpdater;
+use esp_bootloader_esp_idf::partitions::{self, FlashRegion, PARTITION_TABLE_MAX_LEN};
+use esp_storage::FlashStorage;
+use servo_core::image::ImageCheck;
+
+use crate::nvs;
+
+/// Flash sector size; the image is written one erased sector at a time
+const SECTOR_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum OtaError {
+    /// The flash has not been handed to `nvs::init`
+    Unavailable,
+    /// No OTA partitions, or a flash or otadata error
+    Partition(partitions::Error),
+    /// Image larger than the OTA partition
+    TooLarge { size: u32, capacity: u32 },
+    /// Not a valid ESP32 app image
+    InvalidImage(&'static str),
+}
+
+/// Run `f` on the OTA partition the next boot would switch to
+fn with_next_partition<R>(
+    f: impl FnOnce(&mut FlashRegion<'_, FlashStorage<'static>>) -> Result<R, partitions::Error>,
+) -> Result<R, OtaError> {
+    nvs::with_flash(|flash| {
+        let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+        let mut ota = OtaUpdater::new(flash, &mut table)?;
+        let (mut region, _) = ota.next_partition()?;
+        f(&mut region)
+    })
+    .map_err(|_| OtaError::Unavailable)?
+    .map_err(OtaError::Partition)
+}
+
+/// Writes a firmware image into the inactive OTA partition
+///
+/// Nothing changes for the next boot until [`OtaWriter::finish`] has verified
+/// the image, so an interrupted or bad upload leaves the running firmware in
+/// charge.
+pub struct OtaWriter {
+    /// Bytes waiting to fill the next sector
+    sector: Vec<u8>,
+    /// Partition offset of the next sector
+    offset: u32,
+    check: ImageCheck,
+}
+
+impl OtaWriter {
+    /// Start an update with an image of `size` bytes
+    pub fn begin(size: u32) -> Result<Self, OtaError> {
+        let capacity = with_next_partition(|region| Ok(region.capacity() as u32))?;
+        if size > capacity {
+            return Err(OtaError::TooLarge { size, capacity });
+        }
+        Ok(Self { sector: Vec::with_capacity(SECTOR_SIZE), offset: 0, check: ImageCheck::new() })
+    }
+
+    /// Append the next part of the image
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        self.check.feed(data);
+        if let Some(error) = self.check.error() {
+            return Err(OtaError::InvalidImage(error));
+        }
+        while !data.is_empty() {
+            let take = (SECTOR_SIZE - self.sector.len()).min(data.len());
+            self.sector.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.sector.len() == SECTOR_SIZE {
+                self.write_sector()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Erase the next sector and write the buffered bytes to it
+    fn write_sector(&mut self) -> Result<(), OtaError> {
+        // Flash writes are whole words; pad the last sector with erased bytes
+        while !self.sector.len().is_multiple_of(4) {
+            self.sector.push(0xFF);
+        }
+        let offset = self.offset;
+        let sector = &self.sector;
+        with_next_partition(|region| {
+            region.erase(offset, offset + SECTOR_SIZE as u32)?;
+            region.write(offset, sector)
+        })?;
+        self.offset += SECTOR_SIZE as u32;
+        self.sector.clear();
+        Ok(())
+    }
+
+    /// Check the complete image and make it the one booted next
+    pub fn finish(mut self) -> Result<(), OtaError> {
+        if !self.sector.is_empty() {
+            self.write_sector()?;
+        }
+        self.check.finish().map_err(OtaError::InvalidImage)?;
+        nvs::with_flash(|flash| {
+            let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+            let mut ota = OtaUpdater::new(flash, &mut table)?;
+            ota.activate_next_partition()?;
+            ota.set_current_ota_state(OtaImageState::New)
+        })
+        .map_err(|_| OtaError::Unavailable)?
+        .map_err(OtaError::Partition)
+    }
+}
+
+/// Mark the running firmware as good after an update, so a bootloader with
+/// rollback enabled keeps booting it
+///
+/// Call once the firmware has proven itself (it reached the network).
+pub fn confirm_running_image() {
+    let result = nvs::with_flash(|flash| {
+        let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+        let mut ota = OtaUpdater::new(flash, &mut table)?;
+        match ota.current_ota_state()? {
+            OtaImageState::New | OtaImageState::PendingVerify => ota.set_current_ota_state(OtaImageState::Valid).map(|()| true),
+            _ => Ok(false),
+        }
+    });
+    match result {
+        Ok(Ok(true)) => log::info!("OTA: updated firmware confirmed"),
+        Ok(Ok(false)) => {}
+        Ok(Err(e)) => log::warn!("OTA: could not read the boot state: {:?}", e),
+        Err(e) => log::warn!("OTA: {:?}", e),
+    }
+}
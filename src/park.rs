@@ -0,0 +1,49 @@
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::command::{apply_limits, check_ready, send_command, CommandSource, ServoCommand, MAX_SERVOS};
+use crate::state;
+
+/// How often the link state is checked
+const POLL_MS: u64 = 1_000;
+
+/// Move every servo to `angle` once WiFi has been down for `grace_ms`
+///
+/// Nothing happens until the first connect, so a slow boot doesn't park the
+/// servos. The park move is queued as [`CommandSource::Auto`], so a button or
+/// serial command still wins over it, and it is made once per outage; after
+/// the link returns the servos stay parked until something moves them.
+#[embassy_executor::task]
+pub async fn park_task(angle: u8, grace_ms: u64) {
+    log::info!("Park: servos to {} degrees after {} s without WiFi", angle, grace_ms / 1000);
+    while !state::wifi_connected() {
+        Timer::after(Duration::from_millis(POLL_MS)).await;
+    }
+    loop {
+        while state::wifi_connected() {
+            Timer::after(Duration::from_millis(POLL_MS)).await;
+        }
+        let lost_at = Instant::now();
+        while !state::wifi_connected() && lost_at.elapsed() < Duration::from_millis(grace_ms) {
+            Timer::after(Duration::from_millis(POLL_MS)).await;
+        }
+        if state::wifi_connected() {
+            continue;
+        }
+
+        if let Err(reason) = check_ready() {
+            log::warn!("Park: WiFi down for {} s, servos not ready ({}), not parking", grace_ms / 1000, reason.as_str());
+        } else {
+            log::info!("Park: WiFi down for {} s, parking servos at {} degrees", grace_ms / 1000, angle);
+            for servo in 0..MAX_SERVOS as u8 {
+                match apply_limits(servo as usize, angle) {
+                    Ok(angle) => send_command(ServoCommand::set(servo, angle, CommandSource::Auto)),
+                    Err((min, max)) => log::warn!("Park: {} is outside servo {}'s range {}-{}", angle, servo, min, max),
+                }
+            }
+        }
+        while !state::wifi_connected() {
+            Timer::after(Duration::from_millis(POLL_MS)).await;
+        }
+        log::info!("Park: WiFi back, servos stay parked until moved");
+    }
+}
@@ -0,0 +1,100 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::peripherals::GPIO0;
+#[cfg(feature = "esp32")]
+use esp_hal::rtc_cntl::sleep::Ext0WakeupSource;
+#[cfg(feature = "esp32c3")]
+use esp_hal::rtc_cntl::sleep::RtcioWakeupSource;
+use esp_hal::rtc_cntl::sleep::{TimerWakeupSource, WakeSource, WakeupLevel};
+use esp_hal::rtc_cntl::Rtc;
+
+use crate::command::{send_command, CommandSource, ServoCommand, MAX_SERVOS};
+use crate::{nvs, state};
+
+/// Go into deep sleep after this long without an HTTP request, serial
+/// command or button press (0 = never sleep)
+pub const IDLE_SLEEP_AFTER_MS: u32 = 0;
+
+/// Wake from deep sleep after this long even without a button press
+/// (0 = only the button wakes it)
+pub const SLEEP_WAKE_AFTER_S: u64 = 3600;
+
+/// Time the main loop gets to detach the servos before sleeping
+const DETACH_SETTLE_MS: u64 = 100;
+
+/// Uptime in milliseconds of the last request, command or button press
+static LAST_ACTIVITY_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Record a request or command; call from every control entry point
+pub fn note_activity() {
+    LAST_ACTIVITY_MS.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+}
+
+/// Milliseconds since the last request or command
+pub fn idle_ms() -> u32 {
+    (Instant::now().as_millis() as u32).wrapping_sub(LAST_ACTIVITY_MS.load(Ordering::Relaxed))
+}
+
+/// Put the chip in deep sleep once nothing has used it for
+/// [`IDLE_SLEEP_AFTER_MS`]
+///
+/// The servos are detached and pending config is saved first. Pulling GPIO0
+/// low (the BOOT button on ESP32 boards; wire one to ground on the ESP32-C3,
+/// whose BOOT button is on GPIO9) or [`SLEEP_WAKE_AFTER_S`] passing wakes it;
+/// waking is a reset, after which the stored angles are restored as on any
+/// boot.
+#[embassy_executor::task]
+pub async fn idle_sleep_task(mut rtc: Rtc<'static>, wake_pin: GPIO0<'static>) {
+    log::info!("Power: deep sleep after {} s idle", IDLE_SLEEP_AFTER_MS / 1000);
+    loop {
+        let idle = idle_ms();
+        if idle < IDLE_SLEEP_AFTER_MS {
+            Timer::after(Duration::from_millis((IDLE_SLEEP_AFTER_MS - idle) as u64)).await;
+            continue;
+        }
+        // Never cut a move short
+        if (0..MAX_SERVOS).any(state::is_moving) {
+            Timer::after(Duration::from_secs(1)).await;
+            continue;
+        }
+        break;
+    }
+
+    log::info!("Power: idle for {} s, detaching servos and sleeping", idle_ms() / 1000);
+    // Refuse moves from here on, so none undoes the detach
+    state::set_low_power();
+    for servo in 0..MAX_SERVOS {
+        send_command(ServoCommand::detach(servo as u8, CommandSource::Auto));
+    }
+    Timer::after(Duration::from_millis(DETACH_SETTLE_MS)).await;
+    if let Err(e) = nvs::flush() {
+        log::error!("Failed to save config before sleeping: {:?}", e);
+    }
+
+    sleep_until_woken(&mut rtc, wake_pin);
+}
+
+/// Deep sleep until `wake_pin` goes low or the wake timer fires
+#[cfg(feature = "esp32")]
+fn sleep_until_woken(rtc: &mut Rtc<'static>, wake_pin: GPIO0<'static>) -> ! {
+    let button = Ext0WakeupSource::new(wake_pin, WakeupLevel::Low);
+    sleep_deep(rtc, &button)
+}
+
+/// Deep sleep until `wake_pin` goes low or the wake timer fires
+#[cfg(feature = "esp32c3")]
+fn sleep_until_woken(rtc: &mut Rtc<'static>, mut wake_pin: GPIO0<'static>) -> ! {
+    let mut pins = [(&mut wake_pin as &mut dyn esp_hal::gpio::RtcPinWithResistors, WakeupLevel::Low)];
+    let button = RtcioWakeupSource::new(&mut pins);
+    sleep_deep(rtc, &button)
+}
+
+fn sleep_deep(rtc: &mut Rtc<'static>, button: &dyn WakeSource) -> ! {
+    if SLEEP_WAKE_AFTER_S > 0 {
+        let timer = TimerWakeupSource::new(core::time::Duration::from_secs(SLEEP_WAKE_AFTER_S));
+        rtc.sleep_deep(&[button, &timer])
+    } else {
+        rtc.sleep_deep(&[button])
+    }
+}
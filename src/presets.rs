@@ -0,0 +1,80 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Maximum number of stored presets
+pub const MAX_PRESETS: usize = 16;
+
+/// Maximum preset name length in bytes
+pub const MAX_NAME_LEN: usize = 16;
+
+/// A named servo position
+#[derive(Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    pub angle: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PresetError {
+    /// Name is empty, too long or contains characters other than `[A-Za-z0-9_-]`
+    InvalidName,
+    /// All `MAX_PRESETS` slots are used
+    Full,
+}
+
+static PRESETS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Preset>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Whether `name` is acceptable as a preset name
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Store `angle` under `name`, replacing any preset with the same name
+pub fn save(name: &str, angle: u8) -> Result<(), PresetError> {
+    if !is_valid_name(name) {
+        return Err(PresetError::InvalidName);
+    }
+    PRESETS.lock(|presets| {
+        let mut presets = presets.borrow_mut();
+        if let Some(preset) = presets.iter_mut().find(|p| p.name == name) {
+            preset.angle = angle;
+        } else if presets.len() < MAX_PRESETS {
+            presets.push(Preset { name: String::from(name), angle });
+        } else {
+            return Err(PresetError::Full);
+        }
+        Ok(())
+    })
+}
+
+/// Angle stored under `name`
+pub fn get(name: &str) -> Option<u8> {
+    PRESETS.lock(|presets| presets.borrow().iter().find(|p| p.name == name).map(|p| p.angle))
+}
+
+/// Remove the preset `name`; returns whether it existed
+pub fn remove(name: &str) -> bool {
+    PRESETS.lock(|presets| {
+        let mut presets = presets.borrow_mut();
+        let before = presets.len();
+        presets.retain(|p| p.name != name);
+        presets.len() != before
+    })
+}
+
+/// Snapshot of all stored presets
+pub fn list() -> Vec<Preset> {
+    PRESETS.lock(|presets| presets.borrow().clone())
+}
+
+/// Remove all presets
+pub fn clear() {
+    PRESETS.lock(|presets| presets.borrow_mut().clear());
+}
@@ -1,85 +1,376 @@
 use esp_println::println;
-use esp_hal::uart::Uart;
-use esp_hal::Blocking;
-use embassy_time::{Duration, Timer};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
-
-/// Signal for servo angle updates from serial
-pub static SERIAL_SERVO_ANGLE: Signal<CriticalSectionRawMutex, u8> = Signal::new();
-
-/// Parse a servo command from input
-/// Accepts formats like: "90", "servo 90", "angle 90", "s90", "a90"
-fn parse_servo_command(input: &str) -> Option<u8> {
-    let input = input.trim();
-    
-    // Try direct number
-    if let Ok(angle) = input.parse::<u8>() {
-        if angle <= 180 {
-            return Some(angle);
-        }
+use esp_hal::uart::{Config as UartConfig, CtsConfig, HwFlowControl, RtsConfig, Uart};
+use esp_hal::Async;
+use embassy_futures::yield_now;
+use embassy_time::{with_timeout, Duration, Timer};
+use servo_core::serial::{
+    parse_servo_command, Limits, OutputFormat, PulseEnd, Query, SerialCommand, MAX_CALIBRATION_PULSE_US,
+};
+
+use crate::command::{
+    apply_limits, check_ready, CommandSource, ServoAction, ServoCommand, MAX_PULSE_HOLD_MS, MAX_SERVOS, MAX_SWEEP_STEP_MS, PUBLIC_MAX_ANGLE,
+    PUBLIC_MIN_ANGLE, send_command,
+};
+use crate::http_server::{build_angle_json, build_net_json, build_status_json, ANGLE_FIELD};
+use crate::analog;
+use crate::nvs;
+use crate::power;
+use crate::servo::MAX_TRIM_DEG;
+use crate::state::{self, NotReady};
+
+/// Serial baud rate
+pub const SERIAL_BAUD_RATE: u32 = 115_200;
+
+/// Enable RTS/CTS hardware flow control on UART0
+///
+/// Needed for sustained high-rate command streams, where the host could
+/// otherwise overrun the 128-byte RX FIFO before the task is scheduled. Requires wiring
+/// the adapter's RTS to GPIO21 (ESP32 CTS) and its CTS to GPIO22 (ESP32 RTS).
+pub const SERIAL_HW_FLOW_CONTROL: bool = false;
+
+/// RX FIFO fill level at which RTS is deasserted when flow control is on
+const RTS_THRESHOLD: u8 = 64;
+
+/// Inactivity after which an unterminated partial line is discarded
+///
+/// Protects the next command from stale bytes left by a host that stopped
+/// mid-line. Set to 0 to keep partial lines indefinitely.
+pub const SERIAL_LINE_TIMEOUT_MS: u64 = 2000;
+
+/// Bytes drained from the UART per read
+///
+/// The task yields after each chunk, so a large paste is worked through a
+/// chunk at a time while the HTTP handlers keep running.
+const READ_CHUNK_SIZE: usize = 32;
+
+/// Erase keys: terminals send one or the other for Backspace
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+/// UART configuration for the serial command interface
+pub fn uart_config() -> UartConfig {
+    let config = UartConfig::default().with_baudrate(SERIAL_BAUD_RATE);
+    if SERIAL_HW_FLOW_CONTROL {
+        config.with_hw_flow_ctrl(HwFlowControl {
+            cts: CtsConfig::Enabled,
+            rts: RtsConfig::Enabled(RTS_THRESHOLD),
+        })
+    } else {
+        config
     }
-    
-    // Try "servo X" or "s X" or "sX"
-    for prefix in ["servo ", "angle ", "s ", "a ", "s", "a"] {
-        if let Some(rest) = input.strip_prefix(prefix) {
-            if let Ok(angle) = rest.trim().parse::<u8>() {
-                if angle <= 180 {
-                    return Some(angle);
-                }
+}
+
+/// What [`parse_servo_command`] accepts, from this build's configuration
+const PARSE_LIMITS: Limits = Limits {
+    servos: MAX_SERVOS,
+    max_hold_ms: MAX_PULSE_HOLD_MS,
+    max_step_ms: MAX_SWEEP_STEP_MS,
+    reset_confirmation: nvs::FACTORY_RESET_CONFIRMATION,
+};
+
+/// Angle `center` moves to
+const CENTER_ANGLE: u8 = 90;
+
+/// Print the command summary, shown at startup and by `help`
+fn print_help() {
+    println!("  Commands: <angle> or 'servo <angle>' (0-180) for servo 0, 'servo <index> <angle>' for any");
+    println!("  Relative: '+10', '-10'; 'center' moves to {}", CENTER_ANGLE);
+    println!("  Pulse: 'pulse <angle> <hold_ms>' (hold 1-{} ms)", MAX_PULSE_HOLD_MS);
+    println!("  Sweep: 'sweep <from> <to> <step_ms>' (step 1-{} ms per degree)", MAX_SWEEP_STEP_MS);
+    println!("  Queries: 'get angle', 'get status', 'get net' (or 'status')");
+    println!("  Trim: 'trim +1', 'trim -1', 'trim save'");
+    println!("  Calibrate: 'cal min <us>', 'cal max <us>' (up to {} us), 'cal show'", MAX_CALIBRATION_PULSE_US);
+    println!("  Output: 'format json' or 'format human'");
+    println!("  Knob: 'pot on' or 'pot off'");
+    println!("  Reset: 'factory-reset {}' erases stored config and reboots", nvs::FACTORY_RESET_CONFIRMATION);
+    println!("  Help: 'help' or '?'");
+    println!("  Example: 90");
+}
+
+/// Print a line in the current output style: `human` as is, or `json` as
+/// a single-line JSON object
+fn emit(format: OutputFormat, human: core::fmt::Arguments, json: core::fmt::Arguments) {
+    match format {
+        OutputFormat::Human => println!("\n{}", human),
+        OutputFormat::Json => println!("{}", json),
+    }
+}
+
+/// Report an angle outside the allowed range
+fn emit_refused(format: OutputFormat, angle: u8, min: u8, max: u8) {
+    emit(
+        format,
+        format_args!("Refused: {} is outside the allowed range {}-{}", angle, min, max),
+        format_args!(r#"{{"event": "error", "error": "out_of_range", "{}": {}, "min": {}, "max": {}}}"#, ANGLE_FIELD, angle, min, max),
+    );
+}
+
+/// Report a move refused because the servos aren't ready
+fn emit_not_ready(format: OutputFormat, reason: NotReady) {
+    emit(
+        format,
+        format_args!("Refused: servos not ready ({})", reason.as_str()),
+        format_args!(r#"{{"event": "error", "error": "not_ready", "reason": "{}"}}"#, reason.as_str()),
+    );
+}
+
+/// Execute one complete command line
+fn handle_line(cmd: &str, format: &mut OutputFormat) {
+    power::note_activity();
+    let command = parse_servo_command(cmd, &PARSE_LIMITS);
+    let moves = matches!(
+        command,
+        Some(SerialCommand::Angle { .. } | SerialCommand::Relative(_) | SerialCommand::Center | SerialCommand::Pulse(..) | SerialCommand::Sweep { .. })
+    );
+    if let (true, Err(reason)) = (moves, check_ready()) {
+        emit_not_ready(*format, reason);
+        return;
+    }
+    // Relative moves and center resolve to an absolute angle
+    let command = match command {
+        Some(SerialCommand::Relative(delta)) => {
+            let from = state::target_angle(0) as i16;
+            let angle = (from + delta).clamp(PUBLIC_MIN_ANGLE as i16, PUBLIC_MAX_ANGLE as i16) as u8;
+            Some(SerialCommand::Angle { servo: 0, angle })
+        }
+        Some(SerialCommand::Center) => Some(SerialCommand::Angle { servo: 0, angle: CENTER_ANGLE }),
+        command => command,
+    };
+    match command {
+        Some(SerialCommand::Angle { servo, angle }) => match apply_limits(servo as usize, angle) {
+            Ok(angle) => {
+                emit(
+                    *format,
+                    format_args!("Serial: Setting servo {} to {} degrees", servo, angle),
+                    format_args!(r#"{{"event": "move", "servo": {}, "{}": {}}}"#, servo, ANGLE_FIELD, angle),
+                );
+                send_command(ServoCommand::set(servo, angle, CommandSource::Serial));
             }
+            Err((min, max)) => emit_refused(*format, angle, min, max),
+        },
+        Some(SerialCommand::Pulse(angle, hold_ms)) => match apply_limits(0, angle) {
+            Ok(angle) => {
+                emit(
+                    *format,
+                    format_args!("Serial: Pulsing servo to {} degrees for {} ms", angle, hold_ms),
+                    format_args!(r#"{{"event": "pulse", "servo": 0, "{}": {}, "hold_ms": {}}}"#, ANGLE_FIELD, angle, hold_ms),
+                );
+                send_command(ServoCommand::pulse(0, angle, hold_ms, CommandSource::Serial));
+            }
+            Err((min, max)) => emit_refused(*format, angle, min, max),
+        },
+        Some(SerialCommand::Sweep { from, to, step_ms }) => match (apply_limits(0, from), apply_limits(0, to)) {
+            (Ok(from), Ok(to)) => {
+                emit(
+                    *format,
+                    format_args!("Serial: Sweeping servo from {} to {} degrees at {} ms per degree", from, to, step_ms),
+                    format_args!(r#"{{"event": "sweep", "servo": 0, "from": {}, "to": {}, "step_ms": {}}}"#, from, to, step_ms),
+                );
+                send_command(ServoCommand::sweep(0, from, to, step_ms, CommandSource::Serial));
+            }
+            (Err((min, max)), _) => emit_refused(*format, from, min, max),
+            (_, Err((min, max))) => emit_refused(*format, to, min, max),
+        },
+        // Resolved to an absolute angle above
+        Some(SerialCommand::Relative(_) | SerialCommand::Center) => {}
+        Some(SerialCommand::Get(query)) => {
+            let json = match query {
+                Query::Angle => build_angle_json(0),
+                Query::Status => build_status_json(),
+                Query::Net => build_net_json(),
+            };
+            emit(*format, format_args!("{}", json), format_args!("{}", json));
+        }
+        Some(SerialCommand::Help) => match *format {
+            OutputFormat::Human => {
+                println!("");
+                print_help();
+                println!("  Current angle: {}", build_angle_json(0));
+            }
+            // Host software gets the angle line, as `?` always returned
+            OutputFormat::Json => println!("{}", build_angle_json(0)),
+        },
+        Some(SerialCommand::Pot(enabled)) => {
+            analog::set_enabled(enabled);
+            emit(
+                *format,
+                format_args!("Pot control {}", if enabled { "on" } else { "off" }),
+                format_args!(r#"{{"event": "pot", "enabled": {}}}"#, enabled),
+            );
+        }
+        Some(SerialCommand::Format(new_format)) => {
+            *format = new_format;
+            emit(
+                new_format,
+                format_args!("Output format: human"),
+                format_args!(r#"{{"event": "format", "format": "json"}}"#),
+            );
+        }
+        Some(SerialCommand::TrimNudge(delta)) => {
+            let trim = state::trim(0).saturating_add(delta).clamp(-MAX_TRIM_DEG, MAX_TRIM_DEG);
+            emit(
+                *format,
+                format_args!("Trim: {:+} degrees ('trim save' to keep it)", trim),
+                format_args!(r#"{{"event": "trim", "servo": 0, "trim": {}}}"#, trim),
+            );
+            send_command(ServoCommand { servo: 0, action: ServoAction::Trim(trim), source: CommandSource::Serial });
+        }
+        Some(SerialCommand::TrimSave) => {
+            nvs::request_save();
+            emit(
+                *format,
+                format_args!("Trim {:+} degrees will be saved", state::trim(0)),
+                format_args!(r#"{{"event": "trim_saved", "servo": 0, "trim": {}}}"#, state::trim(0)),
+            );
         }
+        Some(SerialCommand::Calibrate(end, pulse_us)) => {
+            let (min_us, max_us) = match (end, state::pulse_range(0)) {
+                (PulseEnd::Min, (_, max_us)) => (pulse_us, max_us),
+                (PulseEnd::Max, (min_us, _)) => (min_us, pulse_us),
+            };
+            if min_us >= max_us {
+                emit(
+                    *format,
+                    format_args!("Refused: min pulse {} us must be below max pulse {} us", min_us, max_us),
+                    format_args!(r#"{{"event": "error", "error": "invalid_pulse_range", "min_us": {}, "max_us": {}}}"#, min_us, max_us),
+                );
+                return;
+            }
+            emit(
+                *format,
+                format_args!("Calibration: {} - {} us (not saved; put it in the servo's ServoConfig)", min_us, max_us),
+                format_args!(r#"{{"event": "calibration", "servo": 0, "min_us": {}, "max_us": {}}}"#, min_us, max_us),
+            );
+            send_command(ServoCommand {
+                servo: 0,
+                action: ServoAction::PulseRange { min_us, max_us },
+                source: CommandSource::Serial,
+            });
+        }
+        Some(SerialCommand::CalibrationShow) => {
+            let (min_us, max_us) = state::pulse_range(0);
+            emit(
+                *format,
+                format_args!("Calibration: {} - {} us at {} Hz", min_us, max_us, state::pwm_freq_hz()),
+                format_args!(r#"{{"servo": 0, "min_us": {}, "max_us": {}, "freq_hz": {}}}"#, min_us, max_us, state::pwm_freq_hz()),
+            );
+        }
+        Some(SerialCommand::FactoryReset(false)) => emit(
+            *format,
+            format_args!("Confirm with 'factory-reset {}'", nvs::FACTORY_RESET_CONFIRMATION),
+            format_args!(r#"{{"event": "error", "error": "confirmation_required"}}"#),
+        ),
+        Some(SerialCommand::FactoryReset(true)) => match nvs::factory_reset() {
+            Ok(()) => {
+                emit(
+                    *format,
+                    format_args!("Factory reset: cleared {:?}, rebooting", nvs::FACTORY_RESET_CLEARS),
+                    format_args!(r#"{{"event": "factory_reset", "rebooting": true}}"#),
+                );
+                state::request_reboot();
+            }
+            Err(e) => emit(
+                *format,
+                format_args!("Factory reset failed: {:?}", e),
+                format_args!(r#"{{"event": "error", "error": "factory_reset_failed"}}"#),
+            ),
+        },
+        None if !cmd.trim().is_empty() => emit(
+            *format,
+            format_args!("Unknown command: '{}'. Use 0-180 for angle, or 'help'.", cmd),
+            format_args!(r#"{{"event": "error", "error": "unknown_command"}}"#),
+        ),
+        None => {}
     }
-    
-    None
 }
 
 /// Task to read serial input and parse servo commands
 #[embassy_executor::task]
-pub async fn serial_input_task(mut uart: Uart<'static, Blocking>) {
-    println!("Serial command interface ready");
-    println!("  Commands: <angle> or 'servo <angle>' (0-180)");
-    println!("  Example: 90");
+pub async fn serial_input_task(mut uart: Uart<'static, Async>) {
+    log::info!("Serial command interface ready");
+    print_help();
     
     let mut buffer = [0u8; 64];
     let mut pos = 0usize;
-    let mut read_buf = [0u8; 1];
+    let mut read_buf = [0u8; READ_CHUNK_SIZE];
+    let mut format = OutputFormat::Human;
+    let mut last_was_cr = false;
     
     loop {
-        // Check if data is available (non-blocking check)
-        if uart.read_ready() {
-            // Try to read a byte
-            match uart.read(&mut read_buf) {
-                Ok(1) => {
-                    let byte = read_buf[0];
-                    
-                    // Echo the character back
-                    let _ = uart.write(&[byte]);
-                    
-                    if byte == b'\r' || byte == b'\n' {
-                        if pos > 0 {
-                            // Try to parse the command
-                            if let Ok(cmd) = core::str::from_utf8(&buffer[..pos]) {
-                                if let Some(angle) = parse_servo_command(cmd) {
-                                    println!("\nSerial: Setting servo to {} degrees", angle);
-                                    SERIAL_SERVO_ANGLE.signal(angle);
-                                } else if !cmd.trim().is_empty() {
-                                    println!("\nUnknown command: '{}'. Use 0-180 for angle.", cmd);
-                                }
-                            }
-                            pos = 0;
+        // Sleep until bytes arrive; a partial line waits at most the line timeout
+        let read = if pos > 0 && SERIAL_LINE_TIMEOUT_MS > 0 {
+            with_timeout(Duration::from_millis(SERIAL_LINE_TIMEOUT_MS), uart.read_async(&mut read_buf)).await
+        } else {
+            Ok(uart.read_async(&mut read_buf).await)
+        };
+        let n = match read {
+            // Drains as much of the FIFO as is there, up to one chunk
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => {
+                log::warn!("Serial read error: {:?}", e);
+                continue;
+            }
+            Err(_) => {
+                emit(
+                    format,
+                    format_args!("Discarding incomplete command after {} ms of inactivity", SERIAL_LINE_TIMEOUT_MS),
+                    format_args!(r#"{{"event": "error", "error": "line_timeout", "timeout_ms": {}}}"#, SERIAL_LINE_TIMEOUT_MS),
+                );
+                pos = 0;
+                continue;
+            }
+        };
+
+        let echo = format == OutputFormat::Human;
+        for &byte in &read_buf[..n] {
+            // The LF of a CRLF pair; the CR already ended the line
+            if byte == b'\n' && last_was_cr {
+                last_was_cr = false;
+                continue;
+            }
+            last_was_cr = byte == b'\r';
+
+            if byte == BACKSPACE || byte == DELETE {
+                if pos > 0 {
+                    pos -= 1;
+                    // Erase the character on the terminal
+                    if echo {
+                        let _ = uart.write_async(b"\x08 \x08").await;
+                    }
+                }
+                continue;
+            }
+
+            // Echo the characters back for people typing at a terminal
+            if echo {
+                let _ = uart.write_async(&[byte]).await;
+            }
+
+            if byte == b'\r' || byte == b'\n' {
+                if pos > 0 {
+                    // Try to parse the command
+                    if let Ok(cmd) = core::str::from_utf8(&buffer[..pos]) {
+                        handle_line(cmd, &mut format);
+                    }
+                    if state::reboot_requested() {
+                        Timer::after(Duration::from_millis(100)).await;
+                        if let Err(e) = nvs::flush() {
+                            log::error!("Failed to save config before reboot: {:?}", e);
                         }
-                        println!("");
-                    } else if pos < buffer.len() - 1 {
-                        buffer[pos] = byte;
-                        pos += 1;
+                        esp_hal::system::software_reset();
                     }
+                    pos = 0;
+                }
+                if format == OutputFormat::Human {
+                    println!("");
                 }
-                _ => {}
+            } else if pos < buffer.len() - 1 {
+                buffer[pos] = byte;
+                pos += 1;
             }
-        } else {
-            // No data available, yield to other tasks
-            Timer::after(Duration::from_millis(10)).await;
         }
+        // With a flood of input the next read completes at once; let the
+        // other tasks run in between
+        yield_now().await;
     }
 }
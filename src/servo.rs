@@ -1,76 +1,453 @@
 use esp_hal::ledc::{
     channel::{self, ChannelIFace, ChannelHW},
-    timer::{self, TimerIFace, config::Duty},
-    Ledc, HighSpeed,
+    timer::{self, TimerIFace, TimerSpeed, config::Duty},
+    Ledc, LowSpeed, LSGlobalClkSource,
 };
-use esp_hal::gpio::{DriveMode, interconnect::PeripheralOutput};
-use esp_println::println;
+#[cfg(feature = "esp32")]
+use esp_hal::ledc::HighSpeed;
+use esp_hal::gpio::{DriveMode, Pin, interconnect::PeripheralOutput};
+use embassy_time::{Duration, Timer};
+use servo_core::pulse::{angle_f32_to_pulse_us, clamp_pulse_range, pulse_f32_to_duty};
 
-/// SG90 servo configuration
-/// - PWM frequency: 50Hz (20ms period)
-/// - Pulse width: 0.5ms (0°) to 2.5ms (180°)
-const SERVO_FREQ_HZ: u32 = 50;
+pub use servo_core::actuator::{self, sweep_angles, AngleActuator};
+pub use servo_core::pulse::{angle_to_pulse_us, pulse_us_to_duty, ServoConfig, SERVO_FREQ_HZ};
 
-/// Minimum pulse width in microseconds (0 degrees)
-const MIN_PULSE_US: u32 = 500;
+/// Largest trim accepted by `set_trim`, in degrees either way
+pub const MAX_TRIM_DEG: i8 = 20;
 
-/// Maximum pulse width in microseconds (180 degrees)
-const MAX_PULSE_US: u32 = 2500;
+/// Duty resolution (14-bit = 16384 steps)
+pub const DUTY_RESOLUTION: u32 = 16384;
 
-/// Period in microseconds (1/50Hz = 20000us)
-const PERIOD_US: u32 = 1_000_000 / SERVO_FREQ_HZ;
+/// Highest PWM frequency the 80MHz APB clock can drive at 14-bit duty
+/// resolution (about 4.9kHz); digital servos need no more than ~333Hz
+pub const MAX_FREQ_HZ: u32 = 80_000_000 / DUTY_RESOLUTION;
 
-/// Duty resolution (14-bit = 16384 steps)
-const DUTY_RESOLUTION: u32 = 16384;
+/// Fewest duty steps the pulse range should span: below this the servo
+/// can't be placed to half a degree
+const MIN_RANGE_STEPS: u32 = 360;
+
+/// An LEDC timer configured for servo PWM, together with its frequency
+///
+/// Each timer runs at a single frequency, so servos that need different
+/// frequencies (e.g. 50Hz analog and 300Hz digital) must use different
+/// timers. The ESP32 has only four HighSpeed LEDC timers (`Timer0`-`Timer3`),
+/// which caps the number of distinct frequencies at four. Any number of
+/// servos, up to the eight HighSpeed channels, can share one timer.
+///
+/// `S` is the LEDC speed group, `HighSpeed` or [`LowSpeed`]. The ESP32 has
+/// both; chips such as the ESP32-C3/C6 only have `LowSpeed`. A servo's
+/// channel must be in the same group as its timer.
+pub struct ServoTimer<'d, S: TimerSpeed> {
+    timer: timer::Timer<'d, S>,
+    number: timer::Number,
+    freq_hz: u32,
+}
+
+impl<'d, S: TimerSpeed> ServoTimer<'d, S> {
+    /// PWM frequency this timer was configured with
+    pub fn freq_hz(&self) -> u32 {
+        self.freq_hz
+    }
+}
+
+/// Where a servo is wired: its GPIO, LEDC channel and LEDC timer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServoHardware {
+    pub gpio: u8,
+    pub channel: u8,
+    pub timer: u8,
+}
+
+/// What the pulse width controls
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServoMode {
+    /// Standard servo: the pulse sets an angle (`set_angle`)
+    Positional,
+    /// 360° continuous-rotation servo: the pulse sets speed and direction
+    /// (`set_speed`), with the center pulse meaning stop
+    Continuous,
+}
 
 /// Servo controller using LEDC PWM
-pub struct ServoController<'d> {
-    channel: channel::Channel<'d, HighSpeed>,
+pub struct ServoController<'d, S: TimerSpeed> {
+    channel: channel::Channel<'d, S>,
+    hardware: ServoHardware,
+    period_us: u32,
+    min_pulse_us: u32,
+    max_pulse_us: u32,
+    min_angle: u8,
+    max_angle: u8,
+    /// Pulse widths are rounded to a multiple of this (0 = no quantization)
+    pulse_step_us: u32,
+    /// Degrees added to every commanded angle to correct horn alignment
+    trim_deg: i8,
+    mode: ServoMode,
+    /// Mirror angles (`a` commands `180 - a`) for servos mounted the other way
+    inverted: bool,
+    /// Angle last passed to `set_angle`, after clamping to the limits
+    angle: Option<u8>,
+    /// Raw duty last written by `set_angle`, restored by `attach`
+    duty_raw: u32,
+    attached: bool,
 }
 
-impl<'d> ServoController<'d> {
-    /// Create a new servo controller
-    pub fn new<P: PeripheralOutput<'d>>(
-        timer: &'d timer::Timer<'d, HighSpeed>,
+impl<'d, S: TimerSpeed> ServoController<'d, S>
+where
+    timer::Timer<'d, S>: TimerIFace<S>,
+    channel::Channel<'d, S>: ChannelIFace<'d, S> + ChannelHW,
+{
+    /// Create a new servo controller on the given LEDC channel with the SG90
+    /// pulse range
+    ///
+    /// The servo runs at whatever frequency `timer` was configured with.
+    pub fn new<P: PeripheralOutput<'d> + Pin>(
+        timer: &'d ServoTimer<'d, S>,
+        channel_number: channel::Number,
+        pin: P,
+    ) -> Self {
+        let config = ServoConfig { freq_hz: timer.freq_hz, ..ServoConfig::SG90 };
+        Self::new_with_config(timer, channel_number, pin, config)
+    }
+
+    /// Create a new servo controller with its own pulse-width calibration
+    ///
+    /// The PWM frequency is set by `timer`; if `config.freq_hz` differs, a
+    /// warning is logged and the timer's frequency is used.
+    pub fn new_with_config<P: PeripheralOutput<'d> + Pin>(
+        timer: &'d ServoTimer<'d, S>,
+        channel_number: channel::Number,
         pin: P,
+        config: ServoConfig,
     ) -> Self {
-        println!("Initializing servo controller (HighSpeed LEDC)");
-        println!("  PWM frequency: {} Hz", SERVO_FREQ_HZ);
-        println!("  Period: {} us", PERIOD_US);
-        println!("  Pulse range: {} - {} us", MIN_PULSE_US, MAX_PULSE_US);
+        if config.freq_hz != timer.freq_hz {
+            log::warn!(
+                "servo expects {} Hz but its timer runs at {} Hz",
+                config.freq_hz, timer.freq_hz
+            );
+        }
+        let period_us = 1_000_000 / timer.freq_hz;
+        let (min_pulse_us, max_pulse_us) = clamp_pulse_range(config.min_pulse_us, config.max_pulse_us, period_us);
+        let hardware = ServoHardware {
+            gpio: pin.number(),
+            channel: channel_number as u8,
+            timer: timer.number as u8,
+        };
+
+        // Each duty step is period / resolution; a narrow range at a given
+        // frequency leaves few steps across it
+        let range_steps = (max_pulse_us - min_pulse_us) * DUTY_RESOLUTION / period_us;
+        if range_steps < MIN_RANGE_STEPS {
+            log::warn!(
+                "servo on GPIO{} has only {} duty steps across {}-{} us at {} Hz",
+                hardware.gpio, range_steps, min_pulse_us, max_pulse_us, timer.freq_hz
+            );
+        }
+
+        log::debug!("Initializing servo controller ({} LEDC)", if S::IS_HS { "HighSpeed" } else { "LowSpeed" });
+        log::debug!("  GPIO: {}", hardware.gpio);
+        log::debug!("  Channel: {:?}", channel_number);
+        log::debug!("  Timer: {:?}", timer.number);
+        log::debug!("  PWM frequency: {} Hz", timer.freq_hz);
+        log::debug!("  Period: {} us", period_us);
+        log::debug!("  Pulse range: {} - {} us", min_pulse_us, max_pulse_us);
         
-        let mut channel = channel::Channel::new(channel::Number::Channel0, pin);
+        let mut channel = channel::Channel::new(channel_number, pin);
         channel.configure(channel::config::Config {
-            timer,
+            timer: &timer.timer,
             duty_pct: 0,
             drive_mode: DriveMode::PushPull,
         }).unwrap();
         
-        Self { channel }
+        Self { channel, hardware, period_us, min_pulse_us, max_pulse_us, min_angle: 0, max_angle: 180, pulse_step_us: 0, trim_deg: 0, mode: ServoMode::Positional, inverted: false, angle: None, duty_raw: 0, attached: false }
+    }
+
+    /// GPIO, channel and timer this servo was constructed with
+    pub fn hardware(&self) -> ServoHardware {
+        self.hardware
     }
 
-    /// Set servo angle (0-180 degrees)
+    /// Restrict the servo to `[min, max]` degrees (both within 0-180)
+    pub fn set_limits(&mut self, min: u8, max: u8) {
+        let max = max.min(180);
+        self.min_angle = min.min(max);
+        self.max_angle = max;
+    }
+
+    /// Change the pulse-width calibration at runtime
+    ///
+    /// Clamped the same way as the range given to `new_with_config`; takes
+    /// effect on the next `set_angle`.
+    pub fn set_pulse_range(&mut self, min_us: u32, max_us: u32) {
+        (self.min_pulse_us, self.max_pulse_us) = clamp_pulse_range(min_us, max_us, self.period_us);
+    }
+
+    /// Pulse widths for 0° and 180° in microseconds
+    pub fn pulse_range(&self) -> (u32, u32) {
+        (self.min_pulse_us, self.max_pulse_us)
+    }
+
+    /// Quantize pulse widths to the servo's actual resolution
+    ///
+    /// Commanding finer steps than the servo's dead band (typically 5-10us on
+    /// hobby servos, listed as "dead band width" in the datasheet) only makes it
+    /// dither. Use the dead band width as the step; 0 disables quantization.
+    pub fn set_pulse_step(&mut self, step_us: u32) {
+        self.pulse_step_us = step_us;
+    }
+
+    /// Offset every angle by `trim_deg` degrees (clamped to ±[`MAX_TRIM_DEG`])
+    ///
+    /// Corrects a horn that can't be mounted exactly centered: after
+    /// `set_trim(-4)`, `set_angle(90)` physically commands 86°. The offset is
+    /// added after the limits are applied and the result is clamped to 0-180,
+    /// so the pulse never leaves the calibrated range. Lasts for the
+    /// controller's lifetime and takes effect on the next `set_angle` (and so
+    /// on every step of `set_angle_smooth`); `get_angle` reports the untrimmed
+    /// angle.
+    pub fn set_trim(&mut self, trim_deg: i8) {
+        self.trim_deg = trim_deg.clamp(-MAX_TRIM_DEG, MAX_TRIM_DEG);
+    }
+
+    /// Mirror the servo's direction, so `set_angle(a)` commands `180 - a`
+    ///
+    /// For a servo mounted mirror-image to its neighbour: both then move the
+    /// same way for the same angle. Limits still apply to the commanded angle;
+    /// trim is a physical correction and is added after inverting.
+    pub fn set_inverted(&mut self, inverted: bool) {
+        self.inverted = inverted;
+    }
+
+    /// Whether the servo's direction is mirrored
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Current trim in degrees
+    pub fn trim(&self) -> i8 {
+        self.trim_deg
+    }
+
+    /// Current angle limits as `(min, max)`
+    pub fn limits(&self) -> (u8, u8) {
+        (self.min_angle, self.max_angle)
+    }
+
+    /// Angle last commanded with `set_angle` (after clamping to the limits,
+    /// before trim), or `None` until the first `set_angle`
+    pub fn get_angle(&self) -> Option<u8> {
+        self.angle
+    }
+
+    /// Switch between position and speed control
+    ///
+    /// Stops sending pulses, so a continuous-rotation servo doesn't keep
+    /// spinning with a pulse meant as a position (or the other way round).
+    pub fn set_mode(&mut self, mode: ServoMode) {
+        if mode != self.mode {
+            self.detach();
+            self.mode = mode;
+        }
+    }
+
+    /// Whether the pulse width sets a position or a speed
+    pub fn mode(&self) -> ServoMode {
+        self.mode
+    }
+
+    /// Set speed of a continuous-rotation servo: -100 is full reverse, 0 stop
+    /// (the center pulse) and 100 full forward
+    ///
+    /// Ignored in [`ServoMode::Positional`].
+    pub fn set_speed(&mut self, speed: i8) {
+        if self.mode != ServoMode::Continuous {
+            log::warn!("Servo: set_speed ignored in positional mode");
+            return;
+        }
+        let speed = speed.clamp(-100, 100) as i32;
+        let center = (self.min_pulse_us + self.max_pulse_us) as i32 / 2;
+        let half_span = (self.max_pulse_us - self.min_pulse_us) as i32 / 2;
+        let pulse_us = (center + half_span * speed / 100) as u32;
+        let duty_raw = self.write_pulse(pulse_us as f32);
+        log::debug!("Servo: speed={} pulse={}us duty_raw={}/{}", speed, pulse_us, duty_raw, DUTY_RESOLUTION);
+    }
+
+    /// Set servo angle (clamped to the configured limits)
+    ///
+    /// Ignored in [`ServoMode::Continuous`], where a pulse sets a speed.
     pub fn set_angle(&mut self, angle: u8) {
-        let angle = angle.min(180);
-        
-        // Calculate pulse width for the given angle
-        let pulse_us = MIN_PULSE_US + ((MAX_PULSE_US - MIN_PULSE_US) * angle as u32) / 180;
-        
-        // Convert pulse width to raw duty value (0-16383 for 14-bit resolution)
-        // duty = (pulse_us / period_us) * max_duty
-        let duty_raw = (pulse_us * DUTY_RESOLUTION) / PERIOD_US;
-        
-        println!("Servo: angle={}° pulse={}us duty_raw={}/{}", angle, pulse_us, duty_raw, DUTY_RESOLUTION);
+        self.set_angle_f32(angle as f32);
+    }
+
+    /// Set servo angle with fractional-degree precision (0.0-180.0, clamped to
+    /// the configured limits)
+    ///
+    /// The pulse is interpolated in floating point and only the final duty is
+    /// rounded, so e.g. 90.25° lands between the 90° and 91° duties instead of
+    /// on one of them. `get_angle` reports the nearest whole degree.
+    pub fn set_angle_f32(&mut self, angle: f32) {
+        if self.mode != ServoMode::Positional {
+            log::warn!("Servo: set_angle ignored in continuous mode");
+            return;
+        }
+        // NaN fails every comparison, so it lands on the lower limit
+        let angle = if angle >= self.min_angle as f32 { angle.min(self.max_angle as f32) } else { self.min_angle as f32 };
+        self.angle = Some((angle + 0.5) as u8);
+        let angle = if self.inverted { 180.0 - angle } else { angle };
+        // Trim shifts the physical position; the pulse range still bounds it
+        let angle = (angle + self.trim_deg as f32).clamp(0.0, 180.0);
+
+        let pulse_us = angle_f32_to_pulse_us(angle, self.min_pulse_us, self.max_pulse_us);
+        let duty_raw = self.write_pulse(pulse_us);
         
+        log::debug!("Servo: angle={:.2}° pulse={:.1}us duty_raw={}/{}", angle, pulse_us, duty_raw, DUTY_RESOLUTION);
+    }
+
+    /// Send a raw pulse width, bypassing the angle math
+    ///
+    /// Clamped to the calibrated pulse range (see [`ServoConfig`]). For
+    /// calibration sweeps and actuators such as ESCs that don't take angles.
+    /// The angle is no longer known afterwards, so `get_angle` returns `None`
+    /// until the next `set_angle`.
+    pub fn set_pulse_us(&mut self, pulse_us: u32) {
+        let pulse_us = pulse_us.clamp(self.min_pulse_us, self.max_pulse_us);
+        self.angle = None;
+        let duty_raw = self.write_pulse(pulse_us as f32);
+        log::debug!("Servo: pulse={}us duty_raw={}/{}", pulse_us, duty_raw, DUTY_RESOLUTION);
+    }
+
+    /// Quantize `pulse_us` to the pulse step and write it to the channel,
+    /// returning the raw duty written
+    fn write_pulse(&mut self, mut pulse_us: f32) -> u32 {
+        if self.pulse_step_us > 0 {
+            // Round to the nearest step
+            let step = self.pulse_step_us as f32;
+            pulse_us = (pulse_us / step + 0.5) as u32 as f32 * step;
+        }
+
+        let duty_raw = pulse_f32_to_duty(pulse_us, self.period_us, DUTY_RESOLUTION);
+
         self.channel.set_duty_hw(duty_raw);
+        self.duty_raw = duty_raw;
+        self.attached = true;
+        duty_raw
+    }
+
+    /// Move to `target` in 1° steps, waiting `step_delay` after each
+    ///
+    /// Starts from the last commanded angle (jumping straight to `target` if
+    /// there is none, or ignored like `set_angle` in continuous mode) and ends
+    /// at `target` clamped to the limits. Cancel-safe: every step is a
+    /// complete `set_angle`, so dropping the future mid-sweep (e.g. from a
+    /// `select` with a new command) leaves the servo holding the last step
+    /// and `get_angle` reporting it, ready for the next move.
+    pub async fn set_angle_smooth(&mut self, target: u8, step_delay: Duration) {
+        let target = target.clamp(self.min_angle, self.max_angle);
+        let (ServoMode::Positional, Some(mut angle)) = (self.mode, self.angle) else {
+            self.set_angle(target);
+            return;
+        };
+        while angle != target {
+            angle = if angle < target { angle + 1 } else { angle - 1 };
+            self.set_angle(angle);
+            if angle != target {
+                Timer::after(step_delay).await;
+            }
+        }
+    }
+
+    /// Stop sending pulses so the servo relaxes (no holding torque)
+    ///
+    /// An idle servo then stops humming and draws almost nothing, which
+    /// matters on battery. The timer keeps running, so `attach` or the next
+    /// `set_angle` resumes the same pulse train at the timer's frequency; the
+    /// LEDC latches a new duty at the start of a period, so the first pulse is
+    /// never cut short.
+    pub fn detach(&mut self) {
+        self.channel.set_duty_hw(0);
+        self.attached = false;
+        log::debug!("Servo: detached");
+    }
+
+    /// Resume pulses at the last commanded position
+    ///
+    /// Does nothing before the first `set_angle`, since there is no position
+    /// to resume yet.
+    pub fn attach(&mut self) {
+        if self.duty_raw == 0 {
+            log::debug!("Servo: attach ignored, no position commanded yet");
+            return;
+        }
+        self.channel.set_duty_hw(self.duty_raw);
+        self.attached = true;
+        log::debug!("Servo: attached");
+    }
+
+    /// Whether the servo is currently receiving pulses
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+}
+
+impl<'d, S: TimerSpeed> AngleActuator for ServoController<'d, S>
+where
+    timer::Timer<'d, S>: TimerIFace<S>,
+    channel::Channel<'d, S>: ChannelIFace<'d, S> + ChannelHW,
+{
+    fn command_angle(&mut self, angle: u8) {
+        self.set_angle(angle);
     }
 }
-pub fn init_servo_timer<'d>(ledc: &'d Ledc<'d>) -> timer::Timer<'d, HighSpeed> {
-    let mut timer = ledc.timer::<HighSpeed>(timer::Number::Timer0);
+
+/// Configure one of the HighSpeed LEDC timers for servo PWM at `freq_hz`
+///
+/// Use a different `number` for every distinct frequency (see [`ServoTimer`]).
+#[cfg(feature = "esp32")]
+pub fn init_servo_timer<'d>(
+    ledc: &'d Ledc<'d>,
+    number: timer::Number,
+    freq_hz: u32,
+) -> ServoTimer<'d, HighSpeed> {
+    configure_timer(ledc.timer::<HighSpeed>(number), number, timer::HSClockSource::APBClk, freq_hz)
+}
+
+/// Clock the LowSpeed LEDC group from the APB clock, as
+/// [`init_servo_timer_low`] expects
+///
+/// Needs `ledc` mutably, so call it once at setup, before any timer or
+/// channel borrows it.
+pub fn init_low_speed_clock(ledc: &mut Ledc<'_>) {
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+}
+
+/// Configure one of the LowSpeed LEDC timers for servo PWM at `freq_hz`
+///
+/// The only option on chips without HighSpeed LEDC (ESP32-C3/C6, ...). The
+/// duty math is identical. Call [`init_low_speed_clock`] first; like
+/// `init_servo_timer` this only borrows `ledc`, so timers can share it.
+pub fn init_servo_timer_low<'d>(
+    ledc: &'d Ledc<'d>,
+    number: timer::Number,
+    freq_hz: u32,
+) -> ServoTimer<'d, LowSpeed> {
+    configure_timer(ledc.timer::<LowSpeed>(number), number, timer::LSClockSource::APBClk, freq_hz)
+}
+
+/// Configure `timer` for 14-bit servo PWM at `freq_hz`
+///
+/// `freq_hz` must be at most [`MAX_FREQ_HZ`], or the timer can't be set up.
+fn configure_timer<'d, S: TimerSpeed>(
+    mut timer: timer::Timer<'d, S>,
+    number: timer::Number,
+    clock_source: S::ClockSourceType,
+    freq_hz: u32,
+) -> ServoTimer<'d, S>
+where
+    timer::Timer<'d, S>: TimerIFace<S>,
+{
     timer.configure(timer::config::Config {
         duty: Duty::Duty14Bit,
-        clock_source: timer::HSClockSource::APBClk,
-        frequency: esp_hal::time::Rate::from_hz(SERVO_FREQ_HZ),
+        clock_source,
+        frequency: esp_hal::time::Rate::from_hz(freq_hz),
     }).unwrap();
-    timer
+    ServoTimer { timer, number, freq_hz }
 }
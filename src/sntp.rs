@@ -0,0 +1,101 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+pub use servo_core::time::iso8601;
+
+/// NTP server queried for the time (`ntp_server` in `cfg.toml`)
+pub const NTP_SERVER: &str = match option_env!("NTP_SERVER") {
+    Some(server) => server,
+    None => "pool.ntp.org",
+};
+
+const NTP_PORT: u16 = 123;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_TO_UNIX_S: u32 = 2_208_988_800;
+
+/// How often the clock is re-synced, and how soon a failed sync is retried
+const SYNC_INTERVAL: Duration = Duration::from_secs(3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the server's reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unix time in seconds at the last sync (0 = never synced)
+static SYNCED_UNIX_S: AtomicU32 = AtomicU32::new(0);
+
+/// Uptime in milliseconds at the last sync
+static SYNCED_AT_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Current Unix time in seconds, if the clock has been synced
+pub fn unix_time() -> Option<u32> {
+    let synced = SYNCED_UNIX_S.load(Ordering::Relaxed);
+    if synced == 0 {
+        return None;
+    }
+    let since_ms = (Instant::now().as_millis() as u32).wrapping_sub(SYNCED_AT_MS.load(Ordering::Relaxed));
+    Some(synced + since_ms / 1000)
+}
+
+/// Current time for logs: the ISO timestamp once synced, uptime before
+pub fn timestamp() -> alloc::string::String {
+    match unix_time() {
+        Some(unix_s) => iso8601(unix_s),
+        None => alloc::format!("+{}s", Instant::now().as_secs()),
+    }
+}
+
+/// Ask the server for the time once, returning Unix seconds
+async fn query(socket: &mut UdpSocket<'_>, server: IpEndpoint) -> Option<u32> {
+    // Client request: leap indicator 0, version 4, mode 3
+    let mut packet = [0u8; 48];
+    packet[0] = 0x23;
+    socket.send_to(&packet, server).await.ok()?;
+    let (len, _) = with_timeout(REPLY_TIMEOUT, socket.recv_from(&mut packet)).await.ok()?.ok()?;
+    // Transmit timestamp, whole seconds since 1900
+    let ntp_s = u32::from_be_bytes(packet.get(40..44)?.try_into().ok()?);
+    (len >= 48 && ntp_s > NTP_TO_UNIX_S).then(|| ntp_s - NTP_TO_UNIX_S)
+}
+
+/// Keep the wall clock synced from [`NTP_SERVER`]
+///
+/// Until the first sync succeeds, [`unix_time`] is `None` and log timestamps
+/// show uptime instead.
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 64];
+
+    loop {
+        stack.wait_config_up().await;
+        let synced = match stack.dns_query(NTP_SERVER, DnsQueryType::A).await.ok().and_then(|a| a.first().copied()) {
+            Some(address) => {
+                let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+                // Any free local port
+                let _ = socket.bind(0);
+                query(&mut socket, IpEndpoint::new(address, NTP_PORT)).await
+            }
+            None => {
+                log::warn!("SNTP: could not resolve {}", NTP_SERVER);
+                None
+            }
+        };
+        match synced {
+            Some(unix_s) => {
+                SYNCED_AT_MS.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+                SYNCED_UNIX_S.store(unix_s, Ordering::Relaxed);
+                log::info!("SNTP: clock set to {}", iso8601(unix_s));
+                Timer::after(SYNC_INTERVAL).await;
+            }
+            None => {
+                log::warn!("SNTP: sync with {} failed, retrying in {} s", NTP_SERVER, RETRY_INTERVAL.as_secs());
+                Timer::after(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use alloc::string::String;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, AtomicU32, AtomicU8, Ordering};
+
+use embassy_net::Ipv4Address;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::watch::Watch;
+use embassy_time::Instant;
+
+pub use servo_core::command::NotReady;
+
+use crate::command::{CommandSource, MAX_SERVOS};
+use crate::http_server::HTTP_HANDLERS;
+use crate::servo::{ServoHardware, SERVO_FREQ_HZ};
+
+/// Marker for "no command applied yet"
+const NO_SOURCE: u8 = u8::MAX;
+
+/// Angle most recently applied to each servo
+static CURRENT_ANGLE: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(90) }; MAX_SERVOS];
+
+/// Source of the most recently applied command for each servo
+static LAST_SOURCE: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(NO_SOURCE) }; MAX_SERVOS];
+
+/// Hardware limits of each servo (as set on its `ServoController`), published
+/// for the command entry points
+static MIN_ANGLE: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(0) }; MAX_SERVOS];
+static MAX_ANGLE: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(180) }; MAX_SERVOS];
+
+/// Trim of each servo in degrees, as set on its `ServoController`
+static TRIM: [AtomicI8; MAX_SERVOS] = [const { AtomicI8::new(0) }; MAX_SERVOS];
+
+/// Publish a servo's trim
+pub fn set_trim(servo: usize, trim_deg: i8) {
+    TRIM[servo].store(trim_deg, Ordering::Relaxed);
+}
+
+/// Trim of a servo in degrees
+pub fn trim(servo: usize) -> i8 {
+    TRIM[servo].load(Ordering::Relaxed)
+}
+
+/// PWM frequency of the servo timer, as configured in `main.rs`
+static PWM_FREQ_HZ: AtomicU32 = AtomicU32::new(SERVO_FREQ_HZ);
+
+/// Publish the servo timer's PWM frequency
+pub fn set_pwm_freq_hz(freq_hz: u32) {
+    PWM_FREQ_HZ.store(freq_hz, Ordering::Relaxed);
+}
+
+/// PWM frequency the servos run at
+pub fn pwm_freq_hz() -> u32 {
+    PWM_FREQ_HZ.load(Ordering::Relaxed)
+}
+
+/// Pulse range of each servo in microseconds, as set on its `ServoController`
+static MIN_PULSE_US: [AtomicU16; MAX_SERVOS] = [const { AtomicU16::new(0) }; MAX_SERVOS];
+static MAX_PULSE_US: [AtomicU16; MAX_SERVOS] = [const { AtomicU16::new(0) }; MAX_SERVOS];
+
+/// Publish a servo's pulse range
+pub fn set_pulse_range(servo: usize, min_us: u16, max_us: u16) {
+    MIN_PULSE_US[servo].store(min_us, Ordering::Relaxed);
+    MAX_PULSE_US[servo].store(max_us, Ordering::Relaxed);
+}
+
+/// Pulse widths for 0° and 180° of a servo in microseconds
+pub fn pulse_range(servo: usize) -> (u16, u16) {
+    (MIN_PULSE_US[servo].load(Ordering::Relaxed), MAX_PULSE_US[servo].load(Ordering::Relaxed))
+}
+
+/// Publish a servo's hardware limits
+pub fn set_limits(servo: usize, min: u8, max: u8) {
+    MIN_ANGLE[servo].store(min, Ordering::Relaxed);
+    MAX_ANGLE[servo].store(max, Ordering::Relaxed);
+}
+
+/// Hardware limits of a servo as `(min, max)`
+pub fn limits(servo: usize) -> (u8, u8) {
+    (MIN_ANGLE[servo].load(Ordering::Relaxed), MAX_ANGLE[servo].load(Ordering::Relaxed))
+}
+
+/// Whether each servo's limits were configured rather than the built-in
+/// `SAFE_MIN_ANGLE`/`SAFE_MAX_ANGLE` defaults
+static LIMITS_CONFIGURED: [AtomicBool; MAX_SERVOS] = [const { AtomicBool::new(false) }; MAX_SERVOS];
+
+/// Mark whether a servo's published limits were configured
+pub fn set_limits_configured(servo: usize, configured: bool) {
+    LIMITS_CONFIGURED[servo].store(configured, Ordering::Relaxed);
+}
+
+/// Limits of a servo worth saving: the published ones if they were
+/// configured, `None` while it runs on the defaults so a save keeps
+/// following them
+pub fn configured_limits(servo: usize) -> Option<(u8, u8)> {
+    LIMITS_CONFIGURED[servo].load(Ordering::Relaxed).then(|| limits(servo))
+}
+
+/// Notified after every applied move, for `GET /events` streams (at most one
+/// per HTTP handler); receivers read the angles themselves
+pub static MOVED: Watch<CriticalSectionRawMutex, (), HTTP_HANDLERS> = Watch::new();
+
+/// Record a move that has been applied to a servo
+pub fn record_move(servo: usize, angle: u8, source: CommandSource) {
+    CURRENT_ANGLE[servo].store(angle, Ordering::Relaxed);
+    LAST_SOURCE[servo].store(source.to_u8(), Ordering::Relaxed);
+    MOVED.sender().send(());
+}
+
+/// Angle each servo is heading to (equals the current angle when idle)
+static TARGET_ANGLE: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(90) }; MAX_SERVOS];
+
+/// Publish the angle a servo is heading to; set it to the reached angle when
+/// the move ends
+pub fn set_target(servo: usize, angle: u8) {
+    TARGET_ANGLE[servo].store(angle, Ordering::Relaxed);
+}
+
+/// Angle a servo is heading to
+pub fn target_angle(servo: usize) -> u8 {
+    TARGET_ANGLE[servo].load(Ordering::Relaxed)
+}
+
+/// Whether a servo is part way through a sweep
+pub fn is_moving(servo: usize) -> bool {
+    target_angle(servo) != current_angle(servo)
+}
+
+/// Angle most recently applied to a servo
+pub fn current_angle(servo: usize) -> u8 {
+    CURRENT_ANGLE[servo].load(Ordering::Relaxed)
+}
+
+/// Source of the most recently applied command for a servo, if any
+pub fn last_source(servo: usize) -> Option<CommandSource> {
+    CommandSource::from_u8(LAST_SOURCE[servo].load(Ordering::Relaxed))
+}
+
+/// Marker for "no hardware assigned"
+const NO_HARDWARE: u8 = u8::MAX;
+
+/// GPIO, LEDC channel and LEDC timer of each servo, for diagnostics
+static GPIO: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(NO_HARDWARE) }; MAX_SERVOS];
+static CHANNEL: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(NO_HARDWARE) }; MAX_SERVOS];
+static TIMER: [AtomicU8; MAX_SERVOS] = [const { AtomicU8::new(NO_HARDWARE) }; MAX_SERVOS];
+
+/// Whether each servo is receiving pulses
+static ATTACHED: [AtomicBool; MAX_SERVOS] = [const { AtomicBool::new(false) }; MAX_SERVOS];
+
+/// Publish the hardware a servo is wired to
+pub fn set_hardware(servo: usize, hardware: ServoHardware) {
+    GPIO[servo].store(hardware.gpio, Ordering::Relaxed);
+    CHANNEL[servo].store(hardware.channel, Ordering::Relaxed);
+    TIMER[servo].store(hardware.timer, Ordering::Relaxed);
+}
+
+/// Hardware a servo is wired to, if it has been configured
+pub fn hardware(servo: usize) -> Option<ServoHardware> {
+    let gpio = GPIO[servo].load(Ordering::Relaxed);
+    if gpio == NO_HARDWARE {
+        return None;
+    }
+    Some(ServoHardware {
+        gpio,
+        channel: CHANNEL[servo].load(Ordering::Relaxed),
+        timer: TIMER[servo].load(Ordering::Relaxed),
+    })
+}
+
+/// Publish whether a servo is receiving pulses
+pub fn set_attached(servo: usize, attached: bool) {
+    ATTACHED[servo].store(attached, Ordering::Relaxed);
+}
+
+/// Whether a servo is receiving pulses
+pub fn is_attached(servo: usize) -> bool {
+    ATTACHED[servo].load(Ordering::Relaxed)
+}
+
+/// Default sweep duration for plain angle commands (0 = instant)
+static SWEEP_DURATION_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Longest default sweep duration accepted
+pub const MAX_SWEEP_DURATION_MS: u32 = 10_000;
+
+/// Set the default sweep duration (clamped to [`MAX_SWEEP_DURATION_MS`])
+pub fn set_sweep_duration_ms(ms: u32) {
+    SWEEP_DURATION_MS.store(ms.min(MAX_SWEEP_DURATION_MS), Ordering::Relaxed);
+}
+
+/// Default sweep duration for plain angle commands (0 = instant)
+pub fn sweep_duration_ms() -> u32 {
+    SWEEP_DURATION_MS.load(Ordering::Relaxed)
+}
+
+/// Latched overload fault; moves are refused while set
+static FAULT: AtomicBool = AtomicBool::new(false);
+
+/// Latch or clear the overload fault
+pub fn set_fault(faulted: bool) {
+    FAULT.store(faulted, Ordering::Relaxed);
+}
+
+/// Whether an overload fault is latched
+pub fn is_faulted() -> bool {
+    FAULT.load(Ordering::Relaxed)
+}
+
+/// IPv4 address of the station interface (0 = none)
+static IP_ADDRESS: AtomicU32 = AtomicU32::new(0);
+
+/// Publish the station's IPv4 address, or `None` when it has none
+pub fn set_ip_address(address: Option<Ipv4Address>) {
+    let raw = address.map(|a| u32::from_be_bytes(a.octets())).unwrap_or(0);
+    IP_ADDRESS.store(raw, Ordering::Relaxed);
+}
+
+/// Station's IPv4 address, if it has one
+pub fn ip_address() -> Option<Ipv4Address> {
+    match IP_ADDRESS.load(Ordering::Relaxed) {
+        0 => None,
+        raw => Some(Ipv4Address::from(raw.to_be_bytes())),
+    }
+}
+
+/// Emergency stop; moves are handled per `ESTOP_POLICY` while set
+static ESTOP: AtomicBool = AtomicBool::new(false);
+
+/// Engage or clear the emergency stop
+pub fn set_estop(engaged: bool) {
+    ESTOP.store(engaged, Ordering::Relaxed);
+}
+
+/// Whether the emergency stop is engaged
+pub fn is_estopped() -> bool {
+    ESTOP.load(Ordering::Relaxed)
+}
+
+/// Set by the idle sleep once it has decided to sleep; never cleared, since
+/// waking from deep sleep is a reset
+static LOW_POWER: AtomicBool = AtomicBool::new(false);
+
+/// Refuse moves from now on because the device is going into deep sleep
+pub fn set_low_power() {
+    LOW_POWER.store(true, Ordering::Relaxed);
+}
+
+/// Whether the device is going into deep sleep
+pub fn is_low_power() -> bool {
+    LOW_POWER.load(Ordering::Relaxed)
+}
+
+/// Signal strength of the joined access point in dBm (0 = not connected;
+/// real readings are always negative)
+static RSSI_DBM: AtomicI8 = AtomicI8::new(0);
+
+/// Publish the latest RSSI reading, or `None` when disconnected
+pub fn set_rssi(dbm: Option<i8>) {
+    RSSI_DBM.store(dbm.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Latest RSSI reading in dBm, if connected
+pub fn rssi() -> Option<i8> {
+    match RSSI_DBM.load(Ordering::Relaxed) {
+        0 => None,
+        dbm => Some(dbm),
+    }
+}
+
+/// WiFi network set at runtime (`POST /wifi`) as `(ssid, password)`,
+/// replacing the one compiled in from `cfg.toml`
+static WIFI_CREDENTIALS: Mutex<CriticalSectionRawMutex, RefCell<Option<(String, String)>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Set (or with `None`, forget) the runtime WiFi network; it is used from the
+/// next connect attempt
+pub fn set_wifi_credentials(credentials: Option<(String, String)>) {
+    WIFI_CREDENTIALS.lock(|cell| *cell.borrow_mut() = credentials);
+}
+
+/// Runtime WiFi network, if one has been set
+pub fn wifi_credentials() -> Option<(String, String)> {
+    WIFI_CREDENTIALS.lock(|cell| cell.borrow().clone())
+}
+
+/// Whether the station is associated with a WiFi network
+static WIFI_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_wifi_connected(connected: bool) {
+    WIFI_CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+/// Whether the station is associated with a WiFi network
+pub fn wifi_connected() -> bool {
+    WIFI_CONNECTED.load(Ordering::Relaxed)
+}
+
+/// Whether the fallback access point is open
+static AP_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_ap_active(active: bool) {
+    AP_ACTIVE.store(active, Ordering::Relaxed);
+}
+
+/// Whether the fallback access point is open (the station isn't connected)
+pub fn ap_active() -> bool {
+    AP_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Set when a handler wants the device restarted once its response is out
+static REBOOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask for a restart after the current response has been sent
+pub fn request_reboot() {
+    REBOOT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a restart has been requested
+pub fn reboot_requested() -> bool {
+    REBOOT_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// When startup homing finishes (`None` = not yet known)
+///
+/// Kept as a full 64-bit [`Instant`]: milliseconds in a `u32` wrap after
+/// about 49.7 days, which would briefly report [`NotReady::Starting`] again.
+static HOMED_AT: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// Publish when startup homing finishes so the servos can be moved from then on
+pub fn set_homed_at(at: Instant) {
+    HOMED_AT.lock(|homed_at| homed_at.set(Some(at)));
+}
+
+/// Whether the servos may be moved, or the reason they may not
+///
+/// The one readiness check shared by every control path. When several reasons
+/// apply the first listed in [`NotReady`] is reported, so a fault (which needs
+/// its own clear) shows even while the emergency stop is engaged.
+pub fn readiness() -> Result<(), NotReady> {
+    let homed_at = HOMED_AT.lock(Cell::get);
+    if homed_at.is_none_or(|at| Instant::now() < at) {
+        Err(NotReady::Starting)
+    } else if is_faulted() {
+        Err(NotReady::Fault)
+    } else if is_estopped() {
+        Err(NotReady::Estop)
+    } else if is_low_power() {
+        Err(NotReady::LowPower)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use embassy_net::Stack;
+use embassy_time::{Duration, Timer};
+
+use crate::http_client::post_json;
+use crate::http_server::build_status_json;
+
+/// Collector URL from cfg.toml (`telemetry_url`, e.g. "http://192.168.1.10:8080/telemetry")
+pub const TELEMETRY_URL: Option<&str> = option_env!("TELEMETRY_URL");
+
+/// Interval between status pushes
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// First retry delay after a failed push; doubles on each further failure
+/// up to `TELEMETRY_INTERVAL`
+const RETRY_BASE: Duration = Duration::from_secs(5);
+
+/// Buffer sizes for the telemetry client socket
+const RX_BUFFER_SIZE: usize = 256;
+const TX_BUFFER_SIZE: usize = 512;
+
+/// Periodically POST the device status to `url`
+///
+/// Runs independently of the control loop; failed pushes are retried with
+/// exponential backoff.
+#[embassy_executor::task]
+pub async fn telemetry_task(stack: Stack<'static>, url: &'static str) {
+    let mut rx_buffer = [0u8; RX_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; TX_BUFFER_SIZE];
+    let mut failures = 0u32;
+
+    log::info!("Telemetry: pushing status to {} every {}s", url, TELEMETRY_INTERVAL.as_secs());
+
+    loop {
+        let delay = if failures == 0 {
+            TELEMETRY_INTERVAL
+        } else {
+            Duration::from_secs(RETRY_BASE.as_secs() << (failures - 1).min(6)).min(TELEMETRY_INTERVAL)
+        };
+        Timer::after(delay).await;
+
+        let body = build_status_json();
+        match post_json(stack, url, &body, &mut rx_buffer, &mut tx_buffer).await {
+            Ok(status) if (200..300).contains(&status) => failures = 0,
+            result => {
+                failures = failures.saturating_add(1);
+                log::warn!("Telemetry push failed ({} in a row): {:?}", failures, result);
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
+use crate::http_client::post_json;
+
+/// Webhook URL from cfg.toml (`webhook_url`, e.g. "http://hooks.local/servo")
+pub const WEBHOOK_URL: Option<&str> = option_env!("WEBHOOK_URL");
+
+/// A named angle that fires the webhook when a move crosses it
+pub struct PositionTrigger {
+    pub name: &'static str,
+    pub servo: u8,
+    pub angle: u8,
+}
+
+/// Position triggers, checked after every move
+///
+/// A trigger fires when a move reaches or passes its angle from either side.
+pub const TRIGGERS: &[PositionTrigger] = &[
+    PositionTrigger { name: "fully-open", servo: 0, angle: 180 },
+    PositionTrigger { name: "fully-closed", servo: 0, angle: 0 },
+];
+
+/// Pending webhook notifications; the oldest are kept if it fills up
+const QUEUE_DEPTH: usize = 4;
+
+/// Buffer sizes for the webhook client socket
+const RX_BUFFER_SIZE: usize = 256;
+const TX_BUFFER_SIZE: usize = 256;
+
+#[derive(Clone, Copy)]
+struct TriggerEvent {
+    trigger: &'static PositionTrigger,
+    angle: u8,
+    rising: bool,
+}
+
+static EVENTS: Channel<CriticalSectionRawMutex, TriggerEvent, QUEUE_DEPTH> = Channel::new();
+
+/// Queue webhook notifications for every trigger crossed by a move from
+/// `from` to `to`; never blocks the caller
+pub fn check_triggers(servo: u8, from: u8, to: u8) {
+    if WEBHOOK_URL.is_none() || from == to {
+        return;
+    }
+    let rising = to > from;
+    for trigger in TRIGGERS.iter().filter(|t| t.servo == servo) {
+        let crossed = if rising {
+            from < trigger.angle && to >= trigger.angle
+        } else {
+            from > trigger.angle && to <= trigger.angle
+        };
+        if crossed && EVENTS.try_send(TriggerEvent { trigger, angle: to, rising }).is_err() {
+            log::warn!("Webhook queue full, dropping '{}' notification", trigger.name);
+        }
+    }
+}
+
+/// Deliver queued trigger notifications to `url`
+#[embassy_executor::task]
+pub async fn webhook_task(stack: Stack<'static>, url: &'static str) {
+    let mut rx_buffer = [0u8; RX_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; TX_BUFFER_SIZE];
+
+    loop {
+        let event = EVENTS.receive().await;
+        let body = alloc::format!(
+            r#"{{"trigger": "{}", "servo": {}, "angle": {}, "direction": "{}"}}"#,
+            event.trigger.name,
+            event.trigger.servo,
+            event.angle,
+            if event.rising { "rising" } else { "falling" }
+        );
+        match post_json(stack, url, &body, &mut rx_buffer, &mut tx_buffer).await {
+            Ok(status) => log::info!("Webhook '{}' delivered ({})", event.trigger.name, status),
+            Err(e) => log::warn!("Webhook '{}' failed: {:?}", event.trigger.name, e),
+        }
+    }
+}